@@ -0,0 +1,123 @@
+pub use ierc721::*;
+#[allow(clippy::too_many_arguments, non_camel_case_types)]
+pub mod ierc721 {
+    #![allow(clippy::enum_variant_names)]
+    #![allow(dead_code)]
+    #![allow(clippy::type_complexity)]
+    #![allow(unused_imports)]
+    use ethers::contract::{
+        builders::{ContractCall, Event},
+        Contract, Lazy,
+    };
+    use ethers::core::{
+        abi::{Abi, Detokenize, InvalidOutputType, Token, Tokenizable},
+        types::*,
+    };
+    use ethers::providers::Middleware;
+    #[doc = "IERC721 was auto-generated with ethers-rs Abigen. More information at: https://github.com/gakonst/ethers-rs"]
+    use std::sync::Arc;
+    # [rustfmt :: skip] const __ABI : & str = "[\n\t{\n\t\t\"anonymous\": false,\n\t\t\"inputs\": [\n\t\t\t{\n\t\t\t\t\"indexed\": true,\n\t\t\t\t\"internalType\": \"address\",\n\t\t\t\t\"name\": \"owner\",\n\t\t\t\t\"type\": \"address\"\n\t\t\t},\n\t\t\t{\n\t\t\t\t\"indexed\": true,\n\t\t\t\t\"internalType\": \"address\",\n\t\t\t\t\"name\": \"operator\",\n\t\t\t\t\"type\": \"address\"\n\t\t\t},\n\t\t\t{\n\t\t\t\t\"indexed\": false,\n\t\t\t\t\"internalType\": \"bool\",\n\t\t\t\t\"name\": \"approved\",\n\t\t\t\t\"type\": \"bool\"\n\t\t\t}\n\t\t],\n\t\t\"name\": \"ApprovalForAll\",\n\t\t\"type\": \"event\"\n\t},\n\t{\n\t\t\"inputs\": [\n\t\t\t{\n\t\t\t\t\"internalType\": \"address\",\n\t\t\t\t\"name\": \"owner\",\n\t\t\t\t\"type\": \"address\"\n\t\t\t},\n\t\t\t{\n\t\t\t\t\"internalType\": \"address\",\n\t\t\t\t\"name\": \"operator\",\n\t\t\t\t\"type\": \"address\"\n\t\t\t}\n\t\t],\n\t\t\"name\": \"isApprovedForAll\",\n\t\t\"outputs\": [\n\t\t\t{\n\t\t\t\t\"internalType\": \"bool\",\n\t\t\t\t\"name\": \"\",\n\t\t\t\t\"type\": \"bool\"\n\t\t\t}\n\t\t],\n\t\t\"stateMutability\": \"view\",\n\t\t\"type\": \"function\"\n\t}\n]\n" ;
+    #[doc = r" The parsed JSON-ABI of the contract."]
+    pub static IERC721_ABI: ethers::contract::Lazy<ethers::core::abi::Abi> =
+        ethers::contract::Lazy::new(|| {
+            ethers::core::utils::__serde_json::from_str(__ABI).expect("invalid abi")
+        });
+    pub struct IERC721<M>(ethers::contract::Contract<M>);
+    impl<M> Clone for IERC721<M> {
+        fn clone(&self) -> Self {
+            IERC721(self.0.clone())
+        }
+    }
+    impl<M> std::ops::Deref for IERC721<M> {
+        type Target = ethers::contract::Contract<M>;
+        fn deref(&self) -> &Self::Target {
+            &self.0
+        }
+    }
+    impl<M> std::fmt::Debug for IERC721<M> {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.debug_tuple(stringify!(IERC721))
+                .field(&self.address())
+                .finish()
+        }
+    }
+    impl<M: ethers::providers::Middleware> IERC721<M> {
+        #[doc = r" Creates a new contract instance with the specified `ethers`"]
+        #[doc = r" client at the given `Address`. The contract derefs to a `ethers::Contract`"]
+        #[doc = r" object"]
+        pub fn new<T: Into<ethers::core::types::Address>>(
+            address: T,
+            client: ::std::sync::Arc<M>,
+        ) -> Self {
+            ethers::contract::Contract::new(address.into(), IERC721_ABI.clone(), client).into()
+        }
+        #[doc = "Calls the contract's `isApprovedForAll` (0xe985e9c5) function"]
+        pub fn is_approved_for_all(
+            &self,
+            owner: ethers::core::types::Address,
+            operator: ethers::core::types::Address,
+        ) -> ethers::contract::builders::ContractCall<M, bool> {
+            self.0
+                .method_hash([233, 133, 233, 197], (owner, operator))
+                .expect("method not found (this should never happen)")
+        }
+        #[doc = "Gets the contract's `ApprovalForAll` event"]
+        pub fn approval_for_all_filter(
+            &self,
+        ) -> ethers::contract::builders::Event<M, ApprovalForAllFilter> {
+            self.0.event()
+        }
+        #[doc = r" Returns an [`Event`](#ethers_contract::builders::Event) builder for all events of this contract"]
+        pub fn events(&self) -> ethers::contract::builders::Event<M, ApprovalForAllFilter> {
+            self.0.event_with_filter(Default::default())
+        }
+    }
+    impl<M: ethers::providers::Middleware> From<ethers::contract::Contract<M>> for IERC721<M> {
+        fn from(contract: ethers::contract::Contract<M>) -> Self {
+            Self(contract)
+        }
+    }
+    #[derive(
+        Clone,
+        Debug,
+        Eq,
+        PartialEq,
+        ethers :: contract :: EthEvent,
+        ethers :: contract :: EthDisplay,
+        Default,
+    )]
+    #[ethevent(name = "ApprovalForAll", abi = "ApprovalForAll(address,address,bool)")]
+    pub struct ApprovalForAllFilter {
+        #[ethevent(indexed)]
+        pub owner: ethers::core::types::Address,
+        #[ethevent(indexed)]
+        pub operator: ethers::core::types::Address,
+        pub approved: bool,
+    }
+    #[doc = "Container type for all input parameters for the `isApprovedForAll` function with signature `isApprovedForAll(address,address)` and selector `[233, 133, 233, 197]`"]
+    #[derive(
+        Clone,
+        Debug,
+        Eq,
+        PartialEq,
+        ethers :: contract :: EthCall,
+        ethers :: contract :: EthDisplay,
+        Default,
+    )]
+    #[ethcall(name = "isApprovedForAll", abi = "isApprovedForAll(address,address)")]
+    pub struct IsApprovedForAllCall {
+        pub owner: ethers::core::types::Address,
+        pub operator: ethers::core::types::Address,
+    }
+    #[doc = "Container type for all return fields from the `isApprovedForAll` function with signature `isApprovedForAll(address,address)` and selector `[233, 133, 233, 197]`"]
+    #[derive(
+        Clone,
+        Debug,
+        Eq,
+        PartialEq,
+        ethers :: contract :: EthAbiType,
+        ethers :: contract :: EthAbiCodec,
+        Default,
+    )]
+    pub struct IsApprovedForAllReturn(pub bool);
+}
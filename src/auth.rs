@@ -0,0 +1,258 @@
+//! Credentials of private HTTP endpoints. They are sent in headers,
+//! so keys do not have to be embedded in node urls, which end up in logs.
+
+use std::{
+    fmt::Debug,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ethers::{
+    providers::{Authorization, HttpClientError, JsonRpcClient},
+    utils::hex,
+};
+use hmac::{Hmac, Mac};
+use instant::SystemTime;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use url::Url;
+
+/// Nodes which do not answer for this long are given up on,
+/// so that requests fail over or are retried instead of hanging
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Secret shared with the node to sign JWT bearer tokens with,
+/// as used by the engine API of execution clients
+#[derive(Clone)]
+pub struct JwtSecret([u8; 32]);
+
+impl JwtSecret {
+    pub fn new(secret: [u8; 32]) -> Self {
+        Self(secret)
+    }
+
+    /// HS256 token with the only `iat` claim required by nodes
+    fn token(&self, issued_at: u64) -> String {
+        let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"HS256","typ":"JWT"}"#);
+        let claims = URL_SAFE_NO_PAD.encode(format!(r#"{{"iat":{issued_at}}}"#));
+        let message = format!("{header}.{claims}");
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC accepts keys of any size");
+        mac.update(message.as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+        format!("{message}.{signature}")
+    }
+}
+
+impl Debug for JwtSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("JwtSecret(..)")
+    }
+}
+
+/// Parses hex secret as written to `jwt.hex` files by nodes
+impl FromStr for JwtSecret {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let secret = hex::decode(s.strip_prefix("0x").unwrap_or(s))?;
+        let secret = secret
+            .try_into()
+            .map_err(|_| anyhow!("JWT secret should be 32 bytes"))?;
+        Ok(Self(secret))
+    }
+}
+
+/// How to authenticate requests to HTTP nodes
+#[derive(Debug, Clone, Default)]
+pub struct Auth {
+    headers: HeaderMap,
+    jwt_secret: Option<JwtSecret>,
+}
+
+impl Auth {
+    /// Sends the header with every request, e.g. an API key
+    pub fn header(mut self, name: &str, value: &str) -> anyhow::Result<Self> {
+        let name = HeaderName::from_str(name)?;
+        let mut value = HeaderValue::from_str(value)?;
+        value.set_sensitive(true);
+        self.headers.insert(name, value);
+        Ok(self)
+    }
+
+    /// Parses `Name: value` header
+    pub fn raw_header(self, header: &str) -> anyhow::Result<Self> {
+        let (name, value) = header
+            .split_once(':')
+            .ok_or_else(|| anyhow!("expected NAME: VALUE header"))?;
+        self.header(name.trim(), value.trim())
+    }
+
+    /// Authenticates with user and password
+    pub fn basic(self, user: &str, password: &str) -> anyhow::Result<Self> {
+        self.header(
+            AUTHORIZATION.as_str(),
+            &Authorization::basic(user, password).to_string(),
+        )
+    }
+
+    /// Authenticates with fresh bearer tokens signed by the secret,
+    /// replacing any other `Authorization` header
+    pub fn jwt(mut self, secret: JwtSecret) -> Self {
+        self.jwt_secret = Some(secret);
+        self
+    }
+
+    /// Client sending the static headers, the JWT is added per request
+    fn client(&self, timeout: Duration) -> reqwest::Client {
+        // same as `Http::new` does, which panics if the client can not be built
+        let builder = reqwest::Client::builder().default_headers(self.headers.clone());
+        // browsers do not support timeouts of the client
+        #[cfg(not(target_arch = "wasm32"))]
+        let builder = builder.timeout(timeout);
+        #[cfg(target_arch = "wasm32")]
+        let _ = timeout;
+        builder.build().expect("failed to build HTTP client")
+    }
+
+    /// Bearer token signed by the secret, if any. Nodes accept tokens
+    /// issued within a minute, so a new one is signed for every request.
+    fn bearer(&self) -> Option<HeaderValue> {
+        let secret = self.jwt_secret.as_ref()?;
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let token = Authorization::bearer(secret.token(now.as_secs()));
+        let mut value = HeaderValue::from_str(&token.to_string()).expect("token is valid header");
+        value.set_sensitive(true);
+        Some(value)
+    }
+}
+
+#[derive(Serialize)]
+struct Request<'a, T> {
+    jsonrpc: &'a str,
+    id: u64,
+    method: &'a str,
+    params: T,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    #[serde(default)]
+    result: Value,
+    error: Option<Value>,
+}
+
+/// HTTP transport sending credentials with each request.
+/// JWT tokens are signed per request, so they never get stale,
+/// while the connections are kept by the one client.
+#[derive(Debug)]
+pub struct AuthHttp {
+    url: Url,
+    auth: Auth,
+    client: reqwest::Client,
+    id: AtomicU64,
+}
+
+impl AuthHttp {
+    pub fn new(url: impl Into<Url>, auth: Auth) -> Self {
+        Self::new_with_timeout(url, auth, REQUEST_TIMEOUT)
+    }
+
+    /// Fails requests not answered within the timeout, which is
+    /// [`REQUEST_TIMEOUT`] by default. Ignored in browsers.
+    pub fn new_with_timeout(url: impl Into<Url>, auth: Auth, timeout: Duration) -> Self {
+        let client = auth.client(timeout);
+        Self {
+            url: url.into(),
+            auth,
+            client,
+            id: AtomicU64::new(1),
+        }
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl JsonRpcClient for AuthHttp {
+    type Error = HttpClientError;
+
+    /// Same as [`ethers::providers::Http`] does, with the `Authorization` header of the request
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned,
+    {
+        let request = Request {
+            jsonrpc: "2.0",
+            id: self.id.fetch_add(1, Ordering::SeqCst),
+            method,
+            params,
+        };
+        let mut builder = self.client.post(self.url.as_ref()).json(&request);
+        if let Some(bearer) = self.auth.bearer() {
+            builder = builder.header(AUTHORIZATION, bearer);
+        }
+        let body = builder.send().await?.bytes().await?;
+        let serde_error = |err| HttpClientError::SerdeJson {
+            err,
+            text: String::from_utf8_lossy(&body).to_string(),
+        };
+        let response: Response = serde_json::from_slice(&body).map_err(serde_error)?;
+        match response.error {
+            Some(error) => Err(HttpClientError::JsonRpcError(
+                serde_json::from_value(error).map_err(serde_error)?,
+            )),
+            None => serde_json::from_value(response.result).map_err(serde_error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_token_with_hs256() {
+        let secret = JwtSecret::new([7; 32]);
+        let token = secret.token(1_700_000_000);
+        let parts: Vec<_> = token.split('.').collect();
+        let [header, claims, signature] = parts[..] else {
+            panic!("token should have 3 parts: {token}");
+        };
+
+        let decode = |part| -> Value {
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(part).unwrap()).unwrap()
+        };
+        assert_eq!(
+            decode(header),
+            serde_json::json!({ "alg": "HS256", "typ": "JWT" })
+        );
+        assert_eq!(decode(claims), serde_json::json!({ "iat": 1_700_000_000 }));
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(&[7; 32]).unwrap();
+        mac.update(format!("{header}.{claims}").as_bytes());
+        mac.verify_slice(&URL_SAFE_NO_PAD.decode(signature).unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn parses_hex_secret() {
+        let hex = format!("0x{}\n", "07".repeat(32));
+        let secret: JwtSecret = hex.parse().unwrap();
+        assert_eq!(secret.token(1), JwtSecret::new([7; 32]).token(1));
+        assert!("0x0707".parse::<JwtSecret>().is_err());
+    }
+}
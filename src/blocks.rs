@@ -0,0 +1,297 @@
+use std::{collections::HashMap, fmt::Display, str::FromStr, sync::Arc, time::Duration};
+
+use anyhow::anyhow;
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use ethers::{
+    providers::Middleware,
+    types::{BlockNumber, U64},
+};
+use futures::{
+    stream::{self, StreamExt, TryStreamExt},
+    Future,
+};
+use itertools::Itertools;
+
+use crate::{
+    cached::CachedMap,
+    error::Error,
+    time::{format_duration, parse_duration},
+};
+
+/// Max number of block headers requested at once. These are separate
+/// concurrent requests, as the middleware stack can not send JSON-RPC batches.
+pub(crate) const MAX_CONCURRENT_HEADERS: usize = 32;
+
+/// Block referenced either directly or by time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockRef {
+    /// Block number or tag: `earliest`, `latest`, `safe`, `finalized` or `pending`
+    Block(BlockNumber),
+    /// First block produced at or after the time
+    Time(DateTime<Utc>),
+    /// Time relative to the latest block
+    Ago(Duration),
+}
+
+impl BlockRef {
+    /// Parses block number (decimal or `0x`-prefixed hex) or tag
+    pub fn parse_block(s: &str) -> anyhow::Result<Self> {
+        Ok(Self::Block(match s {
+            "earliest" => BlockNumber::Earliest,
+            "latest" => BlockNumber::Latest,
+            "safe" => BlockNumber::Safe,
+            "finalized" => BlockNumber::Finalized,
+            "pending" => BlockNumber::Pending,
+            n => match n.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16),
+                None => n.parse(),
+            }
+            .map_err(|_| {
+                anyhow!(
+                    "expected block number or one of: earliest, latest, safe, finalized, pending"
+                )
+            })?
+            .into(),
+        }))
+    }
+
+    /// Parses date (`2023-01-01`), RFC 3339 time (`2023-01-01T12:00:00Z`)
+    /// or duration before the latest block (`30d`)
+    pub fn parse_time(s: &str) -> anyhow::Result<Self> {
+        if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+            return Ok(Self::Time(
+                Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()),
+            ));
+        }
+        if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+            return Ok(Self::Time(time.with_timezone(&Utc)));
+        }
+        parse_duration(s)
+            .map(Self::Ago)
+            .map_err(|_| anyhow!("expected date, RFC 3339 time or duration, e.g. `30d`"))
+    }
+}
+
+/// Accepts everything that [`BlockRef::parse_block`] and [`BlockRef::parse_time`] do
+impl FromStr for BlockRef {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_block(s).or_else(|_| Self::parse_time(s))
+    }
+}
+
+impl Display for BlockRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Block(BlockNumber::Number(n)) => write!(f, "{n}"),
+            Self::Block(tag) => write!(f, "{tag}"),
+            Self::Time(time) => write!(f, "{time}"),
+            Self::Ago(d) => write!(f, "{} ago", format_duration(*d)),
+        }
+    }
+}
+
+/// Which end of the range a time is resolved for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Bound {
+    /// First block at or after the time
+    Start,
+    /// Last block before or at the time
+    End,
+}
+
+pub struct CachedBlocks<M: Middleware> {
+    client: Arc<M>,
+    timestamps: CachedMap<U64, DateTime<Utc>>,
+    /// first block at or after the time
+    by_time: CachedMap<DateTime<Utc>, U64>,
+}
+
+impl<M: Middleware + 'static> CachedBlocks<M> {
+    pub fn new(client: impl Into<Arc<M>>) -> Self {
+        Self {
+            client: client.into(),
+            timestamps: CachedMap::new("block_timestamps"),
+            by_time: CachedMap::new("blocks_by_time"),
+        }
+    }
+
+    /// Resolves block reference to a block number, times are resolved
+    /// by binary search over block headers
+    pub(crate) async fn try_resolve(
+        &self,
+        block: BlockRef,
+        bound: Bound,
+    ) -> Result<BlockNumber, Error> {
+        let time = match block {
+            BlockRef::Block(block) => return Ok(block),
+            BlockRef::Time(time) => time,
+            BlockRef::Ago(ago) => {
+                let head = self.head().await?;
+                self.try_get_timestamp(head).await?
+                    - chrono::Duration::from_std(ago).map_err(|_| {
+                        Error::InvalidInput(format!(
+                            "duration is too long: {}",
+                            format_duration(ago)
+                        ))
+                    })?
+            }
+        };
+        let time = match bound {
+            Bound::Start => time,
+            Bound::End => time + chrono::Duration::seconds(1),
+        };
+        let first = self.try_find_block(time).await?;
+        Ok(match bound {
+            Bound::Start => first,
+            Bound::End => first.saturating_sub(1.into()),
+        }
+        .into())
+    }
+
+    /// First block at or after the time, or the one after the
+    /// latest block if there is no such block yet
+    async fn try_find_block(&self, time: DateTime<Utc>) -> Result<U64, Error> {
+        let head = self.head().await?;
+        if self.try_get_timestamp(head).await? < time {
+            // not cached, since the block is yet to be produced
+            return Ok(head + 1);
+        }
+        self.by_time
+            .get_or_try_insert_with(time, || async move {
+                let (mut lo, mut hi) = (U64::zero(), head);
+                while lo < hi {
+                    let mid = lo + (hi - lo) / 2;
+                    if self.try_get_timestamp(mid).await? < time {
+                        lo = mid + 1;
+                    } else {
+                        hi = mid;
+                    }
+                }
+                Ok(hi)
+            })
+            .await
+    }
+
+    /// Number of the block, tags are resolved by block headers
+    pub async fn try_get_number(&self, block: BlockNumber) -> Result<U64, Error> {
+        match block {
+            BlockNumber::Number(number) => Ok(number),
+            BlockNumber::Earliest => Ok(U64::zero()),
+            BlockNumber::Latest => self.head().await,
+            tag => self
+                .client
+                .get_block(tag)
+                .await
+                .map_err(Error::rpc)?
+                .and_then(|block| block.number)
+                .ok_or_else(|| Error::rpc(format!("{tag} block not found"))),
+        }
+    }
+
+    async fn head(&self) -> Result<U64, Error> {
+        self.client.get_block_number().await.map_err(Error::rpc)
+    }
+
+    pub async fn try_get_timestamp(&self, number: U64) -> Result<DateTime<Utc>, Error> {
+        self.timestamps
+            .get_or_try_insert_with(number, || async move {
+                let block = self
+                    .client
+                    .get_block(number)
+                    .await
+                    .map_err(Error::rpc)?
+                    .ok_or_else(|| Error::rpc(format!("block {number} not found")))?;
+                i64::try_from(block.timestamp)
+                    .ok()
+                    .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single())
+                    .ok_or_else(|| {
+                        Error::rpc(format!(
+                            "block {number} has invalid timestamp: {}",
+                            block.timestamp
+                        ))
+                    })
+            })
+            .await
+    }
+
+    /// Timestamps of all given blocks, deduplicated and requested
+    /// concurrently, at most [`MAX_CONCURRENT_HEADERS`] at a time
+    pub fn try_get_timestamps(
+        &self,
+        numbers: impl IntoIterator<Item = U64>,
+    ) -> impl Future<Output = Result<HashMap<U64, DateTime<Utc>>, Error>> + '_ {
+        // collected before the future is created, so the future does not
+        // hold borrowing iterators and stays `Send` for any lifetime
+        let numbers: Vec<_> = numbers.into_iter().unique().collect();
+        stream::iter(numbers)
+            .map(move |number| async move {
+                self.try_get_timestamp(number)
+                    .await
+                    .map(|timestamp| (number, timestamp))
+            })
+            .buffer_unordered(MAX_CONCURRENT_HEADERS)
+            .try_collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_blocks() {
+        let parse = |s| BlockRef::parse_block(s).unwrap();
+        assert_eq!(
+            parse("100"),
+            BlockRef::Block(BlockNumber::Number(100.into()))
+        );
+        assert_eq!(
+            parse("0x64"),
+            BlockRef::Block(BlockNumber::Number(100.into()))
+        );
+        assert_eq!(parse("earliest"), BlockRef::Block(BlockNumber::Earliest));
+        assert_eq!(parse("finalized"), BlockRef::Block(BlockNumber::Finalized));
+        for invalid in ["", "-1", "0x", "0xzz", "head", "2023-01-01", "30d"] {
+            assert!(
+                BlockRef::parse_block(invalid).is_err(),
+                "{invalid:?} should fail"
+            );
+        }
+    }
+
+    #[test]
+    fn parses_times() {
+        let parse = |s| BlockRef::parse_time(s).unwrap();
+        assert_eq!(
+            parse("2023-01-01"),
+            BlockRef::Time(Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap())
+        );
+        assert_eq!(
+            parse("2023-01-01T12:00:00+02:00"),
+            BlockRef::Time(Utc.with_ymd_and_hms(2023, 1, 1, 10, 0, 0).unwrap())
+        );
+        assert_eq!(
+            parse("30d"),
+            BlockRef::Ago(Duration::from_secs(30 * 86_400))
+        );
+        for invalid in ["", "100", "latest", "2023-13-01", "yesterday"] {
+            assert!(
+                BlockRef::parse_time(invalid).is_err(),
+                "{invalid:?} should fail"
+            );
+        }
+    }
+
+    #[test]
+    fn parses_either_and_displays() {
+        let block: BlockRef = "0x10".parse().unwrap();
+        assert_eq!(block.to_string(), "16");
+        let tag: BlockRef = "safe".parse().unwrap();
+        assert_eq!(tag.to_string(), "safe");
+        let ago: BlockRef = "1y6w".parse().unwrap();
+        assert_eq!(ago.to_string(), "1y 6w ago");
+        assert!("tomorrow".parse::<BlockRef>().is_err());
+    }
+}
@@ -0,0 +1,141 @@
+use std::sync::Arc;
+
+use ethers::{
+    providers::{ens::ENS_ADDRESS, Middleware},
+    types::{Address, H256},
+};
+use futures::lock::Mutex;
+
+#[cfg(all(feature = "index", not(target_arch = "wasm32")))]
+use crate::Index;
+use crate::{
+    blocks::CachedBlocks, chain::ChainProfile, ens::CachedNames, erc20::CachedTokens,
+    proof::Prover, quorum::Quorum, spender::CachedSpenders, verify::Verifier, App, Error,
+    Fingerprints,
+};
+
+/// Configures [`App`] over an arbitrary middleware, see [`App::builder`]
+pub struct AppBuilder<M: Middleware> {
+    client: Arc<M>,
+    fingerprints: Fingerprints,
+    ens_registry: Address,
+    chain: Option<ChainProfile>,
+    quorum: Option<(Vec<Arc<M>>, usize)>,
+    verification: Option<Option<H256>>,
+    storage_proofs: bool,
+    #[cfg(all(feature = "index", not(target_arch = "wasm32")))]
+    index: Option<Arc<Index>>,
+}
+
+impl<M: Middleware + 'static> AppBuilder<M> {
+    pub fn new(client: impl Into<Arc<M>>) -> Self {
+        Self {
+            client: client.into(),
+            fingerprints: Fingerprints::default(),
+            ens_registry: ENS_ADDRESS,
+            chain: None,
+            quorum: None,
+            verification: None,
+            storage_proofs: false,
+            #[cfg(all(feature = "index", not(target_arch = "wasm32")))]
+            index: None,
+        }
+    }
+
+    /// Match spender bytecode against given known drainer fingerprints
+    pub fn fingerprints(mut self, fingerprints: Fingerprints) -> Self {
+        self.fingerprints = fingerprints;
+        self
+    }
+
+    /// Use custom ENS registry instead of the mainnet one
+    pub fn ens_registry(mut self, registry: Address) -> Self {
+        self.ens_registry = registry;
+        self
+    }
+
+    /// Use given chain profile instead of detecting it by `eth_chainId`
+    pub fn chain_profile(mut self, profile: ChainProfile) -> Self {
+        self.chain = Some(profile);
+        self
+    }
+
+    /// Query approval logs from the main client and given independent
+    /// ones, reporting only logs that at least `threshold` of them agree on.
+    /// See [`App::get_checked_token_approvals`].
+    ///
+    /// Fails with [`Error::InvalidInput`] if threshold is zero or greater
+    /// than the total number of clients
+    pub fn quorum(
+        mut self,
+        others: impl IntoIterator<Item = M>,
+        threshold: usize,
+    ) -> Result<Self, Error> {
+        let clients: Vec<_> = [self.client.clone()]
+            .into_iter()
+            .chain(others.into_iter().map(Arc::new))
+            .collect();
+        if !(1..=clients.len()).contains(&threshold) {
+            return Err(Error::InvalidInput(format!(
+                "quorum threshold should be in range 1..={}",
+                clients.len()
+            )));
+        }
+        self.quorum = Some((clients, threshold));
+        Ok(self)
+    }
+
+    /// Verify each approval log against the receipts root of its block,
+    /// and that the block is an ancestor of the trusted `checkpoint` block,
+    /// if given. See [`Verification`](crate::Verification).
+    ///
+    /// Requires `eth_getBlockReceipts` support from the node. Checking
+    /// against a checkpoint fetches every block header between the oldest
+    /// approval and the checkpoint, so it should not be too far away.
+    pub fn verification(mut self, checkpoint: Option<H256>) -> Self {
+        self.verification = Some(checkpoint);
+        self
+    }
+
+    /// Prove current allowances in [`App::get_allowances`] by storage
+    /// proofs against the state root of the head block.
+    /// See [`ProvenAllowance`](crate::ProvenAllowance).
+    ///
+    /// Slot of the allowance is found by probing common layouts of
+    /// the allowances mapping against `allowance()` call.
+    pub fn storage_proofs(mut self) -> Self {
+        self.storage_proofs = true;
+        self
+    }
+
+    /// Answer approval queries from the index, with blocks after
+    /// the last indexed one queried from the node. Not used in quorum
+    /// mode or for ranges starting before the index.
+    /// See [`App::sync_index`].
+    #[cfg(all(feature = "index", not(target_arch = "wasm32")))]
+    pub fn index(mut self, index: Arc<Index>) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    pub fn build(self) -> App<M> {
+        let client = self.client;
+        App {
+            chain: Mutex::new(self.chain.map(Arc::new)),
+            tokens: CachedTokens::new(client.clone()),
+            spenders: CachedSpenders::new(client.clone(), Arc::new(self.fingerprints)),
+            blocks: CachedBlocks::new(client.clone()),
+            names: CachedNames::new(client.clone(), self.ens_registry),
+            quorum: self
+                .quorum
+                .map(|(clients, threshold)| Quorum::new(clients, threshold)),
+            verifier: self
+                .verification
+                .map(|checkpoint| Verifier::new(client.clone(), checkpoint)),
+            prover: self.storage_proofs.then(|| Prover::new(client.clone())),
+            #[cfg(all(feature = "index", not(target_arch = "wasm32")))]
+            index: self.index,
+            client,
+        }
+    }
+}
@@ -7,12 +7,12 @@ use futures::{
     Future, TryFuture, TryFutureExt,
 };
 
-pub struct CachedMap<K, V>(Mutex<HashMap<K, Arc<Mutex<Option<V>>>>>);
+use crate::metrics::observe_cache;
 
-impl<K, V> Default for CachedMap<K, V> {
-    fn default() -> Self {
-        Self(Mutex::new(HashMap::new()))
-    }
+pub struct CachedMap<K, V> {
+    /// label of lookup metrics
+    name: &'static str,
+    map: Mutex<HashMap<K, Arc<Mutex<Option<V>>>>>,
 }
 
 impl<K, V> CachedMap<K, V>
@@ -20,13 +20,23 @@ where
     K: Eq + Hash,
     V: Clone,
 {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            map: Mutex::new(HashMap::new()),
+        }
+    }
+
     async fn get_locked(&self, key: K) -> OwnedMutexGuard<Option<V>> {
-        let mut m = self.0.lock().await;
-        m.entry(key)
+        let mut m = self.map.lock().await;
+        let v = m
+            .entry(key)
             .or_insert_with(|| Arc::new(Mutex::new(None)))
             .clone()
             .lock_owned() // lock is aquired before the whole map lock is released
-            .await
+            .await;
+        observe_cache(self.name, v.is_some());
+        v
     }
 
     #[allow(dead_code)]
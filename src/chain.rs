@@ -0,0 +1,146 @@
+use ethers::types::{Address, H256};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Multicall3 is deployed at the same address on most of the chains
+const MULTICALL3: &str = "0xcA11bde05977b3631167028862bE2a173976CA11";
+const PERMIT2: &str = "0x000000000022D473030F116dDEE9F6B43aC78BA3";
+
+/// Per-chain settings, selected automatically by `eth_chainId`
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ChainProfile {
+    pub chain_id: u64,
+    pub name: &'static str,
+    /// Transaction URL template, where `{tx}` is replaced by transaction hash
+    pub explorer_tx_url: Option<&'static str>,
+    pub multicall: Option<Address>,
+    pub permit2: Option<Address>,
+    /// Max number of blocks in a single `eth_getLogs` request,
+    /// larger ranges are split into chunks
+    pub max_log_range: Option<u64>,
+    pub native_symbol: &'static str,
+}
+
+impl ChainProfile {
+    /// Profile of a known chain, or a generic one without
+    /// explorer, contracts and log range limit
+    pub fn for_chain(chain_id: u64) -> Self {
+        Self::known(chain_id).unwrap_or(Self {
+            chain_id,
+            name: "unknown",
+            explorer_tx_url: None,
+            multicall: None,
+            permit2: None,
+            max_log_range: None,
+            native_symbol: "ETH",
+        })
+    }
+
+    /// Ethereum mainnet and supported L2s
+    pub fn known(chain_id: u64) -> Option<Self> {
+        let (name, explorer_tx_url, multicall, permit2, max_log_range) = match chain_id {
+            1 => (
+                "Ethereum",
+                "https://etherscan.io/tx/{tx}",
+                MULTICALL3,
+                PERMIT2,
+                None,
+            ),
+            10 => (
+                "Optimism",
+                "https://optimistic.etherscan.io/tx/{tx}",
+                MULTICALL3,
+                PERMIT2,
+                Some(10_000),
+            ),
+            324 => (
+                "zkSync Era",
+                "https://explorer.zksync.io/tx/{tx}",
+                "0xF9cda624FBC7e059355ce98a31693d299FACd963",
+                "0x0000000000225e31D15943971F47aD3022F714Fa",
+                Some(10_000),
+            ),
+            8453 => (
+                "Base",
+                "https://basescan.org/tx/{tx}",
+                MULTICALL3,
+                PERMIT2,
+                Some(10_000),
+            ),
+            42161 => (
+                "Arbitrum One",
+                "https://arbiscan.io/tx/{tx}",
+                MULTICALL3,
+                PERMIT2,
+                Some(10_000),
+            ),
+            59144 => (
+                "Linea",
+                "https://lineascan.build/tx/{tx}",
+                MULTICALL3,
+                PERMIT2,
+                Some(10_000),
+            ),
+            _ => return None,
+        };
+        Some(Self {
+            chain_id,
+            name,
+            explorer_tx_url: Some(explorer_tx_url),
+            multicall: Some(multicall.parse().expect("valid multicall address")),
+            permit2: Some(permit2.parse().expect("valid permit2 address")),
+            max_log_range,
+            native_symbol: "ETH",
+        })
+    }
+
+    pub fn tx_url(&self, tx: H256) -> Option<String> {
+        self.explorer_tx_url
+            .map(|template| template.replace("{tx}", &format!("{tx:#x}")))
+    }
+}
+
+/// Names and explorer are taken from the profile of the chain id,
+/// contracts and log range limit are kept as serialized
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for ChainProfile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Settings {
+            chain_id: u64,
+            multicall: Option<Address>,
+            permit2: Option<Address>,
+            max_log_range: Option<u64>,
+        }
+
+        let settings = Settings::deserialize(deserializer)?;
+        Ok(Self {
+            multicall: settings.multicall,
+            permit2: settings.permit2,
+            max_log_range: settings.max_log_range,
+            ..Self::for_chain(settings.chain_id)
+        })
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserializes_profile_of_chain_id() {
+        let mut profile = ChainProfile::for_chain(10);
+        profile.max_log_range = Some(2_000);
+        let json = serde_json::to_string(&profile).unwrap();
+        let parsed: ChainProfile = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.name, "Optimism");
+        assert_eq!(parsed.explorer_tx_url, profile.explorer_tx_url);
+        assert_eq!(parsed.multicall, profile.multicall);
+        assert_eq!(parsed.max_log_range, Some(2_000));
+
+        let parsed: ChainProfile = serde_json::from_str(r#"{"chain_id":1337}"#).unwrap();
+        assert_eq!(parsed.name, "unknown");
+        assert_eq!(parsed.multicall, None);
+    }
+}
@@ -0,0 +1,399 @@
+//! Scan state persisted between runs, so that subsequent scans of
+//! the same owner only fetch logs from blocks produced since.
+
+use std::collections::HashMap;
+
+#[cfg(all(feature = "serde", not(target_arch = "wasm32")))]
+use std::{fs, path::PathBuf};
+
+use ethers::{
+    contract::LogMeta,
+    types::{Address, H256, U256, U64},
+};
+#[cfg(feature = "serde")]
+use {
+    itertools::Itertools,
+    serde::{Deserialize, Deserializer, Serialize, Serializer},
+};
+
+use crate::{abi::ierc20::ApprovalFilter, REORG_DEPTH};
+
+/// Approval logs of an owner folded up to the last fully scanned block
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Checkpoint {
+    /// First block of the scan
+    from: U64,
+    /// Last fully scanned block and its hash, to detect reorgs
+    last_block: Option<(U64, H256)>,
+    /// Blocks saved before the last one, oldest first: the ones within
+    /// [`REORG_DEPTH`] of it and the newest one below, to find the common
+    /// ancestor after a reorg
+    #[cfg_attr(feature = "serde", serde(default))]
+    earlier_blocks: Vec<(U64, H256)>,
+    /// Approval logs for each (token, spender) pair, oldest first: the latest
+    /// one, including revoked ones, so older logs can not override them, and
+    /// the ones it superseded since the earliest saved block, so the pair can
+    /// be rewound to it
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            serialize_with = "serialize_folded",
+            deserialize_with = "deserialize_folded"
+        )
+    )]
+    approvals: FoldedApprovals,
+}
+
+/// Approval logs of each (token, spender) pair, oldest first
+type FoldedApprovals = HashMap<(Address, Address), Vec<FoldedApproval>>;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct FoldedApproval {
+    spender: Address,
+    value: U256,
+    meta: LogMeta,
+}
+
+impl FoldedApproval {
+    fn position(&self) -> (U64, U256) {
+        (self.meta.block_number, self.meta.log_index)
+    }
+}
+
+impl Checkpoint {
+    pub fn new(from: U64) -> Self {
+        Self {
+            from,
+            last_block: None,
+            earlier_blocks: Vec::new(),
+            approvals: HashMap::new(),
+        }
+    }
+
+    pub fn from(&self) -> U64 {
+        self.from
+    }
+
+    pub fn last_block(&self) -> Option<(U64, H256)> {
+        self.last_block
+    }
+
+    /// Number of (token, spender) pairs with any approval
+    pub fn len(&self) -> usize {
+        self.approvals.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.approvals.is_empty()
+    }
+
+    /// Block to continue the scan from
+    pub(crate) fn next_block(&self) -> U64 {
+        self.last_block.map_or(self.from, |(block, _)| block + 1)
+    }
+
+    /// Saved blocks, newest first
+    pub(crate) fn saved_blocks(&self) -> impl Iterator<Item = (U64, H256)> + '_ {
+        self.last_block
+            .into_iter()
+            .chain(self.earlier_blocks.iter().rev().copied())
+    }
+
+    /// Merges logs into the state in any order, keeping the latest one
+    /// for each pair along with the ones it superseded
+    pub(crate) fn fold(&mut self, logs: impl IntoIterator<Item = (ApprovalFilter, LogMeta)>) {
+        for (approval, meta) in logs {
+            let folded = FoldedApproval {
+                spender: approval.spender,
+                value: approval.value,
+                meta,
+            };
+            let pair = self
+                .approvals
+                .entry((folded.meta.address, folded.spender))
+                .or_default();
+            match pair.binary_search_by_key(&folded.position(), FoldedApproval::position) {
+                // the same log fetched again
+                Ok(_) => {}
+                Err(index) => pair.insert(index, folded),
+            }
+        }
+        self.prune();
+    }
+
+    /// Marks all blocks up to the given one as scanned
+    pub(crate) fn advance(&mut self, block: U64, hash: H256) {
+        self.earlier_blocks
+            .extend(self.last_block.replace((block, hash)));
+        self.prune();
+    }
+
+    /// Drops the state of blocks after the given saved one,
+    /// which is the common ancestor with the reorged chain
+    pub(crate) fn rewind(&mut self, block: U64) {
+        let Some(saved) = self.saved_blocks().find(|(saved, _)| *saved == block) else {
+            // not saved, so nothing is known to survive
+            *self = Self::new(self.from);
+            return;
+        };
+        self.last_block = Some(saved);
+        self.earlier_blocks.retain(|(earlier, _)| *earlier < block);
+        self.approvals.retain(|_, pair| {
+            pair.retain(|folded| folded.meta.block_number <= block);
+            !pair.is_empty()
+        });
+    }
+
+    /// Keeps saved blocks within the reorg depth of the last one along
+    /// with the newest one below, and approvals superseded since then
+    fn prune(&mut self) {
+        let Some((last, _)) = self.last_block else {
+            return;
+        };
+        let below = self
+            .earlier_blocks
+            .iter()
+            .rposition(|(block, _)| *block + REORG_DEPTH <= last);
+        if let Some(below) = below {
+            self.earlier_blocks.drain(..below);
+        }
+        let oldest = self.saved_blocks().last().map_or(last, |(block, _)| block);
+        for pair in self.approvals.values_mut() {
+            let kept = pair
+                .iter()
+                .rposition(|folded| folded.meta.block_number <= oldest);
+            if let Some(kept) = kept {
+                pair.drain(..kept);
+            }
+        }
+    }
+
+    /// Latest non-zero approval logs since the block
+    pub(crate) fn live_logs(&self, owner: Address, since: U64) -> Vec<(ApprovalFilter, LogMeta)> {
+        self.approvals
+            .values()
+            .filter_map(|pair| pair.last())
+            .filter(|folded| !folded.value.is_zero() && folded.meta.block_number >= since)
+            .map(|folded| {
+                (
+                    ApprovalFilter {
+                        owner,
+                        spender: folded.spender,
+                        value: folded.value,
+                    },
+                    folded.meta.clone(),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Pairs are part of the approvals, so only approvals are written
+#[cfg(feature = "serde")]
+fn serialize_folded<S: Serializer>(
+    approvals: &FoldedApprovals,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut approvals: Vec<_> = approvals.values().flatten().collect();
+    approvals.sort_by_key(|folded| folded.position());
+    approvals.serialize(serializer)
+}
+
+#[cfg(feature = "serde")]
+fn deserialize_folded<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<FoldedApprovals, D::Error> {
+    let mut approvals = Vec::<FoldedApproval>::deserialize(deserializer)?;
+    approvals.sort_by_key(|folded| folded.position());
+    Ok(approvals
+        .into_iter()
+        .into_group_map_by(|folded| (folded.meta.address, folded.spender)))
+}
+
+/// Where checkpoints are kept between scans
+pub trait CheckpointStore: Send + Sync {
+    fn load(&self, chain_id: u64, owner: Address) -> anyhow::Result<Option<Checkpoint>>;
+
+    fn save(&self, chain_id: u64, owner: Address, checkpoint: &Checkpoint) -> anyhow::Result<()>;
+}
+
+/// Keeps checkpoints as JSON files at `{dir}/{chain_id}/{owner}.json`
+#[cfg(all(feature = "serde", not(target_arch = "wasm32")))]
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+#[cfg(all(feature = "serde", not(target_arch = "wasm32")))]
+impl FileStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, chain_id: u64, owner: Address) -> PathBuf {
+        self.dir
+            .join(chain_id.to_string())
+            .join(format!("{owner:#x}.json"))
+    }
+}
+
+#[cfg(all(feature = "serde", not(target_arch = "wasm32")))]
+impl CheckpointStore for FileStore {
+    fn load(&self, chain_id: u64, owner: Address) -> anyhow::Result<Option<Checkpoint>> {
+        match fs::read(self.path(chain_id, owner)) {
+            Ok(json) => Ok(Some(serde_json::from_slice(&json)?)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn save(&self, chain_id: u64, owner: Address, checkpoint: &Checkpoint) -> anyhow::Result<()> {
+        let path = self.path(chain_id, owner);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // written aside and renamed, so an interrupted write
+        // does not corrupt the previous checkpoint
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(checkpoint)?)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(n: u64) -> Address {
+        Address::from_low_u64_be(n)
+    }
+
+    fn hash(block: u64) -> H256 {
+        H256::from_low_u64_be(block)
+    }
+
+    /// Approval log of the token to the spender in the block
+    fn log(block: u64, spender: u64, value: u64) -> (ApprovalFilter, LogMeta) {
+        let approval = ApprovalFilter {
+            owner: address(0xaa),
+            spender: address(spender),
+            value: value.into(),
+        };
+        let meta = LogMeta {
+            address: address(0x70),
+            block_number: block.into(),
+            block_hash: hash(block),
+            transaction_hash: hash(block),
+            transaction_index: 0.into(),
+            log_index: spender.into(),
+        };
+        (approval, meta)
+    }
+
+    /// (block, spender, value) of live logs, in order of blocks
+    fn live(checkpoint: &Checkpoint, since: u64) -> Vec<(u64, u64, u64)> {
+        let mut logs: Vec<_> = checkpoint
+            .live_logs(address(0xaa), since.into())
+            .into_iter()
+            .map(|(approval, meta)| {
+                (
+                    meta.block_number.as_u64(),
+                    approval.spender.to_low_u64_be(),
+                    approval.value.as_u64(),
+                )
+            })
+            .collect();
+        logs.sort();
+        logs
+    }
+
+    #[test]
+    fn folds_latest_approval_of_each_pair() {
+        let mut checkpoint = Checkpoint::new(0.into());
+        // chunks arrive in any order
+        checkpoint.fold([log(30, 1, 0), log(31, 2, 5)]);
+        checkpoint.fold([log(10, 1, 7), log(20, 2, 3), log(25, 3, 9)]);
+        // the same log fetched again
+        checkpoint.fold([log(31, 2, 5)]);
+        assert_eq!(checkpoint.len(), 3);
+        // revoked pair is left out
+        assert_eq!(live(&checkpoint, 0), [(25, 3, 9), (31, 2, 5)]);
+        assert_eq!(live(&checkpoint, 26), [(31, 2, 5)]);
+    }
+
+    #[test]
+    fn advances_next_block() {
+        let mut checkpoint = Checkpoint::new(5.into());
+        assert_eq!(checkpoint.next_block(), 5.into());
+        checkpoint.advance(100.into(), hash(100));
+        assert_eq!(checkpoint.next_block(), 101.into());
+        assert_eq!(checkpoint.last_block(), Some((100.into(), hash(100))));
+    }
+
+    #[test]
+    fn keeps_saved_blocks_within_reorg_depth() {
+        let mut checkpoint = Checkpoint::new(0.into());
+        for block in [100, 200, 250, 300, 310] {
+            checkpoint.advance(block.into(), hash(block));
+        }
+        let saved: Vec<_> = checkpoint
+            .saved_blocks()
+            .map(|(block, _)| block.as_u64())
+            .collect();
+        // 200 is the newest one at least `REORG_DEPTH` below 310
+        assert_eq!(saved, [310, 300, 250, 200]);
+    }
+
+    #[test]
+    fn rewinds_to_saved_block() {
+        let mut checkpoint = Checkpoint::new(0.into());
+        checkpoint.fold([log(10, 1, 7), log(20, 2, 3)]);
+        checkpoint.advance(50.into(), hash(50));
+        checkpoint.fold([log(60, 1, 0), log(70, 3, 1)]);
+        checkpoint.advance(80.into(), hash(80));
+        assert_eq!(live(&checkpoint, 0), [(20, 2, 3), (70, 3, 1)]);
+
+        checkpoint.rewind(50.into());
+        assert_eq!(checkpoint.last_block(), Some((50.into(), hash(50))));
+        assert_eq!(checkpoint.next_block(), 51.into());
+        // the revocation is reorged out, so the approval it superseded is live
+        assert_eq!(live(&checkpoint, 0), [(10, 1, 7), (20, 2, 3)]);
+        assert_eq!(checkpoint.len(), 2);
+    }
+
+    #[test]
+    fn prunes_approvals_superseded_before_saved_blocks() {
+        let mut checkpoint = Checkpoint::new(0.into());
+        checkpoint.fold([log(10, 1, 7), log(20, 1, 8)]);
+        checkpoint.advance(100.into(), hash(100));
+        checkpoint.fold([log(150, 1, 9)]);
+        checkpoint.advance(200.into(), hash(200));
+        checkpoint.advance(300.into(), hash(300));
+        // only the approval superseded since block 200 is kept
+        assert_eq!(checkpoint.approvals[&(address(0x70), address(1))].len(), 1);
+
+        // rewinding to a block which was not saved starts over
+        checkpoint.rewind(250.into());
+        assert_eq!(checkpoint.last_block(), None);
+        assert!(checkpoint.is_empty());
+        assert_eq!(checkpoint.from(), 0.into());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn reads_checkpoints_without_earlier_blocks() {
+        let mut checkpoint = Checkpoint::new(0.into());
+        checkpoint.fold([log(10, 1, 7), log(20, 2, 3), log(30, 1, 0)]);
+        checkpoint.advance(40.into(), hash(40));
+        let mut json = serde_json::to_value(&checkpoint).unwrap();
+        json.as_object_mut().unwrap().remove("earlier_blocks");
+
+        let read: Checkpoint = serde_json::from_value(json).unwrap();
+        assert_eq!(read.last_block(), checkpoint.last_block());
+        assert_eq!(live(&read, 0), [(20, 2, 3)]);
+        assert_eq!(read.len(), 2);
+    }
+}
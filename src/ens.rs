@@ -0,0 +1,245 @@
+use std::{
+    str::FromStr,
+    sync::{Arc, LazyLock},
+};
+
+use ens_normalize_rs::EnsNameNormalizer;
+use ethers::{
+    abi::{self, ParamType, Token},
+    providers::{ens, Middleware},
+    types::{Address, NameOrAddress, Selector},
+};
+
+use crate::{cached::CachedMap, error::Error};
+
+/// Spec data is parsed once, on the first use
+static NORMALIZER: LazyLock<EnsNameNormalizer> = LazyLock::new(EnsNameNormalizer::default);
+
+/// Parses hex address, treating everything else as ENS name.
+/// Names are normalized on resolution.
+pub fn parse_name_or_address(s: &str) -> NameOrAddress {
+    Address::from_str(s)
+        .map(NameOrAddress::Address)
+        .unwrap_or_else(|_| NameOrAddress::Name(s.to_string()))
+}
+
+/// Name as it is hashed by ENS, per ENSIP-15. Names which differ only
+/// in case or in equivalent Unicode forms normalize to the same name,
+/// while names with disallowed or confusable characters are rejected.
+fn normalize(name: &str) -> Result<String, Error> {
+    NORMALIZER
+        .normalize(name)
+        .map_err(|err| Error::InvalidInput(format!("invalid ENS name {name}: {err}")))
+}
+
+/// ENS resolution with primary names cached for the lifetime of the object
+pub struct CachedNames<M: Middleware> {
+    client: Arc<M>,
+    registry: Address,
+    cached: CachedMap<Address, Option<Arc<str>>>,
+}
+
+impl<M: Middleware + 'static> CachedNames<M> {
+    pub fn new(client: impl Into<Arc<M>>, registry: Address) -> Self {
+        Self {
+            client: client.into(),
+            registry,
+            cached: CachedMap::new("ens_names"),
+        }
+    }
+
+    pub async fn try_resolve(&self, name: &NameOrAddress) -> Result<Address, Error> {
+        let name = match name {
+            NameOrAddress::Address(address) => return Ok(*address),
+            NameOrAddress::Name(name) => normalize(name)?,
+        };
+        self.query_resolver(&name, ens::ADDR_SELECTOR, ParamType::Address)
+            .await?
+            .and_then(Token::into_address)
+            .filter(|address| !address.is_zero())
+            .ok_or_else(|| Error::InvalidInput(format!("ENS name {name} is not registered")))
+    }
+
+    /// Primary name of the address, if it is set, normalized
+    /// and resolves back to the address
+    pub async fn try_get_name(&self, address: Address) -> Result<Option<Arc<str>>, Error> {
+        self.cached
+            .get_or_try_insert_with(address, || async move {
+                let Some(name) = self
+                    .query_resolver(
+                        &ens::reverse_address(address),
+                        ens::NAME_SELECTOR,
+                        ParamType::String,
+                    )
+                    .await?
+                    .and_then(Token::into_string)
+                    .filter(|name| !name.is_empty())
+                else {
+                    return Ok(None);
+                };
+                // a name which is not normalized is displayed differently
+                // from the one it resolves as, e.g. with confusable characters
+                if normalize(&name).ok().as_deref() != Some(&*name) {
+                    return Ok(None);
+                }
+                // anyone can claim any name in reverse records
+                let owned = self
+                    .query_resolver(&name, ens::ADDR_SELECTOR, ParamType::Address)
+                    .await?
+                    .and_then(Token::into_address)
+                    == Some(address);
+                Ok(owned.then(|| name.into()))
+            })
+            .await
+    }
+
+    /// Returns None if the name has no resolver
+    async fn query_resolver(
+        &self,
+        name: &str,
+        selector: Selector,
+        param: ParamType,
+    ) -> Result<Option<Token>, Error> {
+        let data = self
+            .client
+            .call(&ens::get_resolver(self.registry, name).into(), None)
+            .await
+            .map_err(Error::rpc)?;
+        if data.is_empty() {
+            return Ok(None);
+        }
+        let Some(resolver) = abi::decode(&[ParamType::Address], &data)?
+            .pop()
+            .and_then(Token::into_address)
+            .filter(|resolver| !resolver.is_zero())
+        else {
+            return Ok(None);
+        };
+
+        let data = self
+            .client
+            .call(&ens::resolve(resolver, selector, name, None).into(), None)
+            .await
+            .map_err(Error::rpc)?;
+        if data.is_empty() {
+            return Ok(None);
+        }
+        Ok(abi::decode(&[param], &data)?.pop())
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::collections::HashMap;
+
+    use ethers::{
+        providers::{Http, Provider},
+        types::{Bytes, H256},
+    };
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing::{DevChain, Node, Reply};
+
+    const RESOLVER_SELECTOR: Selector = [0x01, 0x78, 0xb8, 0xbf];
+
+    fn address(n: u64) -> Address {
+        Address::from_low_u64_be(n)
+    }
+
+    /// Chain with the ENS registry and a single resolver holding
+    /// forward records of names and reverse records of addresses
+    async fn ens_chain(
+        forward: &[(&str, Address)],
+        reverse: &[(Address, &str)],
+    ) -> (Node, CachedNames<Provider<Http>>) {
+        let (registry, resolver) = (ens::ENS_ADDRESS, address(0xe5));
+        let mut records: HashMap<H256, Token> = HashMap::new();
+        for (name, address) in forward {
+            records.insert(ens::namehash(name), Token::Address(*address));
+        }
+        for (address, name) in reverse {
+            let node = ens::namehash(&ens::reverse_address(*address));
+            records.insert(node, Token::String(name.to_string()));
+        }
+        let node = DevChain::new(1, 10)
+            .start_with(move |method, params: &Value| {
+                let data: Bytes = serde_json::from_value(params[0]["data"].clone()).unwrap();
+                let to: Address = serde_json::from_value(params[0]["to"].clone()).unwrap();
+                let selector = Selector::try_from(&data[..4]).unwrap();
+                let node = H256::from_slice(&data[4..36]);
+                let record = records.get(&node);
+                let output = match (method, to, selector) {
+                    ("eth_call", to, RESOLVER_SELECTOR) if to == registry => {
+                        Token::Address(if record.is_some() {
+                            resolver
+                        } else {
+                            Address::zero()
+                        })
+                    }
+                    ("eth_call", to, ens::ADDR_SELECTOR | ens::NAME_SELECTOR) if to == resolver => {
+                        record.cloned().unwrap()
+                    }
+                    _ => return Reply::Error(-32601, "method not found"),
+                };
+                Reply::ok(Bytes::from(abi::encode(&[output])))
+            })
+            .await;
+        let names = CachedNames::new(Provider::new(Http::new(node.url.clone())), registry);
+        (node, names)
+    }
+
+    #[tokio::test]
+    async fn resolves_normalized_names() {
+        let (_node, names) = ens_chain(&[("vitalik.eth", address(1))], &[]).await;
+        for name in ["vitalik.eth", "Vitalik.ETH", "VITALIK.eth"] {
+            let resolved = names.try_resolve(&parse_name_or_address(name)).await;
+            assert_eq!(resolved.unwrap(), address(1), "{name}");
+        }
+        assert!(matches!(
+            names
+                .try_resolve(&parse_name_or_address("nobody.eth"))
+                .await,
+            Err(Error::InvalidInput(_))
+        ));
+        // empty label and zero width joiner
+        for name in ["vitalik..eth", "vita\u{200d}lik.eth"] {
+            let err = names
+                .try_resolve(&parse_name_or_address(name))
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains("invalid ENS name"), "{err}");
+        }
+    }
+
+    #[tokio::test]
+    async fn trusts_reverse_records_resolving_back() {
+        let (node, names) = ens_chain(
+            &[
+                ("vitalik.eth", address(1)),
+                ("Spoofed.eth", address(3)),
+                ("spoofed.eth", address(3)),
+            ],
+            &[
+                (address(1), "vitalik.eth"),
+                // anyone can claim a name in the reverse record
+                (address(2), "vitalik.eth"),
+                // not normalized, displayed unlike the resolved name
+                (address(3), "Spoofed.eth"),
+            ],
+        )
+        .await;
+        assert_eq!(
+            names.try_get_name(address(1)).await.unwrap().as_deref(),
+            Some("vitalik.eth")
+        );
+        assert_eq!(names.try_get_name(address(2)).await.unwrap(), None);
+        assert_eq!(names.try_get_name(address(3)).await.unwrap(), None);
+        assert_eq!(names.try_get_name(address(4)).await.unwrap(), None);
+
+        // names are cached
+        let calls = node.count("eth_call");
+        names.try_get_name(address(2)).await.unwrap();
+        assert_eq!(node.count("eth_call"), calls);
+    }
+}
@@ -1,22 +1,43 @@
-use std::{fmt::Display, sync::Arc};
+use std::{collections::HashMap, fmt::Display, sync::Arc, time::Duration};
 
+use chrono::{DateTime, Utc};
 use ethers::{
-    contract::{ContractError, LogMeta},
+    contract::LogMeta,
     providers::Middleware,
     types::{Address, U256},
 };
-use futures::future::try_join;
-
-#[cfg(target_arch = "wasm32")]
-use serde::Serialize;
+use futures::future::{try_join, try_join_all};
+use instant::Instant;
+use itertools::Itertools;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use tracing::{debug, debug_span, Instrument};
+#[cfg(feature = "multicall")]
+use {
+    crate::chain::ChainProfile,
+    ethers::{
+        abi::Token,
+        contract::{Multicall, MulticallError},
+    },
+};
 
 use crate::{
     abi::ierc20::{ApprovalFilter, IERC20},
     cached::CachedMap,
+    error::Error,
+    proof::ProvenAllowance,
+    risk::Risk,
+    spender::Spender,
+    time::format_duration,
+    verify::Verification,
 };
 
+/// Max number of calls aggregated into a single Multicall3 `eth_call`
+#[cfg(feature = "multicall")]
+const MULTICALL_BATCH: usize = 100;
+
 #[derive(Debug)]
-#[cfg_attr(target_arch = "wasm32", derive(Serialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct CachedERC20 {
     address: Address,
     symbol: String,
@@ -24,13 +45,50 @@ pub struct CachedERC20 {
 }
 
 impl CachedERC20 {
-    pub async fn new<M: Middleware>(
+    pub async fn new<M: Middleware + 'static>(
         address: impl Into<Address>,
         client: Arc<M>,
-    ) -> Result<Self, ContractError<M>> {
+    ) -> Result<Self, Error> {
         let address = address.into();
         let token = IERC20::new(address, client);
-        let (symbol, decimals) = try_join(token.symbol().call(), token.decimals().call()).await?;
+        let (symbol, decimals) = try_join(token.symbol().call(), token.decimals().call())
+            .await
+            .map_err(|err| Error::token_call(address, err))?;
+
+        Ok(Self {
+            address,
+            symbol,
+            decimals,
+        })
+    }
+
+    /// Same as [`CachedERC20::new`], with symbol and decimals fetched
+    /// in a single `eth_call` through the Multicall3 contract
+    #[cfg(feature = "multicall")]
+    pub async fn new_with_multicall<M: Middleware + 'static>(
+        address: impl Into<Address>,
+        client: Arc<M>,
+        multicall: Address,
+    ) -> Result<Self, Error> {
+        let address = address.into();
+        let token = IERC20::new(address, client.clone());
+        let multicall_error = |err| match err {
+            MulticallError::ContractError(err) => Error::token_call(address, err),
+            // symbol or decimals reverted
+            MulticallError::IllegalRevert => Error::NotErc20 {
+                token: address,
+                source: err.into(),
+            },
+            err => Error::rpc(err),
+        };
+        let mut calls = Multicall::new(client, Some(multicall))
+            .await
+            .map_err(multicall_error)?;
+        calls
+            .add_call(token.symbol(), false)
+            .add_call(token.decimals(), false);
+        let ((_, symbol), (_, decimals)): ((bool, String), (bool, u8)) =
+            calls.call().await.map_err(multicall_error)?;
 
         Ok(Self {
             address,
@@ -66,35 +124,147 @@ impl Display for CachedERC20 {
 
 pub struct CachedTokens<M: Middleware> {
     client: Arc<M>,
-    cached: CachedMap<Address, Arc<CachedERC20>>,
+    /// keyed by (chain id, token address)
+    cached: CachedMap<(u64, Address), Arc<CachedERC20>>,
 }
 
-impl<M: Middleware> CachedTokens<M> {
+impl<M: Middleware + 'static> CachedTokens<M> {
     pub fn new(client: impl Into<Arc<M>>) -> Self {
         Self {
             client: client.into(),
-            cached: Default::default(),
+            cached: CachedMap::new("tokens"),
         }
     }
 
     pub async fn try_get_token(
         &self,
+        chain_id: u64,
         address: Address,
-    ) -> Result<Arc<CachedERC20>, ContractError<M>> {
+    ) -> Result<Arc<CachedERC20>, Error> {
         self.cached
-            .get_or_try_insert_with(address, || CachedERC20::new(address, self.client.clone()))
+            .get_or_try_insert_with((chain_id, address), || {
+                async move {
+                    let started = Instant::now();
+                    #[cfg(feature = "multicall")]
+                    let token = match ChainProfile::for_chain(chain_id).multicall {
+                        Some(multicall) => {
+                            CachedERC20::new_with_multicall(address, self.client.clone(), multicall)
+                                .await?
+                        }
+                        None => CachedERC20::new(address, self.client.clone()).await?,
+                    };
+                    #[cfg(not(feature = "multicall"))]
+                    let token = CachedERC20::new(address, self.client.clone()).await?;
+                    debug!(symbol = %token.symbol(), latency = ?started.elapsed(), "got token");
+                    Ok(token)
+                }
+                .instrument(debug_span!("token_metadata", token = ?address, chain_id))
+            })
             .await
     }
+
+    /// Current allowances of the owner for (token, spender) pairs. Logs
+    /// are not enough, since allowances spent by `transferFrom` emit no
+    /// `Approval`. Read in batches through the Multicall3 contract
+    /// on chains which have one. Reverted `allowance()` calls are
+    /// [`Error::NotErc20`] of their pair only.
+    #[cfg_attr(not(feature = "multicall"), allow(unused_variables))]
+    pub async fn try_get_allowances(
+        &self,
+        chain_id: u64,
+        owner: Address,
+        pairs: impl IntoIterator<Item = (Address, Address)>,
+    ) -> Result<HashMap<(Address, Address), Result<U256, Error>>, Error> {
+        let pairs: Vec<_> = pairs.into_iter().unique().collect();
+        #[cfg(feature = "multicall")]
+        if let Some(multicall) = ChainProfile::for_chain(chain_id).multicall {
+            return self
+                .try_get_allowances_with_multicall(owner, pairs, multicall)
+                .await;
+        }
+        try_join_all(pairs.into_iter().map(|(token, spender)| async move {
+            let allowance = IERC20::new(token, self.client.clone())
+                .allowance(owner, spender)
+                .call()
+                .await
+                .map_err(|err| Error::token_call(token, err));
+            match allowance {
+                Ok(_) | Err(Error::NotErc20 { .. }) => Ok(((token, spender), allowance)),
+                Err(err) => Err(err),
+            }
+        }))
+        .await
+        .map(HashMap::from_iter)
+    }
+
+    #[cfg(feature = "multicall")]
+    async fn try_get_allowances_with_multicall(
+        &self,
+        owner: Address,
+        pairs: Vec<(Address, Address)>,
+        multicall: Address,
+    ) -> Result<HashMap<(Address, Address), Result<U256, Error>>, Error> {
+        let mut allowances = HashMap::new();
+        for batch in pairs.chunks(MULTICALL_BATCH) {
+            let mut calls = Multicall::new(self.client.clone(), Some(multicall))
+                .await
+                .map_err(Error::rpc)?;
+            for &(token, spender) in batch {
+                let token = IERC20::new(token, self.client.clone());
+                calls.add_call(token.allowance(owner, spender), true);
+            }
+            let results = calls.call_raw().await.map_err(Error::rpc)?;
+            for (&(token, spender), result) in batch.iter().zip(results) {
+                // (success, output) of each call
+                let allowance = match result.into_tuple().as_deref() {
+                    Some([Token::Bool(true), Token::Uint(allowance)]) => Ok(*allowance),
+                    _ => Err(Error::NotErc20 {
+                        token,
+                        source: "allowance call reverted".into(),
+                    }),
+                };
+                allowances.insert((token, spender), allowance);
+            }
+        }
+        Ok(allowances)
+    }
 }
 
 /// Custom Approval, since Serialize and wasm_bindgen are
 /// not implemented on ApprovalFilter
-#[derive(Debug)]
-#[cfg_attr(target_arch = "wasm32", derive(Serialize))]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Approval {
-    pub owner: Address,
-    pub spender: Address,
-    pub value: U256,
+    pub(crate) owner: Address,
+    pub(crate) spender: Address,
+    pub(crate) value: U256,
+    pub(crate) owner_name: Option<Arc<str>>,
+    pub(crate) spender_name: Option<Arc<str>>,
+}
+
+impl Approval {
+    pub fn owner(&self) -> Address {
+        self.owner
+    }
+
+    pub fn spender(&self) -> Address {
+        self.spender
+    }
+
+    /// Approved amount, zero for revocations
+    pub fn value(&self) -> U256 {
+        self.value
+    }
+
+    /// Primary ENS name of the owner
+    pub fn owner_name(&self) -> Option<&str> {
+        self.owner_name.as_deref()
+    }
+
+    /// Primary ENS name of the spender
+    pub fn spender_name(&self) -> Option<&str> {
+        self.spender_name.as_deref()
+    }
 }
 
 impl From<ApprovalFilter> for Approval {
@@ -108,30 +278,73 @@ impl From<ApprovalFilter> for Approval {
             owner,
             spender,
             value,
+            owner_name: None,
+            spender_name: None,
         }
     }
 }
 
-#[derive(Debug)]
-#[cfg_attr(target_arch = "wasm32", derive(Serialize))]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TokenApproval {
-    pub token: Arc<CachedERC20>,
-    pub approval: Approval,
-    pub meta: LogMeta,
+    pub(crate) token: Arc<CachedERC20>,
+    pub(crate) approval: Approval,
+    pub(crate) spender: Arc<Spender>,
+    pub(crate) risk: Risk,
+    pub(crate) meta: LogMeta,
+    pub(crate) timestamp: DateTime<Utc>,
+    pub(crate) verification: Verification,
 }
 
 impl TokenApproval {
     pub fn new(
         token: impl Into<Arc<CachedERC20>>,
-        approval: ApprovalFilter,
+        approval: impl Into<Approval>,
+        spender: impl Into<Arc<Spender>>,
+        risk: Risk,
         meta: LogMeta,
+        timestamp: DateTime<Utc>,
     ) -> Self {
         Self {
             token: token.into(),
             approval: approval.into(),
+            spender: spender.into(),
+            risk,
             meta,
+            timestamp,
+            verification: Verification::Skipped,
         }
     }
+
+    pub fn token(&self) -> &CachedERC20 {
+        &self.token
+    }
+
+    pub fn approval(&self) -> &Approval {
+        &self.approval
+    }
+
+    pub fn spender(&self) -> &Spender {
+        &self.spender
+    }
+
+    pub fn risk(&self) -> &Risk {
+        &self.risk
+    }
+
+    /// Block, transaction and log index of the approval log
+    pub fn meta(&self) -> &LogMeta {
+        &self.meta
+    }
+
+    /// Timestamp of the block the approval was made in
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    pub fn verification(&self) -> &Verification {
+        &self.verification
+    }
 }
 
 impl Display for TokenApproval {
@@ -139,8 +352,280 @@ impl Display for TokenApproval {
         let (int_part, frac_part) = self.token.as_decimals(self.approval.value);
         write!(
             f,
-            "tx {}: approval on {} for {} on amount of {int_part}.{frac_part}",
-            self.meta.transaction_hash, self.token, self.approval.spender,
+            "tx {} at {}: approval on {} for ",
+            self.meta.transaction_hash, self.timestamp, self.token,
+        )?;
+        if let Some(name) = &self.approval.spender_name {
+            write!(f, "{name} ")?;
+        }
+        write!(
+            f,
+            "{} on amount of {int_part}.{frac_part}, {}",
+            self.spender, self.risk,
+        )?;
+        if self.verification != Verification::Skipped {
+            write!(f, ", {}", self.verification)?;
+        }
+        Ok(())
+    }
+}
+
+/// Keeps only the latest approval for each (token, spender) pair
+/// and drops the ones with no allowance left, i.e. revoked or spent
+pub fn live_approvals(approvals: impl IntoIterator<Item = TokenApproval>) -> Vec<TokenApproval> {
+    approvals
+        .into_iter()
+        .into_grouping_map_by(|a| (a.token.address(), a.spender.address()))
+        .max_by_key(|_, a| (a.meta.block_number, a.meta.log_index))
+        .into_values()
+        .filter(|a| !a.risk.allowance().is_zero())
+        .collect()
+}
+
+/// When a live approval was granted and last exercised by its spender
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Usage {
+    approved_at: DateTime<Utc>,
+    last_used: Option<DateTime<Utc>>,
+    /// timestamp of the head block at the moment of the query
+    now: DateTime<Utc>,
+}
+
+impl Usage {
+    pub fn new(
+        approved_at: DateTime<Utc>,
+        last_used: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            approved_at,
+            last_used,
+            now,
+        }
+    }
+
+    pub fn approved_at(&self) -> DateTime<Utc> {
+        self.approved_at
+    }
+
+    /// Timestamp of the latest transfer of owner tokens made by spender
+    /// since the approval
+    pub fn last_used(&self) -> Option<DateTime<Utc>> {
+        self.last_used
+    }
+
+    pub fn age(&self) -> Duration {
+        (self.now - self.approved_at).to_std().unwrap_or_default()
+    }
+
+    /// Time since the last use, or since the approval if it was never used
+    pub fn idle(&self) -> Duration {
+        (self.now - self.last_used.unwrap_or(self.approved_at))
+            .to_std()
+            .unwrap_or_default()
+    }
+
+    pub fn is_stale(&self, after: Duration) -> bool {
+        self.idle() >= after
+    }
+}
+
+impl Display for Usage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "approved {} ago, ", format_duration(self.age()))?;
+        match self.last_used {
+            Some(_) => write!(f, "last used {} ago", format_duration(self.idle())),
+            None => write!(f, "never used"),
+        }
+    }
+}
+
+/// Live approval along with its usage
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Allowance {
+    pub(crate) approval: TokenApproval,
+    pub(crate) usage: Usage,
+    pub(crate) proven: ProvenAllowance,
+}
+
+impl Allowance {
+    pub fn approval(&self) -> &TokenApproval {
+        &self.approval
+    }
+
+    pub fn usage(&self) -> &Usage {
+        &self.usage
+    }
+
+    /// Current allowance proven against the state root, if requested
+    pub fn proven(&self) -> &ProvenAllowance {
+        &self.proven
+    }
+}
+
+impl Display for Allowance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}, {}", self.approval, self.usage)?;
+        match &self.proven {
+            ProvenAllowance::Skipped => Ok(()),
+            ProvenAllowance::Proven { value, block } => {
+                let (int_part, frac_part) = self.approval.token.as_decimals(*value);
+                write!(
+                    f,
+                    ", proven allowance {int_part}.{frac_part} at block {block}"
+                )
+            }
+            unverified => write!(f, ", allowance {unverified}"),
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use ethers::{
+        abi::{encode, Token},
+        providers::{Http, Provider},
+        types::Bytes,
+    };
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing::{http_node, Node, Reply};
+
+    const TOKEN: &str = "0x00000000000000000000000000000000000000c1";
+    const SYMBOL: &str = "0x95d89b41";
+    const DECIMALS: &str = "0x313ce567";
+
+    fn call_target(params: &Value) -> (Address, String) {
+        let call = &params[0];
+        (
+            call["to"].as_str().unwrap().parse().unwrap(),
+            call["data"].as_str().unwrap().to_string(),
         )
     }
+
+    fn tokens(node: &Node) -> CachedTokens<Provider<Http>> {
+        CachedTokens::new(Provider::new(Http::new(node.url.clone())))
+    }
+
+    #[cfg(feature = "multicall")]
+    #[tokio::test]
+    async fn fetches_metadata_in_one_multicall() {
+        let multicall = ChainProfile::for_chain(1).multicall.unwrap();
+        let node = http_node(move |_, params| {
+            assert_eq!(call_target(params).0, multicall);
+            let result = |output: Token| {
+                Token::Tuple(vec![Token::Bool(true), Token::Bytes(encode(&[output]))])
+            };
+            Reply::ok(Bytes::from(encode(&[Token::Array(vec![
+                result(Token::String("TKN".into())),
+                result(Token::Uint(6.into())),
+            ])])))
+        })
+        .await;
+
+        let token = tokens(&node)
+            .try_get_token(1, TOKEN.parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!((token.symbol(), token.decimals()), ("TKN", 6));
+        assert_eq!(node.calls(), ["eth_call"]);
+    }
+
+    #[cfg(feature = "multicall")]
+    #[tokio::test]
+    async fn reverted_multicall_is_not_erc20() {
+        let node = http_node(|_, _| Reply::Error(3, "execution reverted")).await;
+        let err = tokens(&node)
+            .try_get_token(1, TOKEN.parse().unwrap())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::NotErc20 { .. }), "{err}");
+        assert_eq!(err.token(), Some(TOKEN.parse().unwrap()));
+    }
+
+    #[cfg(feature = "multicall")]
+    #[tokio::test]
+    async fn reads_allowances_in_one_multicall() {
+        let node = http_node(|_, _| {
+            let result = |allowance: u64| {
+                Token::Tuple(vec![
+                    Token::Bool(true),
+                    Token::Bytes(encode(&[Token::Uint(allowance.into())])),
+                ])
+            };
+            Reply::ok(Bytes::from(encode(&[Token::Array(vec![
+                result(5),
+                result(0),
+            ])])))
+        })
+        .await;
+
+        let token = TOKEN.parse().unwrap();
+        let pairs = [
+            (token, Address::repeat_byte(1)),
+            (token, Address::repeat_byte(2)),
+        ];
+        let allowances = tokens(&node)
+            .try_get_allowances(1, Address::zero(), pairs)
+            .await
+            .unwrap();
+        assert_eq!(allowances[&pairs[0]].as_ref().unwrap(), &5.into());
+        assert_eq!(allowances[&pairs[1]].as_ref().unwrap(), &0.into());
+        assert_eq!(node.calls(), ["eth_call"]);
+    }
+
+    #[cfg(feature = "multicall")]
+    #[tokio::test]
+    async fn reverted_allowance_is_not_erc20() {
+        let node = http_node(|_, _| {
+            let reverted = Token::Tuple(vec![Token::Bool(false), Token::Bytes(Vec::new())]);
+            let allowed = Token::Tuple(vec![
+                Token::Bool(true),
+                Token::Bytes(encode(&[Token::Uint(5.into())])),
+            ]);
+            Reply::ok(Bytes::from(encode(&[Token::Array(vec![
+                reverted, allowed,
+            ])])))
+        })
+        .await;
+
+        let pairs = [
+            (TOKEN.parse().unwrap(), Address::zero()),
+            (Address::repeat_byte(1), Address::zero()),
+        ];
+        let allowances = tokens(&node)
+            .try_get_allowances(1, Address::zero(), pairs)
+            .await
+            .unwrap();
+        let err = allowances[&pairs[0]].as_ref().unwrap_err();
+        assert!(matches!(err, Error::NotErc20 { .. }), "{err}");
+        assert_eq!(err.token(), Some(pairs[0].0));
+        assert_eq!(allowances[&pairs[1]].as_ref().unwrap(), &5.into());
+    }
+
+    #[tokio::test]
+    async fn calls_token_directly_without_multicall() {
+        let node = http_node(|_, params| {
+            let (to, data) = call_target(params);
+            assert_eq!(to, TOKEN.parse().unwrap());
+            let output = match data.as_str() {
+                SYMBOL => Token::String("TKN".into()),
+                DECIMALS => Token::Uint(18.into()),
+                data => panic!("unexpected call {data}"),
+            };
+            Reply::ok(Bytes::from(encode(&[output])))
+        })
+        .await;
+
+        // chain without multicall contract
+        let token = tokens(&node)
+            .try_get_token(1337, TOKEN.parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!((token.symbol(), token.decimals()), ("TKN", 18));
+        assert_eq!(node.count("eth_call"), 2);
+    }
 }
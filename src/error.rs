@@ -0,0 +1,342 @@
+//! Errors of the crate, independent of the provider type
+
+use std::fmt::Display;
+
+#[cfg(not(target_arch = "wasm32"))]
+use ethers::providers::IpcError;
+#[cfg(feature = "ws")]
+use ethers::providers::WsClientError;
+use ethers::{
+    contract::ContractError,
+    providers::{HttpClientError, Middleware, ProviderError},
+    types::{Address, H256},
+};
+
+type Source = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Node is unreachable, failed to answer or answered inconsistently
+    Rpc(Source),
+    /// Node refused to return logs of the block range at once,
+    /// narrower ranges should be queried instead
+    RangeLimit { block_range: String, source: Source },
+    /// Contract at the token address does not behave as an ERC20 token
+    NotErc20 { token: Address, source: Source },
+    /// Log or call result could not be decoded
+    Decode {
+        token: Option<Address>,
+        tx: Option<H256>,
+        source: Source,
+    },
+    /// Argument is invalid, e.g. an ENS name which is not registered
+    InvalidInput(String),
+    /// Index or checkpoint store failed
+    Storage(anyhow::Error),
+}
+
+impl Error {
+    pub(crate) fn rpc(err: impl Into<Source>) -> Self {
+        Self::Rpc(err.into())
+    }
+
+    /// Error of a call to the token contract: reverts and
+    /// unexpected results mean that it is not an ERC20 token
+    pub(crate) fn token_call<M: Middleware + 'static>(
+        token: Address,
+        err: ContractError<M>,
+    ) -> Self {
+        let not_erc20 = match &err {
+            ContractError::DecodingError(_) | ContractError::DetokenizationError(_) => true,
+            ContractError::MiddlewareError(err) => ErrorResponse::find(err).is_some_and(is_revert),
+            ContractError::ProviderError(err) => ErrorResponse::find(err).is_some_and(is_revert),
+            _ => false,
+        };
+        if not_erc20 {
+            Self::NotErc20 {
+                token,
+                source: err.into(),
+            }
+        } else {
+            err.into()
+        }
+    }
+
+    /// Error of a log query for the block range, telling apart
+    /// refusals to return too many blocks or logs at once
+    pub(crate) fn logs_query(err: impl Into<Source>, block_range: impl Display) -> Self {
+        let source = err.into();
+        if ErrorResponse::find(source.as_ref()).is_some_and(is_range_limit) {
+            Self::RangeLimit {
+                block_range: block_range.to_string(),
+                source,
+            }
+        } else {
+            Self::Rpc(source)
+        }
+    }
+
+    /// Token the error is about, if any
+    pub fn token(&self) -> Option<Address> {
+        match self {
+            Self::NotErc20 { token, .. } => Some(*token),
+            Self::Decode { token, .. } => *token,
+            _ => None,
+        }
+    }
+
+    /// Transaction the error is about, if any
+    pub fn tx(&self) -> Option<H256> {
+        match self {
+            Self::Decode { tx, .. } => *tx,
+            _ => None,
+        }
+    }
+}
+
+/// Error object of a JSON-RPC response
+#[derive(Debug, PartialEq, Eq)]
+struct ErrorResponse<'a> {
+    code: i64,
+    message: &'a str,
+    /// Data names the block range to query instead
+    suggests_range: bool,
+}
+
+macro_rules! error_response {
+    ($err:expr) => {
+        ErrorResponse {
+            code: $err.code,
+            message: &$err.message,
+            suggests_range: $err
+                .data
+                .as_ref()
+                .is_some_and(|data| data.get("from").is_some() && data.get("to").is_some()),
+        }
+    };
+}
+
+impl<'a> ErrorResponse<'a> {
+    /// Looks the response up through errors of providers and transports,
+    /// following the source chain of middleware errors
+    fn find(err: &'a (dyn std::error::Error + 'static)) -> Option<Self> {
+        let mut next = Some(err);
+        while let Some(err) = next {
+            if let Some(ProviderError::JsonRpcClientError(err)) = err.downcast_ref() {
+                return Self::find(err.as_ref());
+            }
+            if let Some(HttpClientError::JsonRpcError(err)) = err.downcast_ref() {
+                return Some(error_response!(err));
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            if let Some(IpcError::JsonRpcError(err)) = err.downcast_ref() {
+                return Some(error_response!(err));
+            }
+            #[cfg(feature = "ws")]
+            if let Some(WsClientError::JsonRpcError(err)) = err.downcast_ref() {
+                return Some(error_response!(err));
+            }
+            next = err.source();
+        }
+        None
+    }
+}
+
+/// Execution reverted: code 3 comes with revert data (Geth, Erigon, Reth),
+/// -32015 is the VM execution error (Nethermind, OpenEthereum), and Geth
+/// answers reverts without data by the generic server error
+fn is_revert(response: ErrorResponse) -> bool {
+    match response.code {
+        3 | -32015 => true,
+        -32000 => response.message == "execution reverted",
+        _ => false,
+    }
+}
+
+/// Refusal to return too many blocks or logs at once: limit exceeded along
+/// with the range to query instead (Infura, otherwise it is the request rate),
+/// invalid params (Alchemy, Reth) and the range limit of QuickNode
+fn is_range_limit(response: ErrorResponse) -> bool {
+    match response.code {
+        -32005 => response.suggests_range,
+        -32602 | -32614 => true,
+        _ => false,
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Rpc(err) => write!(f, "node request failed: {err}"),
+            Self::RangeLimit {
+                block_range,
+                source,
+            } => {
+                write!(f, "node refused logs of blocks {block_range}: {source}")
+            }
+            Self::NotErc20 { token, source } => {
+                write!(f, "{token:#x} is not an ERC20 token: {source}")
+            }
+            Self::Decode { token, tx, source } => {
+                f.write_str("can not decode")?;
+                if let Some(token) = token {
+                    write!(f, " log of {token:#x}")?;
+                }
+                if let Some(tx) = tx {
+                    write!(f, " in tx {tx:#x}")?;
+                }
+                write!(f, ": {source}")
+            }
+            Self::InvalidInput(err) => f.write_str(err),
+            Self::Storage(err) => write!(f, "storage failed: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Rpc(source)
+            | Self::RangeLimit { source, .. }
+            | Self::NotErc20 { source, .. }
+            | Self::Decode { source, .. } => Some(source.as_ref()),
+            Self::InvalidInput(_) => None,
+            Self::Storage(err) => Some(err.as_ref()),
+        }
+    }
+}
+
+impl From<ethers::abi::Error> for Error {
+    fn from(err: ethers::abi::Error) -> Self {
+        Self::Decode {
+            token: None,
+            tx: None,
+            source: err.into(),
+        }
+    }
+}
+
+impl<M: Middleware + 'static> From<ContractError<M>> for Error {
+    fn from(err: ContractError<M>) -> Self {
+        match err {
+            ContractError::MiddlewareError(err) => Self::rpc(err),
+            ContractError::ProviderError(err) => Self::rpc(err),
+            ContractError::DecodingError(_)
+            | ContractError::AbiError(_)
+            | ContractError::DetokenizationError(_) => Self::Decode {
+                token: None,
+                tx: None,
+                source: err.into(),
+            },
+            err => Self::rpc(err),
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use ethers::providers::{Http, Provider};
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    /// Error of an HTTP provider answered with the JSON-RPC error
+    fn rpc_error(error: Value) -> ProviderError {
+        let err = HttpClientError::JsonRpcError(serde_json::from_value(error).unwrap());
+        ProviderError::JsonRpcClientError(Box::new(err))
+    }
+
+    fn token_call(error: Value) -> Error {
+        let err = ContractError::<Provider<Http>>::MiddlewareError(rpc_error(error));
+        Error::token_call(Address::zero(), err)
+    }
+
+    fn logs_query(error: Value) -> Error {
+        Error::logs_query(rpc_error(error), "1..=2")
+    }
+
+    #[test]
+    fn classifies_reverts() {
+        for error in [
+            json!({ "code": 3, "message": "execution reverted: ERC20", "data": "0x08c379a0" }),
+            json!({ "code": -32015, "message": "VM execution error." }),
+            json!({ "code": -32000, "message": "execution reverted" }),
+        ] {
+            let err = token_call(error.clone());
+            assert!(matches!(err, Error::NotErc20 { .. }), "{error}");
+            assert_eq!(err.token(), Some(Address::zero()));
+        }
+        for error in [
+            json!({ "code": -32000, "message": "header not found" }),
+            // the message alone does not tell a revert
+            json!({ "code": -32603, "message": "internal error: reverted by the proxy" }),
+            json!({ "code": -32005, "message": "limit exceeded" }),
+        ] {
+            assert!(
+                matches!(token_call(error.clone()), Error::Rpc(_)),
+                "{error}"
+            );
+        }
+    }
+
+    #[test]
+    fn classifies_undecodable_results_as_not_erc20() {
+        let err = ContractError::<Provider<Http>>::DecodingError(ethers::abi::Error::InvalidData);
+        assert!(matches!(
+            Error::token_call(Address::zero(), err),
+            Error::NotErc20 { .. }
+        ));
+    }
+
+    #[test]
+    fn classifies_range_limits() {
+        for error in [
+            json!({
+                "code": -32005,
+                "message": "query returned more than 10000 results",
+                "data": { "from": "0x1", "to": "0x2", "limit": 10000 },
+            }),
+            json!({ "code": -32602, "message": "Log response size exceeded." }),
+            json!({ "code": -32614, "message": "eth_getLogs is limited to a 10,000 range" }),
+        ] {
+            let err = logs_query(error.clone());
+            assert!(
+                matches!(&err, Error::RangeLimit { block_range, .. } if block_range == "1..=2"),
+                "{error}"
+            );
+        }
+        for error in [
+            // rate limited
+            json!({ "code": -32005, "message": "daily request count exceeded" }),
+            json!({ "code": -32000, "message": "too many blocks requested, try again later" }),
+            json!({ "code": 3, "message": "execution reverted" }),
+        ] {
+            assert!(
+                matches!(logs_query(error.clone()), Error::Rpc(_)),
+                "{error}"
+            );
+        }
+    }
+
+    #[test]
+    fn finds_responses_through_sources() {
+        let limit = json!({ "code": -32602, "message": "invalid params" });
+        // error of a middleware wrapping the provider
+        let err = Error::rpc(rpc_error(limit.clone()));
+        assert!(matches!(
+            Error::logs_query(err, "1..=2"),
+            Error::RangeLimit { .. }
+        ));
+
+        let ipc = IpcError::JsonRpcError(serde_json::from_value(limit).unwrap());
+        let err = ProviderError::JsonRpcClientError(Box::new(ipc));
+        assert!(matches!(
+            Error::logs_query(err, "1..=2"),
+            Error::RangeLimit { .. }
+        ));
+
+        let err = ProviderError::CustomError("invalid params".into());
+        assert!(matches!(Error::logs_query(err, "1..=2"), Error::Rpc(_)));
+    }
+}
@@ -0,0 +1,313 @@
+use std::{
+    fmt::Debug,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use ethers::providers::{HttpClientError, JsonRpcClient};
+use instant::Instant;
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::warn;
+use url::Url;
+
+use crate::{Auth, AuthHttp, Error, Metered, REQUEST_TIMEOUT};
+
+/// Endpoint is skipped for this long after the first failure,
+/// doubling with each consecutive failure up to `MAX_COOLDOWN`
+const BASE_COOLDOWN: Duration = Duration::from_secs(1);
+const MAX_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// Weight of the latest latency sample in the moving average
+const LATENCY_WEIGHT: f64 = 0.2;
+
+/// How to pick an endpoint for the next request
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Spread requests evenly across endpoints
+    #[default]
+    RoundRobin,
+    /// Prefer endpoints with the lowest average latency
+    Latency,
+}
+
+impl FromStr for Strategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round-robin" => Ok(Self::RoundRobin),
+            "latency" => Ok(Self::Latency),
+            _ => Err(anyhow!("expected one of: round-robin, latency")),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    consecutive_failures: u32,
+    /// moving average of successful requests latency
+    latency: Option<Duration>,
+    cooldown_until: Option<Instant>,
+}
+
+impl Health {
+    fn is_cooling_down(&self, now: Instant) -> bool {
+        self.cooldown_until.is_some_and(|until| now < until)
+    }
+
+    fn succeeded(&mut self, latency: Duration) {
+        self.consecutive_failures = 0;
+        self.cooldown_until = None;
+        self.latency = Some(match self.latency {
+            Some(avg) => avg.mul_f64(1.0 - LATENCY_WEIGHT) + latency.mul_f64(LATENCY_WEIGHT),
+            None => latency,
+        });
+    }
+
+    fn failed(&mut self) {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        let cooldown = BASE_COOLDOWN
+            .saturating_mul(1 << (self.consecutive_failures - 1).min(16))
+            .min(MAX_COOLDOWN);
+        self.cooldown_until = Some(Instant::now() + cooldown);
+    }
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    client: Metered<AuthHttp>,
+    health: Mutex<Health>,
+}
+
+/// HTTP transport over several nodes with health tracking.
+///
+/// Each request is sent to endpoints in the order defined by [`Strategy`],
+/// failed endpoints are moved to the back of the queue for a cooldown period.
+/// A request fails over to the next endpoint on transport errors, while
+/// JSON-RPC error responses are returned as is, since they are caused
+/// by the request itself.
+#[derive(Debug)]
+pub struct Failover {
+    endpoints: Vec<Endpoint>,
+    strategy: Strategy,
+    next: AtomicUsize,
+}
+
+impl Failover {
+    /// Fails with [`Error::InvalidInput`] if no nodes are given
+    pub fn new(nodes: impl IntoIterator<Item = Url>, strategy: Strategy) -> Result<Self, Error> {
+        Self::new_with_auth(nodes, strategy, Auth::default())
+    }
+
+    /// Same as [`Failover::new`], authenticating to all nodes the same way
+    pub fn new_with_auth(
+        nodes: impl IntoIterator<Item = Url>,
+        strategy: Strategy,
+        auth: Auth,
+    ) -> Result<Self, Error> {
+        Self::new_with_timeout(nodes, strategy, auth, REQUEST_TIMEOUT)
+    }
+
+    /// Same as [`Failover::new_with_auth`], failing over from nodes
+    /// which do not answer within the timeout. See [`AuthHttp::new_with_timeout`].
+    pub fn new_with_timeout(
+        nodes: impl IntoIterator<Item = Url>,
+        strategy: Strategy,
+        auth: Auth,
+        timeout: Duration,
+    ) -> Result<Self, Error> {
+        let endpoints: Vec<_> = nodes
+            .into_iter()
+            .map(|node| Endpoint {
+                client: Metered::new(AuthHttp::new_with_timeout(node, auth.clone(), timeout)),
+                health: Default::default(),
+            })
+            .collect();
+        if endpoints.is_empty() {
+            return Err(Error::InvalidInput(
+                "at least one node is required".to_string(),
+            ));
+        }
+        Ok(Self {
+            endpoints,
+            strategy,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Indices of endpoints in order they should be tried
+    fn order(&self) -> Vec<usize> {
+        let n = self.endpoints.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let health: Vec<_> = self
+            .endpoints
+            .iter()
+            .map(|endpoint| {
+                let health = endpoint.health.lock().unwrap();
+                (health.is_cooling_down(now), health.latency)
+            })
+            .collect();
+
+        let mut order: Vec<_> = (0..n).map(|i| (start + i) % n).collect();
+        if self.strategy == Strategy::Latency {
+            // endpoints without measurements go first to get one
+            order.sort_by_key(|&i| health[i].1);
+        }
+        // sort is stable, so the order within healthy ones is preserved
+        order.sort_by_key(|&i| health[i].0);
+        order
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl JsonRpcClient for Failover {
+    type Error = HttpClientError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned,
+    {
+        let mut last_err = None;
+        for i in self.order() {
+            let endpoint = &self.endpoints[i];
+            let started = Instant::now();
+            match endpoint.client.request(method, &params).await {
+                Ok(res) => {
+                    endpoint.health.lock().unwrap().succeeded(started.elapsed());
+                    return Ok(res);
+                }
+                Err(err @ HttpClientError::JsonRpcError(_)) => {
+                    endpoint.health.lock().unwrap().succeeded(started.elapsed());
+                    return Err(err);
+                }
+                Err(err) => {
+                    warn!(node = %endpoint.client.inner().url(), method, error = %err, "node failed");
+                    endpoint.health.lock().unwrap().failed();
+                    last_err = Some(err);
+                }
+            }
+        }
+        Err(last_err.expect("at least one endpoint"))
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use ethers::types::U64;
+
+    use super::*;
+    use crate::testing::{http_node, Node, Reply};
+
+    fn failover(nodes: &[&Node], strategy: Strategy) -> Failover {
+        Failover::new(nodes.iter().map(|node| node.url.clone()), strategy).unwrap()
+    }
+
+    async fn block_number(failover: &Failover) -> Result<U64, HttpClientError> {
+        failover.request("eth_blockNumber", ()).await
+    }
+
+    #[tokio::test]
+    async fn fails_over_to_next_node_on_http_error() {
+        let down = http_node(|_, _| Reply::Status(503)).await;
+        let up = http_node(|_, _| Reply::ok("0x10")).await;
+        let failover = failover(&[&down, &up], Strategy::RoundRobin);
+
+        for _ in 0..3 {
+            assert_eq!(block_number(&failover).await.unwrap(), 16.into());
+        }
+        // the failed node is cooling down, so it is tried last
+        assert_eq!(down.count("eth_blockNumber"), 1);
+        assert_eq!(up.count("eth_blockNumber"), 3);
+    }
+
+    #[tokio::test]
+    async fn fails_over_from_node_timing_out() {
+        let slow =
+            http_node(|_, _| Reply::Delayed(Duration::from_secs(5), Box::new(Reply::ok("0x1"))))
+                .await;
+        let fast = http_node(|_, _| Reply::ok("0x2")).await;
+        let failover = Failover::new_with_timeout(
+            [slow.url.clone(), fast.url.clone()],
+            Strategy::RoundRobin,
+            Auth::default(),
+            Duration::from_millis(200),
+        )
+        .unwrap();
+
+        let started = Instant::now();
+        assert_eq!(block_number(&failover).await.unwrap(), 2.into());
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(slow.count("eth_blockNumber"), 1);
+    }
+
+    #[tokio::test]
+    async fn fails_with_last_error_when_all_nodes_fail() {
+        let first = http_node(|_, _| Reply::Status(502)).await;
+        let second = http_node(|_, _| Reply::Status(429)).await;
+        let failover = failover(&[&first, &second], Strategy::RoundRobin);
+
+        // ethers reads the body of any status, which is not JSON here
+        let err = block_number(&failover).await.unwrap_err();
+        assert!(matches!(err, HttpClientError::SerdeJson { .. }));
+        assert_eq!(first.count("eth_blockNumber"), 1);
+        assert_eq!(second.count("eth_blockNumber"), 1);
+    }
+
+    #[tokio::test]
+    async fn returns_json_rpc_errors_without_failing_over() {
+        let first = http_node(|_, _| Reply::Error(3, "execution reverted")).await;
+        let second = http_node(|_, _| Reply::ok("0x1")).await;
+        let failover = failover(&[&first, &second], Strategy::RoundRobin);
+
+        let err = block_number(&failover).await.unwrap_err();
+        assert!(matches!(err, HttpClientError::JsonRpcError(err) if err.code == 3));
+        assert!(second.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn prefers_faster_nodes_by_latency() {
+        let slow = http_node(|_, _| {
+            Reply::Delayed(Duration::from_millis(100), Box::new(Reply::ok("0x1")))
+        })
+        .await;
+        let fast = http_node(|_, _| Reply::ok("0x2")).await;
+        let failover = failover(&[&slow, &fast], Strategy::Latency);
+
+        for _ in 0..5 {
+            block_number(&failover).await.unwrap();
+        }
+        // each node is measured once, then the faster one is preferred
+        assert_eq!(slow.count("eth_blockNumber"), 1);
+        assert_eq!(fast.count("eth_blockNumber"), 4);
+    }
+
+    #[test]
+    fn cooldown_doubles_up_to_max() {
+        let mut health = Health::default();
+        let cooldown = |health: &Health| health.cooldown_until.unwrap() - Instant::now();
+        health.failed();
+        assert!(cooldown(&health) <= BASE_COOLDOWN);
+        health.failed();
+        assert!(cooldown(&health) > BASE_COOLDOWN);
+        for _ in 0..20 {
+            health.failed();
+        }
+        assert!(cooldown(&health) <= MAX_COOLDOWN);
+        assert!(cooldown(&health) > MAX_COOLDOWN / 2);
+
+        health.succeeded(Duration::from_millis(100));
+        assert!(!health.is_cooling_down(Instant::now()));
+        assert_eq!(health.consecutive_failures, 0);
+        health.succeeded(Duration::from_millis(200));
+        assert_eq!(health.latency, Some(Duration::from_millis(120)));
+    }
+}
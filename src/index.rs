@@ -0,0 +1,586 @@
+//! Chain-wide index of approval logs, so queries are answered
+//! without scanning logs on the node.
+//!
+//! Records are stored in an SQLite database as blocks are synced, indexed
+//! by owner, spender and token. Every query reads the database, so an
+//! index open for queries follows a sync running in another process.
+
+use std::{collections::HashMap, fs, path::PathBuf, sync::Mutex};
+
+use ethers::{
+    abi::RawLog,
+    contract::{EthEvent, LogMeta},
+    types::{Address, Log, H256, U256, U64},
+};
+use rusqlite::{params, Connection, OptionalExtension, Row, Transaction};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::abi::{ierc20::ApprovalFilter, ierc721::ApprovalForAllFilter};
+
+const DB_FILE: &str = "approvals.sqlite";
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS head (
+        id INTEGER PRIMARY KEY CHECK (id = 0),
+        start INTEGER NOT NULL,
+        last_block INTEGER,
+        last_hash BLOB
+    );
+    CREATE TABLE IF NOT EXISTS approvals (
+        block_number INTEGER NOT NULL,
+        log_index INTEGER NOT NULL,
+        block_hash BLOB NOT NULL,
+        transaction_hash BLOB NOT NULL,
+        transaction_index INTEGER NOT NULL,
+        token BLOB NOT NULL,
+        owner BLOB NOT NULL,
+        spender BLOB NOT NULL,
+        -- ERC20 allowance, or NULL for operator approvals
+        amount BLOB,
+        approved_all INTEGER,
+        PRIMARY KEY (block_number, log_index)
+    );
+    CREATE INDEX IF NOT EXISTS approvals_by_owner ON approvals (owner);
+    CREATE INDEX IF NOT EXISTS approvals_by_spender ON approvals (spender);
+    CREATE INDEX IF NOT EXISTS approvals_by_token ON approvals (token);
+    CREATE TABLE IF NOT EXISTS undecodable (
+        block_number INTEGER NOT NULL,
+        log_index INTEGER NOT NULL,
+        token BLOB NOT NULL,
+        transaction_hash BLOB NOT NULL,
+        PRIMARY KEY (block_number, log_index)
+    );
+";
+
+const COLUMNS: &str = "block_number, log_index, block_hash, transaction_hash, \
+    transaction_index, token, owner, spender, amount, approved_all";
+
+/// What the spender was approved for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Approved {
+    /// ERC20 allowance
+    Amount(U256),
+    /// ERC721 and ERC1155 operator approval of all owner tokens
+    All(bool),
+}
+
+/// Approval log as stored in the index
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+pub struct IndexedApproval {
+    pub owner: Address,
+    /// Spender or operator
+    pub spender: Address,
+    pub approved: Approved,
+    pub meta: LogMeta,
+}
+
+impl IndexedApproval {
+    pub fn token(&self) -> Address {
+        self.meta.address
+    }
+
+    /// Decodes ERC20 `Approval` and `ApprovalForAll` logs. ERC721
+    /// approvals of single tokens share the topic of ERC20 ones,
+    /// but have indexed token id instead of the value and are skipped.
+    /// Fails on logs with the topics of approvals which can not be decoded.
+    pub(crate) fn decode(log: &Log) -> Result<Option<Self>, ethers::abi::Error> {
+        let raw = RawLog {
+            topics: log.topics.clone(),
+            data: log.data.to_vec(),
+        };
+        let Some(&topic) = log.topics.first() else {
+            return Ok(None);
+        };
+        let (owner, spender, approved) = if topic == ApprovalFilter::signature() {
+            if log.topics.len() == 4 {
+                return Ok(None);
+            }
+            let approval = <ApprovalFilter as EthEvent>::decode_log(&raw)?;
+            (
+                approval.owner,
+                approval.spender,
+                Approved::Amount(approval.value),
+            )
+        } else if topic == ApprovalForAllFilter::signature() {
+            let approval = <ApprovalForAllFilter as EthEvent>::decode_log(&raw)?;
+            (
+                approval.owner,
+                approval.operator,
+                Approved::All(approval.approved),
+            )
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            owner,
+            spender,
+            approved,
+            meta: log.into(),
+        }))
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let amount: Option<Vec<u8>> = row.get("amount")?;
+        Ok(Self {
+            owner: Address::from_slice(&row.get::<_, Vec<u8>>("owner")?),
+            spender: Address::from_slice(&row.get::<_, Vec<u8>>("spender")?),
+            approved: match amount {
+                Some(amount) => Approved::Amount(U256::from_big_endian(&amount)),
+                None => Approved::All(row.get("approved_all")?),
+            },
+            meta: LogMeta {
+                address: Address::from_slice(&row.get::<_, Vec<u8>>("token")?),
+                block_number: row.get::<_, u64>("block_number")?.into(),
+                block_hash: H256::from_slice(&row.get::<_, Vec<u8>>("block_hash")?),
+                transaction_hash: H256::from_slice(&row.get::<_, Vec<u8>>("transaction_hash")?),
+                transaction_index: row.get::<_, u64>("transaction_index")?.into(),
+                log_index: row.get::<_, u64>("log_index")?.into(),
+            },
+        })
+    }
+
+    fn insert(&self, tx: &Transaction) -> rusqlite::Result<()> {
+        let (amount, approved_all) = match self.approved {
+            Approved::Amount(amount) => {
+                let mut bytes = [0; 32];
+                amount.to_big_endian(&mut bytes);
+                (Some(bytes), None)
+            }
+            Approved::All(approved) => (None, Some(approved)),
+        };
+        tx.prepare_cached(&format!(
+            "INSERT OR REPLACE INTO approvals ({COLUMNS})
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)"
+        ))?
+        .execute(params![
+            self.meta.block_number.as_u64(),
+            self.meta.log_index.as_u64(),
+            self.meta.block_hash.as_bytes(),
+            self.meta.transaction_hash.as_bytes(),
+            self.meta.transaction_index.as_u64(),
+            self.meta.address.as_bytes(),
+            self.owner.as_bytes(),
+            self.spender.as_bytes(),
+            amount,
+            approved_all,
+        ])?;
+        Ok(())
+    }
+}
+
+/// Approval logs of all owners, either in memory or persisted
+/// in a directory. See [`App::sync_index`](crate::App::sync_index).
+#[derive(Debug)]
+pub struct Index {
+    db: Mutex<Connection>,
+}
+
+impl Index {
+    pub fn in_memory() -> anyhow::Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    /// Opens the index in the directory, creating it if needed.
+    /// Several processes may open the same index, e.g. one syncing it
+    /// and another answering queries.
+    pub fn open(dir: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let db = Connection::open(dir.join(DB_FILE))?;
+        // readers do not block the writer and see its committed blocks
+        db.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+        Self::init(db)
+    }
+
+    fn init(db: Connection) -> anyhow::Result<Self> {
+        db.execute_batch(SCHEMA)?;
+        db.execute("INSERT OR IGNORE INTO head (id, start) VALUES (0, 0)", [])?;
+        Ok(Self { db: Mutex::new(db) })
+    }
+
+    pub fn start(&self) -> anyhow::Result<U64> {
+        let db = self.db.lock().unwrap();
+        let start: u64 = db.query_row("SELECT start FROM head", [], |row| row.get(0))?;
+        Ok(start.into())
+    }
+
+    /// Last fully indexed block and its hash
+    pub fn last_block(&self) -> anyhow::Result<Option<(U64, H256)>> {
+        let db = self.db.lock().unwrap();
+        let last_block: Option<(u64, Vec<u8>)> = db
+            .query_row(
+                "SELECT last_block, last_hash FROM head WHERE last_block IS NOT NULL",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        Ok(last_block.map(|(block, hash)| (block.into(), H256::from_slice(&hash))))
+    }
+
+    /// Number of indexed approval logs
+    pub fn len(&self) -> anyhow::Result<usize> {
+        self.count("approvals")
+    }
+
+    pub fn is_empty(&self) -> anyhow::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Number of logs with approval topics which could not be decoded
+    /// and are left out of the index
+    pub fn undecodable_logs(&self) -> anyhow::Result<usize> {
+        self.count("undecodable")
+    }
+
+    fn count(&self, table: &str) -> anyhow::Result<usize> {
+        let db = self.db.lock().unwrap();
+        let count: u64 = db.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+            row.get(0)
+        })?;
+        Ok(count as usize)
+    }
+
+    /// Records with the address in the column, in order of blocks and log indices
+    fn select(&self, column: &str, address: Address) -> anyhow::Result<Vec<IndexedApproval>> {
+        let db = self.db.lock().unwrap();
+        let mut query = db.prepare_cached(&format!(
+            "SELECT {COLUMNS} FROM approvals WHERE {column} = ?1
+             ORDER BY block_number, log_index"
+        ))?;
+        let approvals = query
+            .query_map([address.as_bytes()], IndexedApproval::from_row)?
+            .collect::<Result<_, _>>()?;
+        Ok(approvals)
+    }
+
+    pub fn by_owner(&self, owner: Address) -> anyhow::Result<Vec<IndexedApproval>> {
+        self.select("owner", owner)
+    }
+
+    pub fn by_spender(&self, spender: Address) -> anyhow::Result<Vec<IndexedApproval>> {
+        self.select("spender", spender)
+    }
+
+    /// The latest approval for each (owner, token) pair of the spender,
+    /// which is not revoked
+    pub fn live_by_spender(&self, spender: Address) -> anyhow::Result<Vec<IndexedApproval>> {
+        let mut latest = HashMap::new();
+        for approval in self.by_spender(spender)? {
+            // records are in order, so later ones override
+            latest.insert((approval.owner, approval.token()), approval);
+        }
+        Ok(latest
+            .into_values()
+            .filter(|approval| match approval.approved {
+                Approved::Amount(value) => !value.is_zero(),
+                Approved::All(approved) => approved,
+            })
+            .collect())
+    }
+
+    pub fn by_token(&self, token: Address) -> anyhow::Result<Vec<IndexedApproval>> {
+        self.select("token", token)
+    }
+
+    /// Sets the first block to index, only while nothing is indexed
+    pub(crate) fn set_start(&self, start: U64) -> anyhow::Result<()> {
+        let db = self.db.lock().unwrap();
+        db.execute(
+            "UPDATE head SET start = ?1 WHERE last_block IS NULL",
+            [start.as_u64()],
+        )?;
+        Ok(())
+    }
+
+    /// Stores logs of blocks up to the given one, which are then marked
+    /// as indexed, along with logs which could not be decoded
+    pub(crate) fn append(
+        &self,
+        approvals: Vec<IndexedApproval>,
+        undecodable: Vec<LogMeta>,
+        block: U64,
+        hash: H256,
+    ) -> anyhow::Result<()> {
+        let mut db = self.db.lock().unwrap();
+        let tx = db.transaction()?;
+        for approval in &approvals {
+            approval.insert(&tx)?;
+        }
+        for meta in &undecodable {
+            tx.prepare_cached(
+                "INSERT OR REPLACE INTO undecodable
+                 (block_number, log_index, token, transaction_hash) VALUES (?1, ?2, ?3, ?4)",
+            )?
+            .execute(params![
+                meta.block_number.as_u64(),
+                meta.log_index.as_u64(),
+                meta.address.as_bytes(),
+                meta.transaction_hash.as_bytes(),
+            ])?;
+        }
+        tx.execute(
+            "UPDATE head SET last_block = ?1, last_hash = ?2",
+            params![block.as_u64(), hash.as_bytes()],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Drops records after the block, e.g. when they were reorged out
+    pub(crate) fn rewind(&self, block: U64, hash: H256) -> anyhow::Result<()> {
+        let mut db = self.db.lock().unwrap();
+        let tx = db.transaction()?;
+        for table in ["approvals", "undecodable"] {
+            tx.execute(
+                &format!("DELETE FROM {table} WHERE block_number > ?1"),
+                [block.as_u64()],
+            )?;
+        }
+        tx.execute(
+            "UPDATE head SET last_block = ?1, last_hash = ?2",
+            params![block.as_u64(), hash.as_bytes()],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{Address, U256};
+
+    use super::*;
+    use crate::{
+        testing::{DevChain, Node},
+        App, BlockRef,
+    };
+
+    fn address(n: u64) -> Address {
+        Address::from_low_u64_be(n)
+    }
+
+    fn approval(
+        block: u64,
+        log_index: u64,
+        owner: u64,
+        spender: u64,
+        value: u64,
+    ) -> IndexedApproval {
+        IndexedApproval {
+            owner: address(owner),
+            spender: address(spender),
+            approved: Approved::Amount(value.into()),
+            meta: LogMeta {
+                address: address(0xc1),
+                block_number: block.into(),
+                block_hash: H256::from_low_u64_be(block),
+                transaction_hash: H256::from_low_u64_be(block << 8 | log_index),
+                transaction_index: 0.into(),
+                log_index: log_index.into(),
+            },
+        }
+    }
+
+    fn blocks(approvals: &[IndexedApproval]) -> Vec<u64> {
+        approvals
+            .iter()
+            .map(|approval| approval.meta.block_number.as_u64())
+            .collect()
+    }
+
+    #[test]
+    fn open_keeps_records_and_head() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let index = Index::open(dir.path()).unwrap();
+            assert_eq!(index.last_block().unwrap(), None);
+            index.set_start(10.into()).unwrap();
+            index
+                .append(
+                    vec![approval(11, 0, 1, 2, 5), approval(12, 3, 1, 3, 0)],
+                    vec![approval(12, 4, 1, 3, 0).meta],
+                    20.into(),
+                    H256::repeat_byte(1),
+                )
+                .unwrap();
+            // the start is fixed once blocks are indexed
+            index.set_start(0.into()).unwrap();
+        }
+
+        let index = Index::open(dir.path()).unwrap();
+        assert_eq!(index.start().unwrap(), 10.into());
+        assert_eq!(
+            index.last_block().unwrap(),
+            Some((20.into(), H256::repeat_byte(1)))
+        );
+        assert_eq!(index.len().unwrap(), 2);
+        assert_eq!(index.undecodable_logs().unwrap(), 1);
+        let by_owner = index.by_owner(address(1)).unwrap();
+        assert_eq!(blocks(&by_owner), [11, 12]);
+        assert_eq!(by_owner[0].approved, Approved::Amount(5.into()));
+        assert_eq!(by_owner[0].meta, approval(11, 0, 1, 2, 5).meta);
+        assert_eq!(blocks(&index.by_spender(address(3)).unwrap()), [12]);
+        assert_eq!(index.by_token(address(0xc1)).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn queries_follow_another_index_of_the_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let writer = Index::open(dir.path()).unwrap();
+        let reader = Index::open(dir.path()).unwrap();
+        writer
+            .append(
+                vec![approval(1, 0, 1, 2, 5)],
+                vec![],
+                1.into(),
+                H256::zero(),
+            )
+            .unwrap();
+        assert_eq!(reader.last_block().unwrap().unwrap().0, 1.into());
+        assert_eq!(reader.by_owner(address(1)).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn rewind_drops_later_blocks() {
+        let index = Index::in_memory().unwrap();
+        index
+            .append(
+                vec![
+                    approval(1, 0, 1, 2, 5),
+                    approval(2, 0, 1, 2, 6),
+                    approval(3, 0, 1, 2, 7),
+                ],
+                vec![approval(3, 1, 1, 2, 0).meta],
+                3.into(),
+                H256::repeat_byte(3),
+            )
+            .unwrap();
+        index.rewind(1.into(), H256::repeat_byte(1)).unwrap();
+        assert_eq!(blocks(&index.by_owner(address(1)).unwrap()), [1]);
+        assert_eq!(index.undecodable_logs().unwrap(), 0);
+        assert_eq!(
+            index.last_block().unwrap(),
+            Some((1.into(), H256::repeat_byte(1)))
+        );
+    }
+
+    #[test]
+    fn live_by_spender_keeps_latest_not_revoked() {
+        let index = Index::in_memory().unwrap();
+        let mut revoked_operator = approval(4, 0, 4, 2, 0);
+        revoked_operator.approved = Approved::All(false);
+        let mut operator = approval(3, 0, 4, 2, 0);
+        operator.approved = Approved::All(true);
+        index
+            .append(
+                vec![
+                    // revoked later
+                    approval(1, 0, 1, 2, 5),
+                    approval(2, 0, 1, 2, 0),
+                    // increased later
+                    approval(1, 1, 3, 2, 5),
+                    approval(2, 1, 3, 2, 9),
+                    operator,
+                    revoked_operator,
+                    // of another spender
+                    approval(3, 1, 5, 6, 1),
+                ],
+                vec![],
+                4.into(),
+                H256::zero(),
+            )
+            .unwrap();
+        let live = index.live_by_spender(address(2)).unwrap();
+        assert_eq!(live.len(), 1);
+        assert_eq!(live[0].owner, address(3));
+        assert_eq!(live[0].approved, Approved::Amount(9.into()));
+    }
+
+    async fn sync(node: &Node, index: &Index) -> Option<U64> {
+        App::new(node.url.clone())
+            .sync_index(index, Some(BlockRef::Block(1.into())))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn sync_indexes_dev_chain() {
+        let chain = DevChain::new(1337, 10);
+        let (token, nft) = (address(0xc1), address(0xd1));
+        chain.approve(2, token, address(1), address(2), U256::MAX);
+        chain.approve_for_all(3, nft, address(1), address(2), true);
+        // ERC721 approval of a single token
+        chain.log(
+            4,
+            nft,
+            vec![
+                ApprovalFilter::signature(),
+                H256::from(address(1)),
+                H256::from(address(2)),
+                H256::from_low_u64_be(7),
+            ],
+            vec![],
+        );
+        // ERC20 approval without value
+        chain.log(
+            5,
+            token,
+            vec![
+                ApprovalFilter::signature(),
+                H256::from(address(1)),
+                H256::from(address(2)),
+            ],
+            vec![],
+        );
+        let node = chain.start().await;
+
+        let index = Index::in_memory().unwrap();
+        assert_eq!(sync(&node, &index).await, Some(10.into()));
+        assert_eq!(index.start().unwrap(), 1.into());
+        assert_eq!(index.len().unwrap(), 2);
+        assert_eq!(index.undecodable_logs().unwrap(), 1);
+        let approvals = index.by_owner(address(1)).unwrap();
+        assert_eq!(approvals[0].approved, Approved::Amount(U256::MAX));
+        assert_eq!(approvals[1].approved, Approved::All(true));
+        assert_eq!(approvals[1].token(), nft);
+
+        // only new blocks are queried
+        chain.approve(12, token, address(3), address(2), 1.into());
+        chain.mine(5);
+        let queried = node.count("eth_getLogs");
+        assert_eq!(sync(&node, &index).await, Some(15.into()));
+        assert_eq!(node.count("eth_getLogs"), queried + 1);
+        assert_eq!(index.live_by_spender(address(2)).unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn sync_reindexes_reorged_blocks() {
+        let chain = DevChain::new(1337, 10);
+        let token = address(0xc1);
+        chain.approve(2, token, address(1), address(2), 5.into());
+        chain.approve(9, token, address(1), address(3), 5.into());
+        let node = chain.start().await;
+        let index = Index::in_memory().unwrap();
+        sync(&node, &index).await;
+
+        chain.reorg(8);
+        chain.approve(8, token, address(1), address(4), 5.into());
+        chain.mine(1);
+        assert_eq!(sync(&node, &index).await, Some(11.into()));
+        let spenders: Vec<_> = index
+            .by_owner(address(1))
+            .unwrap()
+            .iter()
+            .map(|approval| approval.spender)
+            .collect();
+        assert_eq!(spenders, [address(2), address(4)]);
+        assert_eq!(
+            index.last_block().unwrap(),
+            Some((11.into(), chain.block_hash(11)))
+        );
+    }
+}
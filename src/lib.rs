@@ -1,114 +1,1773 @@
+// Bindings are regenerated by build.rs on every build, so the lint can not
+// be silenced in them: abigen of ethers 1.0.2 elides the lifetime of
+// `Event<'_, M, D>` in event getters, which rustc 1.89+ warns about.
+// ERC721 bindings are only used by the indexer, which is built on request.
+#[allow(mismatched_lifetime_syntaxes)]
+#[cfg_attr(
+    any(not(feature = "index"), target_arch = "wasm32"),
+    allow(unused_imports)
+)]
 pub(crate) mod abi;
+mod auth;
+mod blocks;
+mod builder;
 mod cached;
+mod chain;
+mod checkpoint;
+mod ens;
 mod erc20;
+mod error;
+mod failover;
+#[cfg(all(feature = "index", not(target_arch = "wasm32")))]
+mod index;
+mod metered;
+mod metrics;
+mod proof;
+mod quorum;
+mod report;
+mod risk;
+#[cfg(all(feature = "server", not(target_arch = "wasm32")))]
+mod server;
+mod spender;
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod testing;
+mod throttle;
+mod time;
+mod trie;
+mod verify;
+mod watch;
+#[cfg(all(feature = "serde", not(target_arch = "wasm32")))]
+mod webhook;
 
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    pin::pin,
+    sync::Arc,
+    time::Duration,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use {
+    ethers::providers::{Ipc, ProviderError},
+    std::path::Path,
+};
+
+#[cfg(all(feature = "index", not(target_arch = "wasm32")))]
+use self::abi::ierc721::ApprovalForAllFilter;
+
+#[cfg(feature = "ws")]
+use ethers::providers::{Ws, WsClientError};
 
 use ethers::{
-    contract::{ContractError, EthEvent, LogMeta},
-    providers::{Http, JsonRpcClient, Provider},
-    types::{Address, Filter, FilterBlockOption, H256},
+    abi::RawLog,
+    contract::{EthEvent, LogMeta},
+    providers::{Http, Middleware, Provider},
+    types::{Address, BlockNumber, Filter, FilterBlockOption, NameOrAddress, H256, U256, U64},
 };
 use futures::{
-    stream::{FuturesUnordered, TryStreamExt},
-    TryFutureExt,
+    future::{join_all, try_join, try_join3, try_join_all},
+    lock::Mutex,
+    stream::{self, FuturesUnordered, StreamExt, TryStreamExt},
 };
+use instant::Instant;
+use itertools::Itertools;
+use tracing::{debug, debug_span, info, instrument, warn, Instrument, Span};
 use url::Url;
 
 use self::{
-    abi::ierc20::ApprovalFilter,
-    erc20::{CachedTokens, TokenApproval},
+    abi::ierc20::{ApprovalFilter, TransferFilter, IERC20},
+    blocks::{Bound, CachedBlocks},
+    cached::CachedMap,
+    ens::CachedNames,
+    erc20::CachedTokens,
+    metrics::{observe_range_split, observe_scan},
+    proof::Prover,
+    quorum::Quorum,
+    spender::CachedSpenders,
+    verify::Verifier,
 };
 
-pub struct App<P: JsonRpcClient> {
-    client: Arc<Provider<P>>,
-    tokens: CachedTokens<Provider<P>>,
+pub use self::{
+    auth::{Auth, AuthHttp, JwtSecret, REQUEST_TIMEOUT},
+    blocks::BlockRef,
+    builder::AppBuilder,
+    chain::ChainProfile,
+    checkpoint::{Checkpoint, CheckpointStore},
+    ens::parse_name_or_address,
+    erc20::{live_approvals, Allowance, Approval, CachedERC20, TokenApproval, Usage},
+    error::Error,
+    failover::{Failover, Strategy},
+    metered::Metered,
+    proof::ProvenAllowance,
+    quorum::{CheckedApprovals, Discrepancy},
+    report::{ChainApproval, ChainTotals, CrossChain, CrossChainReport, Exposure},
+    risk::{Risk, RiskFactor, RiskLevel, RiskScore},
+    spender::{Fingerprints, Spender, SpenderKind},
+    throttle::{compute_units, RateLimit, RetryPolicy, RetryableError, Throttle},
+    time::{format_duration, parse_duration},
+    verify::Verification,
+    watch::{ApprovalEvent, Trigger, Watcher},
+};
+
+#[cfg(all(feature = "index", not(target_arch = "wasm32")))]
+pub use self::index::{Approved, Index, IndexedApproval};
+
+#[cfg(feature = "metrics")]
+pub use self::metrics::{gather_metrics, METRICS_CONTENT_TYPE};
+
+#[cfg(all(feature = "serde", not(target_arch = "wasm32")))]
+pub use self::checkpoint::FileStore;
+
+#[cfg(all(feature = "server", not(target_arch = "wasm32")))]
+pub use self::server::{serve, serve_metrics};
+
+#[cfg(all(feature = "serde", not(target_arch = "wasm32")))]
+pub use self::webhook::{sign_payload, DeadLetter, Webhooks, SIGNATURE_HEADER};
+
+/// Max number of concurrent `eth_getLogs` requests
+/// when the range is split into chunks
+const LOG_QUERY_CONCURRENCY: usize = 4;
+
+/// Blocks per `eth_getLogs` request of incremental scans
+/// on chains without max log range
+const SCAN_CHUNK: u64 = 100_000;
+
+/// Min time between checkpoints saved during a scan
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Max blocks per `eth_getLogs` request when indexing logs of all owners
+#[cfg(all(feature = "index", not(target_arch = "wasm32")))]
+const INDEX_CHUNK: u64 = 1_000;
+
+/// How many blocks are indexed again when the last indexed block is reorged,
+/// how long hashes of watched blocks are kept to detect reorgs, and how deep
+/// scan checkpoints can be rewound without starting over
+const REORG_DEPTH: u64 = 64;
+
+/// Block range of the filter for logs, `from..=to`
+fn block_range(block_option: &FilterBlockOption) -> String {
+    match block_option {
+        FilterBlockOption::Range {
+            from_block,
+            to_block,
+        } => {
+            let bound = |block: &Option<BlockNumber>| {
+                block.map_or_else(String::new, |block| match block {
+                    BlockNumber::Number(number) => number.to_string(),
+                    tag => format!("{tag:?}").to_lowercase(),
+                })
+            };
+            format!("{}..={}", bound(from_block), bound(to_block))
+        }
+        FilterBlockOption::AtBlockHash(hash) => format!("{hash:#x}"),
+    }
 }
 
-impl App<Http> {
+pub struct App<M: Middleware> {
+    client: Arc<M>,
+    /// detected on first use
+    chain: Mutex<Option<Arc<ChainProfile>>>,
+    tokens: CachedTokens<M>,
+    spenders: CachedSpenders<M>,
+    blocks: CachedBlocks<M>,
+    names: CachedNames<M>,
+    /// cross-checks approval logs if set
+    quorum: Option<Quorum<M>>,
+    /// verifies approval logs against block headers if set
+    verifier: Option<Verifier<M>>,
+    /// proves current allowances by storage proofs if set
+    prover: Option<Prover<M>>,
+    #[cfg(all(feature = "index", not(target_arch = "wasm32")))]
+    index: Option<Arc<Index>>,
+}
+
+impl App<Provider<Metered<Http>>> {
     pub fn new(node: impl Into<Url>) -> Self {
-        let client = Arc::new(Provider::new(Http::new(node)));
+        Self::builder(Provider::new(Metered::new(Http::new(node)))).build()
+    }
+}
+
+impl App<Provider<Metered<AuthHttp>>> {
+    /// Sends credentials in headers instead of the node url. See [`Auth`].
+    pub fn new_with_auth(node: impl Into<Url>, auth: Auth) -> Self {
+        Self::builder(Provider::new(Metered::new(AuthHttp::new(node, auth)))).build()
+    }
+}
+
+impl App<Provider<Failover>> {
+    /// Spreads requests across several nodes, failing over to the
+    /// next one when a node is unavailable. See [`Failover`].
+    /// Fails if no nodes are given.
+    pub fn new_with_failover(
+        nodes: impl IntoIterator<Item = Url>,
+        strategy: Strategy,
+    ) -> Result<Self, Error> {
+        Ok(Self::builder(Provider::new(Failover::new(nodes, strategy)?)).build())
+    }
+}
+
+impl App<Provider<Throttle<Failover>>> {
+    /// Same as [`App::new_with_failover`], with failed requests retried
+    /// by the policy and all requests kept under the rate limit.
+    /// See [`Throttle`]. Fails if no nodes are given.
+    pub fn new_with_throttle(
+        nodes: impl IntoIterator<Item = Url>,
+        strategy: Strategy,
+        policy: RetryPolicy,
+        limit: RateLimit,
+    ) -> Result<Self, Error> {
+        Ok(Self::builder(Provider::new(Throttle::new(
+            Failover::new(nodes, strategy)?,
+            policy,
+            limit,
+        )))
+        .build())
+    }
+}
+
+#[cfg(feature = "ws")]
+impl App<Provider<Metered<Ws>>> {
+    /// Connects to the node over WebSocket, keeping a single
+    /// connection for all requests
+    pub async fn new_ws(node: Url) -> Result<Self, WsClientError> {
+        let ws = Ws::connect(node.as_str()).await?;
+        Ok(Self::builder(Provider::new(Metered::new(ws))).build())
+    }
+}
+
+#[cfg(feature = "ws")]
+impl App<Provider<Throttle<Metered<Ws>>>> {
+    /// Same as [`App::new_ws`], with failed requests retried
+    /// by the policy and all requests kept under the rate limit
+    pub async fn new_ws_with_throttle(
+        node: Url,
+        policy: RetryPolicy,
+        limit: RateLimit,
+    ) -> Result<Self, WsClientError> {
+        let ws = Metered::new(Ws::connect(node.as_str()).await?);
+        Ok(Self::builder(Provider::new(Throttle::new(ws, policy, limit))).build())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl App<Provider<Metered<Ipc>>> {
+    /// Connects to a local node over its IPC socket, which avoids
+    /// HTTP overhead on large historical scans
+    pub async fn new_ipc(path: impl AsRef<Path>) -> Result<Self, ProviderError> {
+        let ipc = Ipc::connect(path).await?;
+        Ok(Self::builder(Provider::new(Metered::new(ipc))).build())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl App<Provider<Throttle<Metered<Ipc>>>> {
+    /// Same as [`App::new_ipc`], with failed requests retried
+    /// by the policy and all requests kept under the rate limit
+    pub async fn new_ipc_with_throttle(
+        path: impl AsRef<Path>,
+        policy: RetryPolicy,
+        limit: RateLimit,
+    ) -> Result<Self, ProviderError> {
+        let ipc = Metered::new(Ipc::connect(path).await?);
+        Ok(Self::builder(Provider::new(Throttle::new(ipc, policy, limit))).build())
+    }
+}
+
+impl<M: Middleware + 'static> App<M> {
+    /// Builds an app on top of any middleware stack,
+    /// e.g. `Provider` wrapped in custom layers
+    pub fn builder(client: impl Into<Arc<M>>) -> AppBuilder<M> {
+        AppBuilder::new(client)
+    }
+
+    /// Matches spender bytecode against the fingerprints from now on,
+    /// keeping the rest of the configuration and caches. Spenders
+    /// looked up before are looked up again.
+    pub fn with_fingerprints(self, fingerprints: Arc<Fingerprints>) -> Self {
         Self {
-            tokens: CachedTokens::new(client.clone()),
-            client,
+            spenders: CachedSpenders::new(self.client.clone(), fingerprints),
+            ..self
         }
     }
 }
 
-impl<P: JsonRpcClient + 'static> App<P> {
+impl<M: Middleware + 'static> App<M> {
+    /// Resolves block range given by block numbers, tags, times or durations
+    /// before the latest block. Range defaults to `earliest..=latest`.
+    pub async fn resolve_range(
+        &self,
+        from: Option<BlockRef>,
+        to: Option<BlockRef>,
+    ) -> Result<FilterBlockOption, Error> {
+        let (from_block, to_block) = try_join(
+            self.blocks.try_resolve(
+                from.unwrap_or(BlockRef::Block(BlockNumber::Earliest)),
+                Bound::Start,
+            ),
+            self.blocks.try_resolve(
+                to.unwrap_or(BlockRef::Block(BlockNumber::Latest)),
+                Bound::End,
+            ),
+        )
+        .await?;
+        Ok(FilterBlockOption::Range {
+            from_block: Some(from_block),
+            to_block: Some(to_block),
+        })
+    }
+
+    /// Resolves block reference to a block number, times to the first
+    /// block at or after them
+    pub async fn resolve_block(&self, block: BlockRef) -> Result<U64, Error> {
+        let block = self.blocks.try_resolve(block, Bound::Start).await?;
+        self.blocks.try_get_number(block).await
+    }
+
+    /// Resolves ENS name to address, addresses are returned as is
+    pub async fn resolve_name(&self, name: &NameOrAddress) -> Result<Address, Error> {
+        self.names.try_resolve(name).await
+    }
+
+    /// Primary ENS name of the address, if set
+    pub async fn lookup_name(&self, address: Address) -> Result<Option<Arc<str>>, Error> {
+        self.names.try_get_name(address).await
+    }
+
+    /// Profile of the chain the node is connected to
+    pub async fn chain(&self) -> Result<Arc<ChainProfile>, Error> {
+        let mut chain = self.chain.lock().await;
+        if let Some(chain) = &*chain {
+            return Ok(chain.clone());
+        }
+        let chain_id = self.client.get_chainid().await.map_err(Error::rpc)?;
+        Ok(chain
+            .insert(Arc::new(ChainProfile::for_chain(chain_id.as_u64())))
+            .clone())
+    }
+
+    async fn query_logs<D: EthEvent>(&self, filter: Filter) -> Result<Vec<(D, LogMeta)>, Error> {
+        self.query_logs_on(&self.client, filter).await
+    }
+
+    /// Queries logs from given provider, splitting the block range
+    /// into chunks not exceeding max log range of the chain
+    #[instrument(name = "query_logs", level = "debug", skip_all, fields(event = %D::name(), chunks))]
+    async fn query_logs_on<D: EthEvent>(
+        &self,
+        client: &M,
+        filter: Filter,
+    ) -> Result<Vec<(D, LogMeta)>, Error> {
+        let ranges = match (self.chain().await?.max_log_range, filter.block_option) {
+            (
+                Some(max_log_range),
+                FilterBlockOption::Range {
+                    from_block,
+                    to_block,
+                },
+            ) => {
+                let (from, to) = try_join(
+                    self.blocks
+                        .try_get_number(from_block.unwrap_or(BlockNumber::Earliest)),
+                    self.blocks
+                        .try_get_number(to_block.unwrap_or(BlockNumber::Latest)),
+                )
+                .await?;
+                (from.as_u64()..=to.as_u64())
+                    .step_by(max_log_range as usize)
+                    .map(|start| FilterBlockOption::Range {
+                        from_block: Some(start.into()),
+                        to_block: Some(U64::from(start + max_log_range - 1).min(to).into()),
+                    })
+                    .collect()
+            }
+            (_, block_option) => vec![block_option],
+        };
+        if ranges.len() > 1 {
+            observe_range_split();
+        }
+        Span::current().record("chunks", ranges.len());
+
+        stream::iter(ranges)
+            .map(|range| {
+                let range_text = block_range(&range);
+                let span = debug_span!("chunk", block_range = %range_text);
+                let filter = filter.clone().select(range).event(&D::abi_signature());
+                async move {
+                    let started = Instant::now();
+                    let logs = match client.get_logs(&filter).await {
+                        Ok(logs) => logs,
+                        Err(err) => {
+                            debug!(error = %err, latency = ?started.elapsed(), "failed to get logs");
+                            return Err(Error::logs_query(err, range_text));
+                        }
+                    };
+                    debug!(logs = logs.len(), latency = ?started.elapsed(), "got logs");
+                    logs.into_iter()
+                        .map(|log| {
+                            let meta = LogMeta::from(&log);
+                            let raw = RawLog {
+                                topics: log.topics,
+                                data: log.data.to_vec(),
+                            };
+                            let event = <D as EthEvent>::decode_log(&raw).map_err(|err| Error::Decode {
+                                    token: Some(meta.address),
+                                    tx: Some(meta.transaction_hash),
+                                    source: err.into(),
+                                })?;
+                            Ok((event, meta))
+                        })
+                        .collect::<Result<Vec<_>, _>>()
+                }
+                .instrument(span)
+            })
+            .buffered(LOG_QUERY_CONCURRENCY)
+            .try_concat()
+            .await
+    }
+
     async fn get_approvals_from(
         &self,
         owner: Address,
         block_filter: FilterBlockOption,
-    ) -> Result<Vec<(ApprovalFilter, LogMeta)>, ContractError<Provider<P>>> {
-        ApprovalFilter::new(
-            Filter::new().select(block_filter).topic1(H256::from(owner)),
-            &*self.client,
+    ) -> Result<(Vec<(ApprovalFilter, LogMeta)>, Vec<Discrepancy>), Error> {
+        #[cfg(all(feature = "index", not(target_arch = "wasm32")))]
+        if let (Some(index), None) = (&self.index, &self.quorum) {
+            if let Some(approvals) = self
+                .get_indexed_approvals(index, owner, block_filter)
+                .await?
+            {
+                return Ok((approvals, Vec::new()));
+            }
+        }
+        let Some(quorum) = &self.quorum else {
+            return Ok((
+                self.query_logs(Filter::new().select(block_filter).topic1(H256::from(owner)))
+                    .await?,
+                Vec::new(),
+            ));
+        };
+
+        // providers may be at different heights, so tags are resolved once
+        let block_filter = match block_filter {
+            FilterBlockOption::Range {
+                from_block,
+                to_block,
+            } => {
+                let (from, to) = try_join(
+                    self.blocks
+                        .try_get_number(from_block.unwrap_or(BlockNumber::Earliest)),
+                    self.blocks
+                        .try_get_number(to_block.unwrap_or(BlockNumber::Latest)),
+                )
+                .await?;
+                FilterBlockOption::Range {
+                    from_block: Some(from.into()),
+                    to_block: Some(to.into()),
+                }
+            }
+            block_filter => block_filter,
+        };
+        let filter = Filter::new().select(block_filter).topic1(H256::from(owner));
+
+        let mut reported = Vec::new();
+        let mut last_err = None;
+        for res in join_all(
+            quorum
+                .providers()
+                .iter()
+                .map(|client| self.query_logs_on(client, filter.clone())),
         )
-        .query_with_meta()
         .await
+        {
+            match res {
+                Ok(logs) => reported.push(logs),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        match last_err {
+            // quorum can not be reached anyway
+            Some(err) if reported.len() < quorum.threshold() => Err(err),
+            _ => Ok(quorum.agree(reported)),
+        }
+    }
+
+    /// Approvals from the index, with blocks after the last indexed one
+    /// queried from the node. None if the range starts before the index.
+    #[cfg(all(feature = "index", not(target_arch = "wasm32")))]
+    async fn get_indexed_approvals(
+        &self,
+        index: &Index,
+        owner: Address,
+        block_filter: FilterBlockOption,
+    ) -> Result<Option<Vec<(ApprovalFilter, LogMeta)>>, Error> {
+        let (
+            Some((last_block, _)),
+            FilterBlockOption::Range {
+                from_block,
+                to_block,
+            },
+        ) = (index.last_block().map_err(Error::Storage)?, block_filter)
+        else {
+            return Ok(None);
+        };
+        let (from, to) = try_join(
+            self.blocks
+                .try_get_number(from_block.unwrap_or(BlockNumber::Earliest)),
+            self.blocks
+                .try_get_number(to_block.unwrap_or(BlockNumber::Latest)),
+        )
+        .await?;
+        if from < index.start().map_err(Error::Storage)? {
+            return Ok(None);
+        }
+
+        let mut approvals: Vec<_> = index
+            .by_owner(owner)
+            .map_err(Error::Storage)?
+            .into_iter()
+            .filter(|approval| (from..=to).contains(&approval.meta.block_number))
+            .filter_map(|approval| match approval.approved {
+                Approved::Amount(value) => Some((
+                    ApprovalFilter {
+                        owner,
+                        spender: approval.spender,
+                        value,
+                    },
+                    approval.meta,
+                )),
+                Approved::All(_) => None,
+            })
+            .collect();
+        if to > last_block {
+            approvals.extend(
+                self.query_logs(
+                    Filter::new()
+                        .from_block(from.max(last_block + 1))
+                        .to_block(to)
+                        .topic1(H256::from(owner)),
+                )
+                .await?,
+            );
+        }
+        Ok(Some(approvals))
+    }
+
+    /// Indexes approval logs of all owners up to the head block, starting
+    /// from the last indexed block or `from` if the index is empty.
+    /// Blocks are indexed in chunks as they are fetched, so an interrupted
+    /// sync continues where it left off. If the last indexed block
+    /// was reorged out, the last blocks are indexed again.
+    /// Returns the last indexed block, if any.
+    #[cfg(all(feature = "index", not(target_arch = "wasm32")))]
+    #[instrument(skip_all)]
+    pub async fn sync_index(
+        &self,
+        index: &Index,
+        from: Option<BlockRef>,
+    ) -> Result<Option<U64>, Error> {
+        let started = Instant::now();
+        let index_error = Error::Storage;
+        let head = self.blocks.try_get_number(BlockNumber::Latest).await?;
+        let next = match index.last_block().map_err(index_error)? {
+            None => {
+                let from = self
+                    .blocks
+                    .try_resolve(
+                        from.unwrap_or(BlockRef::Block(BlockNumber::Earliest)),
+                        Bound::Start,
+                    )
+                    .await?;
+                let from = self.blocks.try_get_number(from).await?;
+                index.set_start(from).map_err(index_error)?;
+                from
+            }
+            Some((block, hash)) if self.try_get_block_hash(block).await? == Some(hash) => block + 1,
+            Some((last_block, _)) => {
+                let block = last_block
+                    .saturating_sub(REORG_DEPTH.into())
+                    .max(index.start().map_err(index_error)?);
+                warn!(%last_block, rewind_to = %block, "last indexed block was reorged out");
+                let hash = self
+                    .try_get_block_hash(block)
+                    .await?
+                    .ok_or_else(|| Error::rpc(format!("block {block} not found")))?;
+                index.rewind(block, hash).map_err(index_error)?;
+                block + 1
+            }
+        };
+
+        let chunk = self
+            .chain()
+            .await?
+            .max_log_range
+            .map_or(INDEX_CHUNK, |range| range.min(INDEX_CHUNK));
+        let mut chunks = pin!(stream::iter(
+            (next.as_u64()..=head.as_u64()).step_by(chunk as usize)
+        )
+        .map(|start| {
+            let end = U64::from(start + chunk - 1).min(head);
+            let range = format!("{start}..={end}");
+            let span = debug_span!("chunk", block_range = %range);
+            let filter = Filter::new().from_block(start).to_block(end).topic0(vec![
+                ApprovalFilter::signature(),
+                ApprovalForAllFilter::signature(),
+            ]);
+            async move {
+                let started = Instant::now();
+                let logs = self
+                    .client
+                    .get_logs(&filter)
+                    .await
+                    .map_err(|err| Error::logs_query(err, &range))?;
+                let hash = self
+                    .try_get_block_hash(end)
+                    .await?
+                    .ok_or_else(|| Error::rpc(format!("block {end} not found")))?;
+                debug!(logs = logs.len(), latency = ?started.elapsed(), "got logs");
+                Ok::<_, Error>((end, hash, logs))
+            }
+            .instrument(span)
+        })
+        .buffered(LOG_QUERY_CONCURRENCY));
+        let (mut indexed, mut skipped) = (0, 0);
+        while let Some((end, hash, logs)) = chunks.try_next().await? {
+            let (mut approvals, mut undecodable) = (Vec::new(), Vec::new());
+            for log in &logs {
+                match IndexedApproval::decode(log) {
+                    Ok(approval) => approvals.extend(approval),
+                    Err(err) => {
+                        warn!(
+                            token = ?log.address,
+                            tx = ?log.transaction_hash,
+                            error = %err,
+                            "undecodable approval log"
+                        );
+                        undecodable.push(LogMeta::from(log));
+                    }
+                }
+            }
+            indexed += approvals.len();
+            skipped += undecodable.len();
+            index
+                .append(approvals, undecodable, end, hash)
+                .map_err(index_error)?;
+        }
+        observe_scan("index", started.elapsed(), indexed);
+        let last_block = index.last_block().map_err(index_error)?;
+        info!(
+            approvals = indexed,
+            undecodable = skipped,
+            last_block = ?last_block.map(|(block, _)| block),
+            latency = ?started.elapsed(),
+            "synced index"
+        );
+        Ok(last_block.map(|(block, _)| block))
     }
 
+    /// All approvals from the owner in the given range. In quorum mode
+    /// approvals providers did not agree on are left out, use
+    /// [`App::get_checked_token_approvals`] to get them as discrepancies.
     pub async fn get_token_approvals(
         &self,
         owner: Address,
         block_filter: FilterBlockOption,
-    ) -> Result<Vec<TokenApproval>, ContractError<Provider<P>>> {
-        self.get_approvals_from(owner, block_filter)
+    ) -> Result<Vec<TokenApproval>, Error> {
+        Ok(self
+            .get_checked_token_approvals(owner, block_filter)
             .await?
+            .approvals)
+    }
+
+    /// Same as [`App::get_token_approvals`], along with logs
+    /// which did not reach the quorum, if it is set
+    #[instrument(skip_all, fields(owner = ?owner, block_range = %block_range(&block_filter)))]
+    pub async fn get_checked_token_approvals(
+        &self,
+        owner: Address,
+        block_filter: FilterBlockOption,
+    ) -> Result<CheckedApprovals, Error> {
+        let started = Instant::now();
+        let (approvals, discrepancies) = self.get_approvals_from(owner, block_filter).await?;
+        let approvals = self.approvals_from_logs(owner, approvals).await?;
+        observe_scan("approvals", started.elapsed(), approvals.len());
+        info!(
+            approvals = approvals.len(),
+            discrepancies = discrepancies.len(),
+            latency = ?started.elapsed(),
+            "got approvals"
+        );
+        Ok(CheckedApprovals {
+            approvals,
+            discrepancies,
+        })
+    }
+
+    /// Latest approval for each (token, spender) pair from the owner which
+    /// is not revoked, scanning logs since `from` or resuming the previous
+    /// scan from its checkpoint in the store.
+    ///
+    /// The checkpoint of the owner on the chain is saved as the scan goes,
+    /// so interrupted scans continue where they left off, and later scans
+    /// from any block between its first and next block (e.g. `--since 30d`)
+    /// resume it. When saved blocks are reorged out, the scan rewinds to
+    /// the newest one still on the chain, and starts over if there is none.
+    /// Quorum is not checked.
+    #[instrument(skip_all, fields(owner = ?owner))]
+    pub async fn scan_approvals(
+        &self,
+        owner: Address,
+        from: Option<BlockRef>,
+        store: &impl CheckpointStore,
+    ) -> Result<Vec<TokenApproval>, Error> {
+        let started = Instant::now();
+        let chain = self.chain().await?;
+        let store_error = Error::Storage;
+        let from = self
+            .blocks
+            .try_resolve(
+                from.unwrap_or(BlockRef::Block(BlockNumber::Earliest)),
+                Bound::Start,
+            )
+            .await?;
+        let (from, head) = try_join(
+            self.blocks.try_get_number(from),
+            self.blocks.try_get_number(BlockNumber::Latest),
+        )
+        .await?;
+
+        let mut checkpoint = match store.load(chain.chain_id, owner).map_err(store_error)? {
+            Some(checkpoint) if (checkpoint.from()..=checkpoint.next_block()).contains(&from) => {
+                checkpoint
+            }
+            _ => Checkpoint::new(from),
+        };
+        if let Some((last_block, _)) = checkpoint.last_block() {
+            let mut ancestor = None;
+            for (block, hash) in checkpoint.saved_blocks() {
+                if self.try_get_block_hash(block).await? == Some(hash) {
+                    ancestor = Some(block);
+                    break;
+                }
+            }
+            match ancestor {
+                Some(block) if block == last_block => {}
+                Some(block) => {
+                    debug!(%last_block, ancestor = %block, "rewinding reorged checkpoint");
+                    checkpoint.rewind(block);
+                }
+                None => {
+                    debug!(%last_block, "checkpoint reorged deeper than saved blocks");
+                    checkpoint = Checkpoint::new(from);
+                }
+            }
+        }
+        debug!(next_block = %checkpoint.next_block(), %head, "resuming scan");
+
+        let chunk = chain.max_log_range.unwrap_or(SCAN_CHUNK);
+        let mut chunks = pin!(stream::iter(
+            (checkpoint.next_block().as_u64()..=head.as_u64()).step_by(chunk as usize)
+        )
+        .map(|start| {
+            let end = U64::from(start + chunk - 1).min(head);
+            let filter = Filter::new()
+                .from_block(start)
+                .to_block(end)
+                .topic1(H256::from(owner));
+            async move { Ok::<_, Error>((end, self.query_logs::<ApprovalFilter>(filter).await?)) }
+        })
+        .buffered(LOG_QUERY_CONCURRENCY));
+        let mut saved = Instant::now();
+        while let Some((end, logs)) = chunks.try_next().await? {
+            checkpoint.fold(logs);
+            if end == head || saved.elapsed() >= CHECKPOINT_INTERVAL {
+                // a block deep enough to rewind to after a reorg of the head
+                let anchor = head.saturating_sub(REORG_DEPTH.into());
+                if end == head && anchor >= checkpoint.next_block() && anchor < end {
+                    if let Some(hash) = self.try_get_block_hash(anchor).await? {
+                        checkpoint.advance(anchor, hash);
+                    }
+                }
+                let hash = self
+                    .try_get_block_hash(end)
+                    .await?
+                    .ok_or_else(|| Error::rpc(format!("block {end} not found")))?;
+                checkpoint.advance(end, hash);
+                debug!(last_block = %end, pairs = checkpoint.len(), "saving checkpoint");
+                store
+                    .save(chain.chain_id, owner, &checkpoint)
+                    .map_err(store_error)?;
+                saved = Instant::now();
+            }
+        }
+
+        let approvals = self
+            .approvals_from_logs(owner, checkpoint.live_logs(owner, from))
+            .await?;
+        observe_scan("incremental", started.elapsed(), approvals.len());
+        info!(approvals = approvals.len(), latency = ?started.elapsed(), "scanned approvals");
+        Ok(approvals)
+    }
+
+    /// Approvals of the owners from the block up to the head block
+    /// which are new, increase the allowance or have at least `min_risk`,
+    /// in order of logs. Returns them along with the head block, so
+    /// the next call continues after it. Allowances before the range
+    /// are fetched at the block preceding it.
+    #[instrument(skip_all, fields(owners = owners.len(), from = %from))]
+    pub async fn get_approval_events(
+        &self,
+        owners: &[Address],
+        from: U64,
+        min_risk: RiskScore,
+    ) -> Result<(Vec<ApprovalEvent>, U64), Error> {
+        let started = Instant::now();
+        let (chain, head) = try_join(
+            self.chain(),
+            self.blocks.try_get_number(BlockNumber::Latest),
+        )
+        .await?;
+        if owners.is_empty() || from > head {
+            return Ok((Vec::new(), head));
+        }
+        let mut logs = self
+            .query_logs::<ApprovalFilter>(
+                Filter::new()
+                    .from_block(from)
+                    .to_block(head)
+                    .topic1(owners.iter().map(|&owner| H256::from(owner)).collect_vec()),
+            )
+            .await?;
+        logs.sort_by_key(|(_, meta)| (meta.block_number, meta.log_index));
+
+        let mut allowances: HashMap<_, _> = try_join_all(
+            logs.iter()
+                .map(|(approval, meta)| (meta.address, approval.owner, approval.spender))
+                .unique()
+                .map(|(token, owner, spender)| async move {
+                    let allowance = match from.checked_sub(1.into()) {
+                        Some(before) => IERC20::new(token, self.client.clone())
+                            .allowance(owner, spender)
+                            .block(before)
+                            .call()
+                            .await
+                            .map_err(|err| Error::token_call(token, err))?,
+                        None => U256::zero(),
+                    };
+                    Ok::<_, Error>(((token, owner, spender), allowance))
+                }),
+        )
+        .await?
+        .into_iter()
+        .collect();
+        let mut previous = HashMap::new();
+        for (approval, meta) in &logs {
+            let allowance = allowances
+                .insert(
+                    (meta.address, approval.owner, approval.spender),
+                    approval.value,
+                )
+                .unwrap_or_default();
+            previous.insert((meta.transaction_hash, meta.log_index), allowance);
+        }
+
+        let by_owner = logs
+            .into_iter()
+            .filter(|(approval, _)| !approval.value.is_zero())
+            .into_group_map_by(|(approval, _)| approval.owner);
+        let mut events: Vec<_> = try_join_all(
+            by_owner
+                .into_iter()
+                .map(|(owner, logs)| self.approvals_from_logs(owner, logs)),
+        )
+        .await?
+        .into_iter()
+        .flatten()
+        .filter_map(|approval| {
+            let previous = previous[&(approval.meta.transaction_hash, approval.meta.log_index)];
+            let triggers = Trigger::of(previous, &approval, min_risk);
+            (!triggers.is_empty()).then_some(ApprovalEvent {
+                chain_id: chain.chain_id,
+                triggers,
+                previous,
+                approval,
+                removed: false,
+            })
+        })
+        .collect();
+        events.sort_by_key(|event| {
+            (
+                event.approval.meta.block_number,
+                event.approval.meta.log_index,
+            )
+        });
+        observe_scan("events", started.elapsed(), events.len());
+        info!(events = events.len(), %head, latency = ?started.elapsed(), "got approval events");
+        Ok((events, head))
+    }
+
+    async fn try_get_block_hash(&self, block: U64) -> Result<Option<H256>, Error> {
+        Ok(self
+            .client
+            .get_block(block)
+            .await
+            .map_err(Error::rpc)?
+            .and_then(|block| block.hash))
+    }
+
+    /// Resolves tokens, spenders and risks of approval logs. Only the latest
+    /// approval for each (token, spender) pair is scored by its live allowance,
+    /// the ones it superseded score 0.
+    #[instrument(level = "debug", skip_all, fields(owner = ?owner, logs = approvals.len()))]
+    async fn approvals_from_logs(
+        &self,
+        owner: Address,
+        approvals: Vec<(ApprovalFilter, LogMeta)>,
+    ) -> Result<Vec<TokenApproval>, Error> {
+        if approvals.is_empty() {
+            return Ok(Vec::new());
+        }
+        let head = self.client.get_block_number().await.map_err(Error::rpc)?;
+        let chain_id = self.chain().await?.chain_id;
+        let balances: CachedMap<Address, U256> = CachedMap::new("balances");
+        let latest: HashSet<_> = approvals
+            .iter()
+            .into_grouping_map_by(|(approval, meta)| (meta.address, approval.spender))
+            .max_by_key(|_, (_, meta)| (meta.block_number, meta.log_index))
+            .into_values()
+            .map(|(_, meta)| (meta.transaction_hash, meta.log_index))
+            .collect();
+        let is_live = |approval: &ApprovalFilter, meta: &LogMeta| {
+            !approval.value.is_zero() && latest.contains(&(meta.transaction_hash, meta.log_index))
+        };
+
+        let (allowances, timestamps) = try_join(
+            self.tokens.try_get_allowances(
+                chain_id,
+                owner,
+                approvals
+                    .iter()
+                    .filter(|(approval, meta)| is_live(approval, meta))
+                    .map(|(approval, meta)| (meta.address, approval.spender)),
+            ),
+            self.blocks.try_get_timestamps(
+                approvals
+                    .iter()
+                    .map(|(_, meta)| meta.block_number)
+                    .chain([head]),
+            ),
+        )
+        .await?;
+        let now = timestamps[&head];
+        // spenders with code at a block from before the recent window are not
+        // recent, so their deployments are only searched after the newest such
+        // block among the ones already fetched, and only resolved if there is none
+        let recent_cutoff = now - chrono::Duration::from_std(risk::RECENT_DEPLOYMENT).unwrap();
+        let recent_since = match timestamps
+            .iter()
+            .filter(|(_, timestamp)| **timestamp <= recent_cutoff)
+            .map(|(block, _)| *block)
+            .max()
+        {
+            Some(block) => block,
+            None => {
+                let since = self
+                    .blocks
+                    .try_resolve(BlockRef::Ago(risk::RECENT_DEPLOYMENT), Bound::Start)
+                    .await?;
+                self.blocks.try_get_number(since).await?
+            }
+        };
+
+        approvals
             .into_iter()
             .map(|(approval, meta)| {
-                self.tokens
-                    .try_get_token(meta.address)
-                    .map_ok(move |token| TokenApproval::new(token, approval, meta))
+                let balances = &balances;
+                let allowance = if is_live(&approval, &meta) {
+                    match &allowances[&(meta.address, approval.spender)] {
+                        Ok(allowance) => *allowance,
+                        // allowance is unknown, so the approval is assumed unspent
+                        Err(err) => {
+                            warn!(%err, spender = ?approval.spender, "scoring by approved value");
+                            approval.value
+                        }
+                    }
+                } else {
+                    U256::zero()
+                };
+                let timestamp = timestamps[&meta.block_number];
+                async move {
+                    let ((token, spender, balance), (owner_name, spender_name), verification) =
+                        try_join3(
+                            try_join3(
+                                self.tokens.try_get_token(chain_id, meta.address),
+                                self.spenders
+                                    .try_get_spender(approval.spender, recent_since),
+                                balances.get_or_try_insert_with(meta.address, || async move {
+                                    IERC20::new(meta.address, self.client.clone())
+                                        .balance_of(owner)
+                                        .call()
+                                        .await
+                                        .map_err(|err| Error::token_call(meta.address, err))
+                                }),
+                            ),
+                            try_join(
+                                self.names.try_get_name(approval.owner),
+                                self.names.try_get_name(approval.spender),
+                            ),
+                            async {
+                                match &self.verifier {
+                                    Some(verifier) => verifier.try_verify(&approval, &meta).await,
+                                    None => Ok(Verification::Skipped),
+                                }
+                            },
+                        )
+                        .await?;
+                    let deployed_at = match spender.deployed_at() {
+                        Some(block) => Some(self.blocks.try_get_timestamp(block).await?),
+                        None => None,
+                    };
+                    let risk =
+                        Risk::assess(allowance, &spender, deployed_at, balance, timestamp, now);
+                    let approval = Approval {
+                        owner_name,
+                        spender_name,
+                        ..approval.into()
+                    };
+                    Ok::<_, Error>(TokenApproval {
+                        verification,
+                        ..TokenApproval::new(token, approval, spender, risk, meta, timestamp)
+                    })
+                }
             })
             .collect::<FuturesUnordered<_>>()
             .try_collect()
             .await
     }
+
+    /// Live approvals, i.e. the latest approval for each (token, spender)
+    /// pair with allowance left at the head block, along with when they
+    /// were last used.
+    ///
+    /// An approval is considered used when owner tokens were transferred
+    /// in a transaction sent by the spender or sent directly to the spender
+    /// contract. Uses through intermediate contracts are not detected.
+    #[instrument(skip_all, fields(owner = ?owner, block_range = %block_range(&block_filter)))]
+    pub async fn get_allowances(
+        &self,
+        owner: Address,
+        block_filter: FilterBlockOption,
+    ) -> Result<Vec<Allowance>, Error> {
+        let started = Instant::now();
+        let approvals = self.get_token_approvals(owner, block_filter).await?;
+        let allowances = self.allowances_from_approvals(owner, approvals).await?;
+        observe_scan("allowances", started.elapsed(), allowances.len());
+        info!(allowances = allowances.len(), latency = ?started.elapsed(), "got allowances");
+        Ok(allowances)
+    }
+
+    /// Same as [`App::get_allowances`], with approvals scanned
+    /// incrementally by [`App::scan_approvals`]
+    #[instrument(skip_all, fields(owner = ?owner))]
+    pub async fn scan_allowances(
+        &self,
+        owner: Address,
+        from: Option<BlockRef>,
+        store: &impl CheckpointStore,
+    ) -> Result<Vec<Allowance>, Error> {
+        let started = Instant::now();
+        let approvals = self.scan_approvals(owner, from, store).await?;
+        let allowances = self.allowances_from_approvals(owner, approvals).await?;
+        observe_scan("allowances", started.elapsed(), allowances.len());
+        info!(allowances = allowances.len(), latency = ?started.elapsed(), "got allowances");
+        Ok(allowances)
+    }
+
+    async fn allowances_from_approvals(
+        &self,
+        owner: Address,
+        approvals: Vec<TokenApproval>,
+    ) -> Result<Vec<Allowance>, Error> {
+        let approvals = live_approvals(approvals);
+        let Some(since) = approvals.iter().map(|a| a.meta.block_number).min() else {
+            return Ok(Vec::new());
+        };
+        let head = self.client.get_block_number().await.map_err(Error::rpc)?;
+
+        let txs: CachedMap<H256, Option<(Address, Option<Address>)>> =
+            CachedMap::new("transactions");
+        let uses: Vec<_> = self
+            .query_logs::<TransferFilter>(
+                Filter::new()
+                    .address(
+                        approvals
+                            .iter()
+                            .map(|a| a.token.address())
+                            .unique()
+                            .collect_vec(),
+                    )
+                    .from_block(since)
+                    .to_block(head)
+                    .topic1(H256::from(owner)),
+            )
+            .await?
+            .into_iter()
+            .map(|(_, meta)| {
+                let txs = &txs;
+                async move {
+                    let tx = txs
+                        .get_or_try_insert_with(meta.transaction_hash, || async move {
+                            self.client
+                                .get_transaction(meta.transaction_hash)
+                                .await
+                                .map(|tx| tx.map(|tx| (tx.from, tx.to)))
+                                .map_err(Error::rpc)
+                        })
+                        .await?;
+                    Ok::<_, Error>(tx.map(|(from, to)| (meta, from, to)))
+                }
+            })
+            .collect::<FuturesUnordered<_>>()
+            .try_filter_map(|tx| async move { Ok(tx) })
+            .try_collect()
+            .await?;
+
+        let last_used: Vec<_> = approvals
+            .iter()
+            .map(|approval| {
+                uses.iter()
+                    .filter(|(meta, from, to)| {
+                        meta.address == approval.token.address()
+                            && (meta.block_number, meta.log_index)
+                                > (approval.meta.block_number, approval.meta.log_index)
+                            && (*from == approval.spender.address()
+                                || *to == Some(approval.spender.address()))
+                    })
+                    .map(|(meta, _, _)| meta.block_number)
+                    .max()
+            })
+            .collect();
+        let timestamps = self
+            .blocks
+            .try_get_timestamps(last_used.iter().flatten().copied().chain([head]))
+            .await?;
+        let proven = try_join_all(approvals.iter().map(|approval| async move {
+            match &self.prover {
+                Some(prover) => {
+                    prover
+                        .try_prove(
+                            approval.token.address(),
+                            owner,
+                            approval.spender.address(),
+                            head,
+                        )
+                        .await
+                }
+                None => Ok(ProvenAllowance::Skipped),
+            }
+        }))
+        .await?;
+
+        Ok(approvals
+            .into_iter()
+            .zip(last_used)
+            .zip(proven)
+            .map(|((approval, last_used), proven)| Allowance {
+                usage: Usage::new(
+                    approval.timestamp,
+                    last_used.map(|block| timestamps[&block]),
+                    timestamps[&head],
+                ),
+                approval,
+                proven,
+            })
+            .collect())
+    }
 }
 
-#[cfg(target_arch = "wasm32")]
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
 mod wasm {
     use super::*;
-    use std::str::FromStr;
 
+    use js_sys::Reflect;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_wasm::{WASMLayer, WASMLayerConfigBuilder};
     use wasm_bindgen::prelude::*;
 
-    /// Non-generic wrapper for App<P> to use with #[wasm_bindgen].
+    type Client = Provider<Throttle<Failover>>;
+
+    /// JS `Error` named after the kind of the error, e.g. `RangeLimitError`,
+    /// with `token` and `tx` properties if the error is about them
+    impl From<Error> for JsValue {
+        fn from(err: Error) -> Self {
+            let js_error = js_sys::Error::new(&err.to_string());
+            js_error.set_name(match err {
+                Error::Rpc(_) => "RpcError",
+                Error::RangeLimit { .. } => "RangeLimitError",
+                Error::NotErc20 { .. } => "NotErc20Error",
+                Error::Decode { .. } => "DecodeError",
+                Error::InvalidInput(_) => "InvalidInputError",
+                Error::Storage(_) => "StorageError",
+            });
+            if let Some(token) = err.token() {
+                let _ = Reflect::set(&js_error, &"token".into(), &format!("{token:#x}").into());
+            }
+            if let Some(tx) = err.tx() {
+                let _ = Reflect::set(&js_error, &"tx".into(), &format!("{tx:#x}").into());
+            }
+            js_error.into()
+        }
+    }
+
+    /// Forwards logs up to the level (error, warn, info, debug or trace,
+    /// info by default) to the browser console. Can only be called once.
+    #[wasm_bindgen]
+    pub fn init_logging(level: Option<String>) -> Result<(), JsError> {
+        let level = level
+            .as_deref()
+            .unwrap_or("info")
+            .parse::<tracing::Level>()
+            .map_err(|err| JsError::new(&err.to_string()))?;
+        let config = WASMLayerConfigBuilder::new()
+            .set_max_level(level)
+            .set_report_logs_in_timings(false)
+            .build();
+        tracing::subscriber::set_global_default(
+            tracing_subscriber::registry().with(WASMLayer::new(config)),
+        )
+        .map_err(|err| JsError::new(&err.to_string()))
+    }
+
+    /// Non-generic wrapper for App<M> to use with #[wasm_bindgen].
     /// It is needed since #[wasm_bindgen] does not support generics,
     /// and to implement conversions between Rust and JS types.
     #[wasm_bindgen]
-    pub struct HTTPApp(App<Http>);
+    pub struct HTTPApp(App<Client>);
 
     #[wasm_bindgen]
     impl HTTPApp {
         pub fn new(node: &str) -> Result<HTTPApp, JsError> {
-            Ok(Self(App::new(Url::parse(node)?)))
+            Self::new_with_failover(vec![node.into()], false)
+        }
+
+        /// Spreads requests across several nodes, failing over to the next
+        /// one when a node is unavailable. Nodes are picked by the lowest
+        /// latency if `by_latency` is set, and in round-robin otherwise.
+        pub fn new_with_failover(
+            nodes: Vec<JsValue>,
+            by_latency: bool,
+        ) -> Result<HTTPApp, JsError> {
+            let nodes = parse_nodes(nodes)?;
+            Ok(Self(App::new_with_throttle(
+                nodes,
+                if by_latency {
+                    Strategy::Latency
+                } else {
+                    Strategy::RoundRobin
+                },
+                RetryPolicy::default(),
+                RateLimit::default(),
+            )?))
+        }
+
+        /// Same as `new`, but requests are kept under the given rate
+        /// in requests and compute units per second. Zero means no limit.
+        pub fn new_with_rate_limit(
+            node: &str,
+            requests_per_second: f64,
+            compute_units_per_second: f64,
+        ) -> Result<HTTPApp, JsError> {
+            Ok(Self(App::new_with_throttle(
+                [Url::parse(node)?],
+                Strategy::default(),
+                RetryPolicy::default(),
+                RateLimit {
+                    requests_per_second: Some(requests_per_second),
+                    compute_units_per_second: Some(compute_units_per_second),
+                },
+            )?))
+        }
+
+        /// Cross-checks approval logs between given nodes, reporting only
+        /// the ones at least `threshold` of nodes agree on. The first node
+        /// is used for all other requests.
+        pub fn new_with_quorum(nodes: Vec<JsValue>, threshold: usize) -> Result<HTTPApp, JsError> {
+            let mut nodes = parse_nodes(nodes)?.into_iter();
+            let main = nodes.next().expect("at least one node");
+            Ok(Self(
+                App::builder(client(main)?)
+                    .quorum(nodes.map(client).collect::<Result<Vec<_>, _>>()?, threshold)?
+                    .build(),
+            ))
+        }
+
+        /// Same as `new`, but approval logs are verified against receipts
+        /// roots of their blocks, and blocks against the trusted checkpoint
+        /// block hash, if given
+        pub fn new_with_verification(
+            node: &str,
+            checkpoint: Option<String>,
+        ) -> Result<HTTPApp, JsError> {
+            let checkpoint = checkpoint
+                .filter(|checkpoint| !checkpoint.is_empty())
+                .map(|checkpoint| checkpoint.parse::<H256>())
+                .transpose()
+                .map_err(|err| JsError::new(&err.to_string()))?;
+            Ok(Self(
+                App::builder(client(Url::parse(node)?)?)
+                    .verification(checkpoint)
+                    .build(),
+            ))
+        }
+
+        /// Same as `new`, but requests carry credentials: `headers` object
+        /// of header names and values, and hex `jwt_secret` to sign JWT
+        /// bearer tokens with, if given
+        pub fn new_with_auth(
+            node: &str,
+            headers: JsValue,
+            jwt_secret: Option<String>,
+        ) -> Result<HTTPApp, JsError> {
+            let js_error = |err: anyhow::Error| JsError::new(&err.to_string());
+            let mut auth = Auth::default();
+            if !headers.is_undefined() && !headers.is_null() {
+                let headers: std::collections::BTreeMap<String, String> =
+                    serde_wasm_bindgen::from_value(headers)?;
+                for (name, value) in headers {
+                    auth = auth.header(&name, &value).map_err(js_error)?;
+                }
+            }
+            if let Some(secret) = jwt_secret.filter(|secret| !secret.is_empty()) {
+                auth = auth.jwt(secret.parse().map_err(js_error)?);
+            }
+            Ok(Self(
+                App::builder(Provider::new(Throttle::new(
+                    Failover::new_with_auth([Url::parse(node)?], Strategy::default(), auth)?,
+                    RetryPolicy::default(),
+                    RateLimit::default(),
+                )))
+                .build(),
+            ))
+        }
+
+        /// Same as `new`, but spenders are matched against known drainer
+        /// fingerprints given in text format
+        pub fn new_with_fingerprints(node: &str, fingerprints: &str) -> Result<HTTPApp, JsError> {
+            Ok(Self(
+                App::builder(client(Url::parse(node)?)?)
+                    .fingerprints(
+                        fingerprints
+                            .parse()
+                            .map_err(|err: anyhow::Error| JsError::new(&err.to_string()))?,
+                    )
+                    .build(),
+            ))
+        }
+
+        /// Profile of the chain the node is connected to
+        pub async fn chain(&self) -> Result<JsValue, JsValue> {
+            serde_wasm_bindgen::to_value(&*self.0.chain().await?).map_err(Into::into)
         }
 
         pub async fn get_token_approvals(
             &self,
             owner: &str,
-            from_block: Option<u64>,
-            to_block: Option<u64>,
-        ) -> Result<JsValue, JsError> {
+            from: Option<String>,
+            to: Option<String>,
+        ) -> Result<JsValue, JsValue> {
+            let block_filter = self
+                .0
+                .resolve_range(parse_block_ref(from)?, parse_block_ref(to)?)
+                .await?;
             serde_wasm_bindgen::to_value(
                 &self
                     .0
                     .get_token_approvals(
-                        Address::from_str(owner)?,
-                        FilterBlockOption::Range {
-                            from_block: from_block.map(Into::into),
-                            to_block: to_block.map(Into::into),
-                        },
+                        self.0.resolve_name(&parse_name_or_address(owner)).await?,
+                        block_filter,
+                    )
+                    .await?,
+            )
+            .map_err(Into::into)
+        }
+
+        /// Same as `get_token_approvals`, along with approval logs
+        /// which did not reach the quorum as `discrepancies`
+        pub async fn get_checked_token_approvals(
+            &self,
+            owner: &str,
+            from: Option<String>,
+            to: Option<String>,
+        ) -> Result<JsValue, JsValue> {
+            let block_filter = self
+                .0
+                .resolve_range(parse_block_ref(from)?, parse_block_ref(to)?)
+                .await?;
+            serde_wasm_bindgen::to_value(
+                &self
+                    .0
+                    .get_checked_token_approvals(
+                        self.0.resolve_name(&parse_name_or_address(owner)).await?,
+                        block_filter,
                     )
                     .await?,
             )
             .map_err(Into::into)
         }
     }
+
+    /// Combined report of live approvals on several chains.
+    /// Endpoints are given as an array of `[chain_id, node_url]` pairs.
+    #[wasm_bindgen]
+    pub async fn get_cross_chain_approvals(
+        endpoints: JsValue,
+        owner: &str,
+        from: Option<String>,
+        to: Option<String>,
+    ) -> Result<JsValue, JsValue> {
+        let chains = CrossChain::new(
+            serde_wasm_bindgen::from_value::<Vec<(u64, String)>>(endpoints)?
+                .into_iter()
+                .map(|(chain_id, node)| {
+                    let node = Url::parse(&node).map_err(|err| JsError::new(&err.to_string()))?;
+                    Ok((chain_id, App::builder(client(node)?).build()))
+                })
+                .collect::<Result<Vec<_>, JsError>>()?,
+        );
+        let owner = chains.resolve_name(&parse_name_or_address(owner)).await?;
+        serde_wasm_bindgen::to_value(
+            &chains
+                .get_report(owner, parse_block_ref(from)?, parse_block_ref(to)?)
+                .await?,
+        )
+        .map_err(Into::into)
+    }
+
+    /// Single node with default retry policy and no rate limit
+    fn client(node: Url) -> Result<Client, Error> {
+        Ok(Provider::new(Throttle::new(
+            Failover::new([node], Strategy::default())?,
+            RetryPolicy::default(),
+            RateLimit::default(),
+        )))
+    }
+
+    fn parse_nodes(nodes: Vec<JsValue>) -> Result<Vec<Url>, JsError> {
+        let nodes = nodes
+            .into_iter()
+            .map(|node| {
+                node.as_string()
+                    .ok_or_else(|| JsError::new("node url should be a string"))
+                    .and_then(|node| Url::parse(&node).map_err(Into::into))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if nodes.is_empty() {
+            return Err(JsError::new("at least one node is required"));
+        }
+        Ok(nodes)
+    }
+
+    /// Empty strings are treated as missing
+    fn parse_block_ref(block: Option<String>) -> Result<Option<BlockRef>, JsError> {
+        block
+            .filter(|block| !block.is_empty())
+            .map(|block| block.parse::<BlockRef>())
+            .transpose()
+            .map_err(|err| JsError::new(&err.to_string()))
+    }
 }
 
-#[cfg(target_arch = "wasm32")]
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
 pub use wasm::*;
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use ethers::{
+        providers::{Http, Provider},
+        types::{Address, BlockNumber, Filter, U256, U64},
+    };
+
+    use crate::{
+        testing::{http_node, DevChain, Reply},
+        App, BlockRef, Checkpoint, CheckpointStore, Error, Fingerprints, RiskFactor, TokenApproval,
+    };
+
+    fn address(n: u64) -> Address {
+        Address::from_low_u64_be(n)
+    }
+
+    #[derive(Default)]
+    struct MemoryStore(Mutex<HashMap<(u64, Address), Checkpoint>>);
+
+    impl CheckpointStore for MemoryStore {
+        fn load(&self, chain_id: u64, owner: Address) -> anyhow::Result<Option<Checkpoint>> {
+            Ok(self.0.lock().unwrap().get(&(chain_id, owner)).cloned())
+        }
+
+        fn save(
+            &self,
+            chain_id: u64,
+            owner: Address,
+            checkpoint: &Checkpoint,
+        ) -> anyhow::Result<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .insert((chain_id, owner), checkpoint.clone());
+            Ok(())
+        }
+    }
+
+    /// (block, spender) of approvals, in order of blocks
+    fn approved(approvals: &[TokenApproval]) -> Vec<(u64, Address)> {
+        let mut approved: Vec<_> = approvals
+            .iter()
+            .map(|a| (a.meta().block_number.as_u64(), a.spender().address()))
+            .collect();
+        approved.sort();
+        approved
+    }
+
+    fn from_block(block: u64) -> Option<BlockRef> {
+        Some(BlockRef::Block(BlockNumber::Number(block.into())))
+    }
+
+    #[tokio::test]
+    async fn resumes_scans_from_later_blocks() {
+        let (token, owner) = (address(0x70), address(0xaa));
+        let chain = DevChain::new(1337, 100);
+        chain.approve(10, token, owner, address(1), 5.into());
+        chain.approve(50, token, owner, address(2), 5.into());
+        let node = chain.start().await;
+        let app = App::new(node.url.clone());
+        let store = MemoryStore::default();
+
+        let approvals = app
+            .scan_approvals(owner, from_block(5), &store)
+            .await
+            .unwrap();
+        assert_eq!(approved(&approvals), [(10, address(1)), (50, address(2))]);
+
+        // e.g. `--since` resolved to a later block on the next run
+        chain.mine(10);
+        chain.approve(105, token, owner, address(3), 5.into());
+        let approvals = app
+            .scan_approvals(owner, from_block(30), &store)
+            .await
+            .unwrap();
+        assert_eq!(approved(&approvals), [(50, address(2)), (105, address(3))]);
+        let checkpoint = store.load(1337, owner).unwrap().unwrap();
+        assert_eq!(checkpoint.from(), 5.into());
+        assert_eq!(checkpoint.next_block(), 111.into());
+
+        // before the checkpoint the scan starts over
+        let approvals = app
+            .scan_approvals(owner, from_block(0), &store)
+            .await
+            .unwrap();
+        assert_eq!(
+            approved(&approvals),
+            [(10, address(1)), (50, address(2)), (105, address(3))]
+        );
+        let checkpoint = store.load(1337, owner).unwrap().unwrap();
+        assert_eq!(checkpoint.from(), 0.into());
+    }
+
+    #[tokio::test]
+    async fn rewinds_reorged_scans() {
+        let (token, owner) = (address(0x70), address(0xaa));
+        let chain = DevChain::new(1337, 100);
+        chain.approve(10, token, owner, address(1), 5.into());
+        chain.approve(20, token, owner, address(2), 5.into());
+        chain.approve(98, token, owner, address(1), U256::zero());
+        let node = chain.start().await;
+        let app = App::new(node.url.clone());
+        let store = MemoryStore::default();
+
+        let approvals = app
+            .scan_approvals(owner, from_block(0), &store)
+            .await
+            .unwrap();
+        assert_eq!(approved(&approvals), [(20, address(2))]);
+
+        // the revocation is reorged out along with the last scanned block
+        chain.reorg(98);
+        chain.mine(5);
+        let approvals = app
+            .scan_approvals(owner, from_block(0), &store)
+            .await
+            .unwrap();
+        assert_eq!(approved(&approvals), [(10, address(1)), (20, address(2))]);
+        let checkpoint = store.load(1337, owner).unwrap().unwrap();
+        let saved: Vec<_> = checkpoint.saved_blocks().map(|(block, _)| block).collect();
+        // the head and the block `REORG_DEPTH` below it
+        assert_eq!(saved, [105.into(), 41.into()]);
+    }
+
+    #[tokio::test]
+    async fn scores_only_live_approvals() {
+        let (token, owner) = (address(0x70), address(0xaa));
+        let chain = DevChain::new(1337, 10);
+        chain.approve(5, token, owner, address(1), U256::MAX);
+        chain.approve(6, token, owner, address(2), U256::MAX);
+        chain.approve(7, token, owner, address(1), U256::zero());
+        chain.approve(8, token, owner, address(2), 5.into());
+        let node = chain.start().await;
+        let app = App::new(node.url.clone());
+
+        let mut approvals = app
+            .get_token_approvals(owner, Filter::new().from_block(0).block_option)
+            .await
+            .unwrap();
+        approvals.sort_by_key(|approval| approval.meta().block_number);
+        let scores = approvals
+            .iter()
+            .map(|approval| approval.risk().score().value())
+            .collect::<Vec<_>>();
+        // superseded and revoked ones score nothing
+        assert_eq!(scores, [0, 0, 0, 20]);
+        let live = approvals[3].risk();
+        assert_eq!(live.factors(), [RiskFactor::EoaSpender]);
+        assert_eq!(live.allowance(), 5.into());
+    }
+
+    #[tokio::test]
+    async fn bounds_deployment_search_by_fetched_blocks() {
+        let (token, owner) = (address(0x70), address(0xaa));
+        // 12 second blocks, so the head is 40 days after the genesis
+        let chain = DevChain::new(1337, 288_000);
+        chain.approve(5, token, owner, address(1), U256::MAX);
+        chain.approve(287_000, token, owner, address(2), U256::MAX);
+        let node = chain.start().await;
+        let app = App::new(node.url.clone());
+
+        let approvals = app
+            .get_token_approvals(owner, Filter::new().from_block(0).block_option)
+            .await
+            .unwrap();
+        assert_eq!(approvals.len(), 2);
+        // block 5 is older than the recent window, so only the blocks
+        // of approvals and the head are fetched, without resolving it
+        assert_eq!(node.count("eth_getBlockByNumber"), 3);
+    }
+
+    #[test]
+    fn rejects_invalid_quorum_threshold() {
+        let client = || Provider::new(Http::new(url::Url::parse("http://localhost").unwrap()));
+        for threshold in [0, 3] {
+            let result = App::builder(client()).quorum([client()], threshold);
+            assert!(matches!(result, Err(Error::InvalidInput(_))));
+        }
+        assert!(App::builder(client()).quorum([client()], 2).is_ok());
+    }
+
+    #[tokio::test]
+    async fn sets_fingerprints_keeping_configuration() {
+        let node = http_node(|method, _| match method {
+            "eth_blockNumber" => Reply::ok(U64::from(100)),
+            "eth_getCode" => Reply::ok("0x6080"),
+            _ => Reply::Error(-32601, "method not found"),
+        })
+        .await;
+        let app = App::builder(Provider::new(Http::new(node.url.clone())))
+            .verification(None)
+            .storage_proofs()
+            .build();
+        let fingerprint = Fingerprints::fingerprint(&[0x60, 0x80]);
+        let fingerprints: Fingerprints = format!("{fingerprint:#x} Kit").parse().unwrap();
+
+        let app = app.with_fingerprints(Arc::new(fingerprints));
+        assert!(app.verifier.is_some());
+        assert!(app.prover.is_some());
+        let spender = app
+            .spenders
+            .try_get_spender(address(1), 0.into())
+            .await
+            .unwrap();
+        assert_eq!(spender.drainer(), Some("Kit"));
+    }
+
+    #[tokio::test]
+    async fn drops_spent_allowances() {
+        let (token, owner) = (address(0x70), address(0xaa));
+        let chain = DevChain::new(1337, 10);
+        chain.approve(5, token, owner, address(1), 100.into());
+        chain.approve(6, token, owner, address(2), 100.into());
+        // spent by `transferFrom`, which emits no `Approval`
+        chain.set_allowance(7, token, owner, address(1), U256::zero());
+        chain.set_allowance(8, token, owner, address(2), 40.into());
+        let node = chain.start().await;
+        let app = App::new(node.url.clone());
+
+        let allowances = app
+            .get_allowances(owner, Filter::new().from_block(0).block_option)
+            .await
+            .unwrap();
+        let [allowance] = allowances.as_slice() else {
+            panic!("expected a single allowance: {allowances:?}");
+        };
+        assert_eq!(allowance.approval().spender().address(), address(2));
+        assert_eq!(allowance.approval().risk().allowance(), 40.into());
+    }
+
+    #[cfg(feature = "ws")]
+    mod ws {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        use crate::{
+            testing::{ws_node, Reply},
+            App, RateLimit, RetryPolicy,
+        };
+
+        #[tokio::test]
+        async fn detects_chain_over_ws() {
+            let node = ws_node(|method, _| match method {
+                "eth_chainId" => Reply::ok("0x89"),
+                _ => Reply::Error(-32601, "method not found"),
+            })
+            .await;
+            let app = App::new_ws(node.url.clone()).await.unwrap();
+            assert_eq!(app.chain().await.unwrap().chain_id, 137);
+            // detected once and cached
+            app.chain().await.unwrap();
+            assert_eq!(node.calls(), ["eth_chainId"]);
+        }
+
+        #[tokio::test]
+        async fn retries_rate_limited_ws_requests() {
+            let attempts = AtomicUsize::new(0);
+            let node = ws_node(move |_, _| match attempts.fetch_add(1, Ordering::Relaxed) {
+                0 => Reply::Error(-32005, "limit exceeded"),
+                _ => Reply::ok("0x1"),
+            })
+            .await;
+            let policy = RetryPolicy {
+                initial_backoff: std::time::Duration::from_millis(1),
+                ..Default::default()
+            };
+            let app = App::new_ws_with_throttle(node.url.clone(), policy, RateLimit::default())
+                .await
+                .unwrap();
+            assert_eq!(app.chain().await.unwrap().chain_id, 1);
+            assert_eq!(node.count("eth_chainId"), 2);
+        }
+
+        #[tokio::test]
+        async fn does_not_retry_other_ws_errors() {
+            let node = ws_node(|_, _| Reply::Error(-32000, "header not found")).await;
+            let app = App::new_ws_with_throttle(
+                node.url.clone(),
+                RetryPolicy::default(),
+                RateLimit::default(),
+            )
+            .await
+            .unwrap();
+            assert!(app.chain().await.is_err());
+            assert_eq!(node.count("eth_chainId"), 1);
+        }
+    }
+}
@@ -1,59 +1,756 @@
-use clap::{Parser, ValueHint};
-use ethers::types::{Address, BlockNumber, FilterBlockOption};
+use std::{
+    cmp::Reverse,
+    fmt::Display,
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use clap::{error::ErrorKind, ArgAction, CommandFactory, Parser, Subcommand, ValueEnum, ValueHint};
+#[cfg(feature = "ws")]
+use ethers::providers::Ws;
+use ethers::{
+    providers::{Ipc, Middleware, Provider},
+    types::{Address, BlockNumber, H256},
+};
+use futures::StreamExt;
 use tokio::main;
+use tracing::warn;
+use tracing_subscriber::EnvFilter;
 use url::Url;
 
-use my_approvals::App;
+use anyhow::anyhow;
+use my_approvals::{
+    format_duration, parse_duration, parse_name_or_address, serve, serve_metrics, App, AppBuilder,
+    Auth, BlockRef, CheckedApprovals, CheckpointStore, CrossChain, Error, Failover, FileStore,
+    Fingerprints, Index, Metered, ProvenAllowance, RateLimit, RetryPolicy, RiskLevel, RiskScore,
+    Strategy, Throttle, Watcher, Webhooks,
+};
 
 #[derive(Parser)]
 struct Args {
-    /// HTTP ethereum node url
+    /// HTTP ethereum node url. Can be given multiple times
+    /// to fail over between nodes. A local node may be given
+    /// by its socket as ipc:///path/to/geth.ipc, or a node
+    /// by its WebSocket url (ws:// or wss://) instead.
     #[arg(
         short, long,
         value_hint = ValueHint::Url,
         value_name = "URL",
+        required_unless_present = "chains",
+    )]
+    node: Vec<Url>,
+
+    /// How to pick a node when several are given: round-robin or latency
+    #[arg(long, value_name = "STRATEGY", default_value = "round-robin")]
+    rpc_strategy: Strategy,
+
+    /// Max retries of a request failed with timeout, rate limit
+    /// or another transient error
+    #[arg(long, value_name = "N", default_value_t = RetryPolicy::default().max_retries)]
+    max_retries: u32,
+
+    /// Header to send with every request to the nodes, e.g. an API key.
+    /// Can be given multiple times.
+    #[arg(long = "header", value_name = "NAME: VALUE")]
+    headers: Vec<String>,
+
+    /// File with hex secret to sign JWT tokens with, as used by the engine API
+    #[arg(long, value_hint = ValueHint::FilePath, value_name = "FILE")]
+    jwt_secret: Option<PathBuf>,
+
+    /// Max requests per second to send
+    #[arg(long, value_name = "REQ_PER_SEC")]
+    rate_limit: Option<f64>,
+
+    /// Max provider compute units per second to spend
+    #[arg(long, value_name = "CU_PER_SEC")]
+    cu_limit: Option<f64>,
+
+    /// Scan several chains and print combined report of live approvals.
+    /// Rate limits and retries apply to each chain's node separately.
+    /// Can be given multiple times.
+    #[arg(
+        long = "chain",
+        value_name = "CHAIN_ID=URL",
+        value_parser = parse_chain_endpoint,
+        conflicts_with_all = [
+            "node", "headers", "jwt_secret", "stale_after", "ens_registry", "quorum_nodes",
+            "verify", "checkpoint",
+        ],
     )]
-    node: Url,
+    chains: Vec<(u64, Url)>,
+
+    /// Independent node to cross-check approval logs with.
+    /// Can be given multiple times.
+    #[arg(long = "quorum-node", value_hint = ValueHint::Url, value_name = "URL")]
+    quorum_nodes: Vec<Url>,
+
+    /// Min number of nodes, including the main one, which should report
+    /// an approval log for it to be shown [default: majority of nodes]
+    #[arg(long, value_name = "N", requires = "quorum_nodes")]
+    quorum: Option<usize>,
 
-    /// Starting block number to query from [default: earliest]
-    #[arg(short, long, value_name = "BLOCK_NUMBER")]
-    from_block: Option<u64>,
+    /// Verify approval logs against receipts roots of their blocks
+    /// instead of trusting the node. Requires `eth_getBlockReceipts`.
+    #[arg(long)]
+    verify: bool,
 
-    /// Ending block number to query from [default: latest]
-    #[arg(short, long, value_name = "BLOCK_NUMBER")]
-    to_block: Option<u64>,
+    /// Also verify that blocks with approvals are ancestors of this
+    /// trusted block. Fetches every block header down from it. Implies `--verify`.
+    #[arg(long, value_name = "BLOCK_HASH")]
+    checkpoint: Option<H256>,
+
+    /// Starting block number or tag (earliest, latest, safe, finalized, pending)
+    /// to query from [default: earliest]
+    #[arg(short, long, value_name = "BLOCK", value_parser = BlockRef::parse_block)]
+    from_block: Option<BlockRef>,
+
+    /// Ending block number or tag (earliest, latest, safe, finalized, pending)
+    /// to query to [default: latest]
+    #[arg(short, long, value_name = "BLOCK", value_parser = BlockRef::parse_block)]
+    to_block: Option<BlockRef>,
+
+    /// Query from the first block at or after this date (`2023-01-01`),
+    /// RFC 3339 time or duration before the latest block (`30d`)
+    #[arg(
+        long,
+        value_name = "TIME",
+        value_parser = BlockRef::parse_time,
+        conflicts_with = "from_block"
+    )]
+    since: Option<BlockRef>,
+
+    /// Query to the last block before or at this date (`2023-01-01`),
+    /// RFC 3339 time or duration before the latest block (`30d`)
+    #[arg(
+        long,
+        value_name = "TIME",
+        value_parser = BlockRef::parse_time,
+        conflicts_with = "to_block"
+    )]
+    until: Option<BlockRef>,
+
+    /// File with known drainer bytecode fingerprints,
+    /// one `<fingerprint> <family>` pair per line
+    #[arg(long, value_hint = ValueHint::FilePath, value_name = "FILE")]
+    fingerprints: Option<PathBuf>,
+
+    /// Only show approvals with at least this risk score (0-100)
+    /// or level (low, medium, high, critical)
+    #[arg(long, value_name = "SCORE|LEVEL")]
+    min_risk: Option<RiskScore>,
+
+    /// Only show live approvals not used for at least this long,
+    /// e.g. `180d` or `2y`
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    stale_after: Option<Duration>,
+
+    /// Prove current allowances of live approvals by storage proofs
+    /// (`eth_getProof`) instead of trusting the node. Only live
+    /// approvals are shown.
+    #[arg(long)]
+    prove: bool,
+
+    /// Directory to keep scan state in. Subsequent runs for the same owner
+    /// only scan new blocks, and interrupted scans resume where they left off.
+    /// Only live approvals are shown.
+    #[arg(
+        long,
+        value_hint = ValueHint::DirPath,
+        value_name = "DIR",
+        conflicts_with_all = ["to_block", "until", "quorum_nodes", "chains"],
+    )]
+    state_dir: Option<PathBuf>,
+
+    /// Custom ENS registry address [default: mainnet registry]
+    #[arg(long, value_name = "ADDRESS")]
+    ens_registry: Option<Address>,
+
+    /// Answer queries from the index built by the `index` command,
+    /// querying the node only for blocks after the last indexed one.
+    /// Queries follow an `index` command syncing it meanwhile.
+    #[arg(
+        long,
+        value_hint = ValueHint::DirPath,
+        value_name = "DIR",
+        conflicts_with_all = ["quorum_nodes", "chains"],
+    )]
+    index: Option<PathBuf>,
 
-    /// Owner of tokens
-    #[arg()]
-    owner: Address,
+    /// Serve Prometheus metrics at /metrics on this address while
+    /// the `index` or `watch` command runs. `serve` exposes them
+    /// along with the API.
+    #[arg(long, value_name = "ADDR")]
+    metrics_listen: Option<SocketAddr>,
+
+    /// Log what is going on to stderr: `-v` for info, `-vv` for debug
+    /// and `-vvv` for trace. `RUST_LOG` takes precedence if set,
+    /// e.g. `RUST_LOG=my_approvals=debug`.
+    #[arg(short, long, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Format of logs: text or json, one object per line
+    #[arg(long, value_name = "FORMAT", default_value = "text")]
+    log_format: LogFormat,
+
+    /// Owner of tokens: address or ENS name. Required unless a command is given.
+    owner: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Index approval logs of all owners into a local database
+    /// and keep it synced with the head block
+    Index {
+        /// Directory of the index
+        #[arg(long, value_hint = ValueHint::DirPath, value_name = "DIR")]
+        db: PathBuf,
+
+        /// Block number or tag to start a new index from [default: earliest]
+        #[arg(long, value_name = "BLOCK", value_parser = BlockRef::parse_block)]
+        start: Option<BlockRef>,
+
+        /// How often to check for new blocks
+        #[arg(
+            long,
+            value_name = "DURATION",
+            value_parser = parse_duration,
+            default_value = "12s",
+        )]
+        poll_interval: Duration,
+    },
+
+    /// Serve REST API with JSON responses: /approvals/{owner},
+    /// /allowances/{owner} and /spenders/{spender}/owners (requires `--index`).
+    /// Query parameters match the filters: from, to, since, until,
+    /// min_risk and stale_after.
+    Serve {
+        /// Address to listen on
+        #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8080")]
+        listen: SocketAddr,
+    },
+
+    /// Watch owners for new approvals, increases of allowances and risky
+    /// approvals (`--min-risk`, high by default), and post them to webhooks
+    /// as JSON. Starts from `--from-block` or `--since`, or the next block.
+    Watch {
+        /// File with owners to watch, one address or ENS name per line
+        #[arg(long, value_hint = ValueHint::FilePath, value_name = "FILE")]
+        watchlist: PathBuf,
+
+        /// Url to post events to. Can be given multiple times.
+        #[arg(
+            long = "webhook",
+            value_hint = ValueHint::Url,
+            value_name = "URL",
+            required = true,
+        )]
+        webhooks: Vec<Url>,
+
+        /// File with secret to sign payloads with, the signature is sent
+        /// as `X-Signature-256: sha256=<hex HMAC-SHA256 of the body>`
+        #[arg(long, value_hint = ValueHint::FilePath, value_name = "FILE")]
+        webhook_secret: Option<PathBuf>,
+
+        /// JSON lines file to append deliveries failed after retries to
+        #[arg(
+            long,
+            value_hint = ValueHint::FilePath,
+            value_name = "FILE",
+            default_value = "dead_letters.jsonl",
+        )]
+        dead_letters: PathBuf,
+
+        /// How often to check for new blocks
+        #[arg(
+            long,
+            value_name = "DURATION",
+            value_parser = parse_duration,
+            default_value = "12s",
+        )]
+        poll_interval: Duration,
+    },
 }
 
 #[main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
+    // commands do not take an owner, but still need a node
+    if args.command.is_none() && args.owner.is_none() {
+        Args::command()
+            .error(ErrorKind::MissingRequiredArgument, "<OWNER> is required")
+            .exit();
+    }
+    if args.command.is_some() && !args.chains.is_empty() {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--chain can not be used with commands",
+            )
+            .exit();
+    }
+    init_tracing(args.verbose, args.log_format);
+
+    let policy = RetryPolicy {
+        max_retries: args.max_retries,
+        ..Default::default()
+    };
+    let limit = RateLimit {
+        requests_per_second: args.rate_limit,
+        compute_units_per_second: args.cu_limit,
+    };
 
-    let app = App::new(args.node);
-
-    eprintln!("getting approvals from {:#x}", &args.owner);
-    let approvals = app
-        .get_token_approvals(
-            args.owner,
-            FilterBlockOption::Range {
-                from_block: Some(
-                    args.from_block
-                        .map(Into::into)
-                        .unwrap_or(BlockNumber::Earliest),
-                ),
-                to_block: Some(args.to_block.map(Into::into).unwrap_or(BlockNumber::Latest)),
-            },
+    if !args.chains.is_empty() {
+        // each chain has its own node, so limits apply to each node separately
+        let chains = CrossChain::new(
+            args.chains
+                .iter()
+                .map(|(chain_id, node)| {
+                    let app =
+                        App::new_with_throttle([node.clone()], args.rpc_strategy, policy, limit)?;
+                    Ok((*chain_id, app))
+                })
+                .collect::<Result<Vec<_>, my_approvals::Error>>()?,
+        );
+        return cross_chain(args, chains).await;
+    }
+
+    #[cfg(feature = "ws")]
+    if let Some(node) = ws_node(&args)? {
+        let ws = Metered::new(
+            Ws::connect(node.as_str())
+                .await
+                .map_err(|err| anyhow!("can not connect to {node}: {err}"))?,
+        );
+        return scan(
+            args,
+            App::builder(Provider::new(Throttle::new(ws, policy, limit))),
         )
+        .await;
+    }
+
+    if let Some(path) = ipc_path(&args)? {
+        let ipc = Metered::new(
+            Ipc::connect(&path)
+                .await
+                .map_err(|err| anyhow!("can not connect to {}: {err}", path.display()))?,
+        );
+        return scan(
+            args,
+            App::builder(Provider::new(Throttle::new(ipc, policy, limit))),
+        )
+        .await;
+    }
+
+    let mut auth = Auth::default();
+    for header in &args.headers {
+        auth = auth.raw_header(header)?;
+    }
+    if let Some(path) = &args.jwt_secret {
+        auth = auth.jwt(fs::read_to_string(path)?.parse()?);
+    }
+    let throttled = |nodes: Vec<Url>| {
+        Ok::<_, my_approvals::Error>(Provider::new(Throttle::new(
+            Failover::new_with_auth(nodes, args.rpc_strategy, auth.clone())?,
+            policy,
+            limit,
+        )))
+    };
+    let mut app = App::builder(throttled(args.node.clone())?);
+    if !args.quorum_nodes.is_empty() {
+        let n = args.quorum_nodes.len() + 1;
+        let quorum = args.quorum.unwrap_or(n / 2 + 1);
+        app = app.quorum(
+            args.quorum_nodes
+                .iter()
+                .map(|node| throttled(vec![node.clone()]))
+                .collect::<Result<Vec<_>, _>>()?,
+            quorum,
+        )?;
+        eprintln!("cross-checking logs with quorum of {quorum} of {n} nodes");
+    }
+    scan(args, app).await
+}
+
+fn init_tracing(verbose: u8, format: LogFormat) {
+    let level = match verbose {
+        0 => "warn",
+        1 => "info",
+        2 => "debug",
+        _ => "trace",
+    };
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(format!("warn,my_approvals={level}")));
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    match format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+}
+
+/// Path of the node socket if it is given as `ipc://` url
+fn ipc_path(args: &Args) -> anyhow::Result<Option<PathBuf>> {
+    if !args.node.iter().any(|node| node.scheme() == "ipc") {
+        return Ok(None);
+    }
+    if args.node.len() > 1 || !args.quorum_nodes.is_empty() {
+        return Err(anyhow!("IPC node can not be combined with other nodes"));
+    }
+    if !args.headers.is_empty() || args.jwt_secret.is_some() {
+        return Err(anyhow!("IPC node does not need authentication"));
+    }
+    Ok(Some(PathBuf::from(args.node[0].path())))
+}
+
+/// Node url if it is given as `ws://` or `wss://` url
+#[cfg(feature = "ws")]
+fn ws_node(args: &Args) -> anyhow::Result<Option<Url>> {
+    if !args
+        .node
+        .iter()
+        .any(|node| matches!(node.scheme(), "ws" | "wss"))
+    {
+        return Ok(None);
+    }
+    if args.node.len() > 1 || !args.quorum_nodes.is_empty() {
+        return Err(anyhow!(
+            "WebSocket node can not be combined with other nodes"
+        ));
+    }
+    if !args.headers.is_empty() || args.jwt_secret.is_some() {
+        return Err(anyhow!("WebSocket node does not support authentication"));
+    }
+    Ok(Some(args.node[0].clone()))
+}
+
+async fn scan<M: Middleware + 'static>(args: Args, mut app: AppBuilder<M>) -> anyhow::Result<()> {
+    if let Some(fingerprints) = load_fingerprints(&args.fingerprints)? {
+        app = app.fingerprints(fingerprints);
+    }
+    if let Some(registry) = args.ens_registry {
+        app = app.ens_registry(registry);
+    }
+    if args.verify || args.checkpoint.is_some() {
+        app = app.verification(args.checkpoint);
+    }
+    if args.prove {
+        app = app.storage_proofs();
+    }
+    if let Some(dir) = &args.index {
+        let index = Index::open(dir)?;
+        match index.last_block()? {
+            Some((block, _)) => eprintln!("using index up to block {block}"),
+            None => eprintln!("index is empty"),
+        }
+        app = app.index(Arc::new(index));
+    }
+    let app = app.build();
+    if let Some(addr) = args.metrics_listen {
+        eprintln!("serving metrics at http://{addr}/metrics");
+        tokio::spawn(async move {
+            if let Err(err) = serve_metrics(addr).await {
+                eprintln!("metrics server failed: {err}");
+            }
+        });
+    }
+
+    if let Some(Command::Index {
+        db,
+        start,
+        poll_interval,
+    }) = &args.command
+    {
+        let (db, start, poll_interval) = (db.clone(), *start, *poll_interval);
+        return index(app, db, start, poll_interval).await;
+    }
+    if let Some(Command::Watch {
+        watchlist,
+        webhooks,
+        webhook_secret,
+        dead_letters,
+        poll_interval,
+    }) = &args.command
+    {
+        let mut webhooks = Webhooks::new(webhooks.clone())
+            .retry_policy(RetryPolicy {
+                max_retries: args.max_retries,
+                ..Default::default()
+            })
+            .dead_letters(dead_letters);
+        if let Some(path) = webhook_secret {
+            webhooks = webhooks.secret(fs::read_to_string(path)?.trim());
+        }
+        let min_risk = args.min_risk.unwrap_or(RiskLevel::High.min_score());
+        let from = args.from_block.or(args.since);
+        return watch(app, watchlist, webhooks, from, min_risk, *poll_interval).await;
+    }
+    if let Some(Command::Serve { listen }) = args.command {
+        eprintln!("listening on http://{listen}");
+        return serve(app, listen).await.map_err(Into::into);
+    }
+
+    let chain = app.chain().await?;
+    eprintln!("connected to {} (chain id {})", chain.name, chain.chain_id);
+
+    let owner = app
+        .resolve_name(&parse_name_or_address(
+            args.owner.as_deref().expect("owner is required"),
+        ))
         .await?;
+    let owner_name = match app.lookup_name(owner).await? {
+        Some(name) => format!("{name} ({owner:#x})"),
+        None => format!("{owner:#x}"),
+    };
 
-    eprintln!("got {} approvals", approvals.len());
+    let from = args.from_block.or(args.since);
+    let store = args.state_dir.as_ref().map(FileStore::new);
+    if let Some(store) = &store {
+        if let Some((block, _)) = store
+            .load(chain.chain_id, owner)?
+            .and_then(|checkpoint| checkpoint.last_block())
+        {
+            eprintln!("found scan state up to block {block}");
+        }
+    }
+    let block_filter = app
+        .resolve_range(from, args.to_block.or(args.until))
+        .await?;
 
-    for a in approvals {
-        println!("{}", a);
+    if args.stale_after.is_some() || args.prove {
+        eprintln!("getting live approvals from {owner_name}");
+        let mut allowances = match &store {
+            Some(store) => app.scan_allowances(owner, from, store).await?,
+            None => app.get_allowances(owner, block_filter).await?,
+        };
+        eprintln!("got {} live approvals", allowances.len());
+        if args.prove {
+            eprintln!(
+                "{} of them with proven allowance",
+                allowances
+                    .iter()
+                    .filter(|a| matches!(a.proven(), ProvenAllowance::Proven { .. }))
+                    .count()
+            );
+        }
+
+        if let Some(stale_after) = args.stale_after {
+            allowances.retain(|a| a.usage().is_stale(stale_after));
+            eprintln!(
+                "{} of them not used for at least {}",
+                allowances.len(),
+                format_duration(stale_after)
+            );
+        }
+        report(allowances, |a| a.approval().risk().score(), args.min_risk);
+    } else {
+        let checked = match &store {
+            Some(store) => {
+                eprintln!("getting live approvals from {owner_name}");
+                let approvals = app.scan_approvals(owner, from, store).await?;
+                eprintln!("got {} live approvals", approvals.len());
+                CheckedApprovals::from(approvals)
+            }
+            None => {
+                eprintln!("getting approvals from {owner_name}");
+                let checked = app.get_checked_token_approvals(owner, block_filter).await?;
+                eprintln!("got {} approvals", checked.approvals.len());
+                checked
+            }
+        };
+        if args.verify || args.checkpoint.is_some() {
+            eprintln!(
+                "{} of them verified",
+                checked
+                    .approvals
+                    .iter()
+                    .filter(|a| a.verification().is_verified())
+                    .count()
+            );
+        }
+        if !checked.discrepancies.is_empty() {
+            eprintln!(
+                "{} approval logs did not reach the quorum:",
+                checked.discrepancies.len()
+            );
+            for discrepancy in &checked.discrepancies {
+                eprintln!("{discrepancy}");
+            }
+        }
+        let approvals = checked.approvals;
+
+        report(approvals, |a| a.risk().score(), args.min_risk);
+    }
+
+    Ok(())
+}
+
+async fn index<M: Middleware + 'static>(
+    app: App<M>,
+    dir: PathBuf,
+    start: Option<BlockRef>,
+    poll_interval: Duration,
+) -> anyhow::Result<()> {
+    let index = Index::open(&dir)?;
+    if let Some((block, _)) = index.last_block()? {
+        eprintln!(
+            "resuming index at block {block} with {} approval logs",
+            index.len()?
+        );
+    }
+    loop {
+        // node failures are retried on the next poll,
+        // while invalid configuration would fail every time
+        match app.sync_index(&index, start).await {
+            Ok(Some(block)) => eprintln!(
+                "indexed up to block {block}, {} approval logs, {} undecodable logs skipped",
+                index.len()?,
+                index.undecodable_logs()?
+            ),
+            Ok(None) => {}
+            Err(err @ Error::InvalidInput(_)) => return Err(err.into()),
+            Err(err) => warn!(%err, "failed to sync index, retrying"),
+        }
+        futures_timer::Delay::new(poll_interval).await;
+    }
+}
+
+async fn watch<M: Middleware + 'static>(
+    app: App<M>,
+    watchlist: &Path,
+    webhooks: Webhooks,
+    from: Option<BlockRef>,
+    min_risk: RiskScore,
+    poll_interval: Duration,
+) -> anyhow::Result<()> {
+    let mut owners = Vec::new();
+    for line in fs::read_to_string(watchlist)?.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if !line.is_empty() {
+            owners.push(app.resolve_name(&parse_name_or_address(line)).await?);
+        }
+    }
+    let from = match from {
+        Some(from) => app.resolve_block(from).await?,
+        None => {
+            app.resolve_block(BlockRef::Block(BlockNumber::Latest))
+                .await?
+                + 1
+        }
+    };
+    eprintln!("watching {} owners from block {from}", owners.len());
+    let mut watcher = Watcher::new(owners, from, min_risk);
+
+    // events are delivered in order by a separate task,
+    // so slow webhooks do not hold up polling
+    let (queue, mut events) = futures::channel::mpsc::unbounded();
+    let delivery = tokio::spawn(async move {
+        while let Some(event) = events.next().await {
+            for letter in webhooks.notify(&event).await? {
+                eprintln!("failed to notify {}: {}", letter.url, letter.error);
+            }
+        }
+        anyhow::Ok(())
+    });
+    loop {
+        // the watcher is unchanged by failed polls, so they are retried
+        // from the same block, while invalid configuration would fail every time
+        let events = match watcher.poll(&app).await {
+            Ok(events) => events,
+            Err(err @ Error::InvalidInput(_)) => return Err(err.into()),
+            Err(err) => {
+                warn!(%err, next = %watcher.next_block(), "failed to poll, retrying");
+                Vec::new()
+            }
+        };
+        for event in events {
+            println!("{event}");
+            // the task only stops if dead letters can not be stored
+            if queue.unbounded_send(event).is_err() {
+                return delivery.await?;
+            }
+        }
+        futures_timer::Delay::new(poll_interval).await;
+    }
+}
+
+async fn cross_chain<M: Middleware + 'static>(
+    args: Args,
+    mut chains: CrossChain<M>,
+) -> anyhow::Result<()> {
+    let n = args.chains.len();
+    if let Some(fingerprints) = load_fingerprints(&args.fingerprints)? {
+        chains = chains.with_fingerprints(fingerprints);
     }
 
+    let owner = chains
+        .resolve_name(&parse_name_or_address(
+            args.owner.as_deref().expect("owner is required"),
+        ))
+        .await?;
+    eprintln!("getting live approvals from {owner:#x} on {n} chains");
+    let combined = chains
+        .get_report(
+            owner,
+            args.from_block.or(args.since),
+            args.to_block.or(args.until),
+        )
+        .await?;
+    for totals in &combined.chains {
+        eprintln!("{totals}");
+    }
+    eprintln!("total: {}", combined.summary);
+
+    report(
+        combined.approvals,
+        |a| a.approval.risk().score(),
+        args.min_risk,
+    );
     Ok(())
 }
+
+fn load_fingerprints(path: &Option<PathBuf>) -> anyhow::Result<Option<Fingerprints>> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    let fingerprints: Fingerprints = fs::read_to_string(path)?.parse()?;
+    eprintln!("loaded {} drainer fingerprints", fingerprints.len());
+    Ok(Some(fingerprints))
+}
+
+fn parse_chain_endpoint(s: &str) -> anyhow::Result<(u64, Url)> {
+    let (chain_id, node) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected CHAIN_ID=URL"))?;
+    Ok((chain_id.parse()?, node.parse()?))
+}
+
+/// Prints items from the riskiest, skipping ones below `min_risk`
+fn report<T: Display>(
+    mut items: Vec<T>,
+    risk: impl Fn(&T) -> RiskScore,
+    min_risk: Option<RiskScore>,
+) {
+    if let Some(min_risk) = min_risk {
+        items.retain(|a| risk(a) >= min_risk);
+        eprintln!("{} of them with risk of at least {}", items.len(), min_risk);
+    }
+    items.sort_by_key(|a| Reverse(risk(a)));
+
+    for a in items {
+        println!("{}", a);
+    }
+}
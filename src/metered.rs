@@ -0,0 +1,85 @@
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+use ethers::providers::JsonRpcClient;
+use instant::Instant;
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::trace;
+
+use crate::metrics::observe_rpc;
+
+/// Transport wrapper recording every request sent to the node in
+/// metrics, with the `metrics` feature. All constructors of [`App`](crate::App)
+/// wrap the node transport in it, below any retries and failover,
+/// so each attempt is counted.
+#[derive(Debug)]
+pub struct Metered<C> {
+    inner: C,
+}
+
+impl<C> Metered<C> {
+    pub fn new(inner: C) -> Self {
+        Self { inner }
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<C: JsonRpcClient> JsonRpcClient for Metered<C> {
+    type Error = C::Error;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned,
+    {
+        let started = Instant::now();
+        let res = self.inner.request(method, params).await;
+        let latency = started.elapsed();
+        let outcome = if res.is_ok() { "ok" } else { "error" };
+        observe_rpc(method, outcome, latency);
+        trace!(method, outcome, ?latency, "request");
+        res
+    }
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use ethers::providers::{MockError, MockProvider};
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::metrics::{RPC_CALLS, RPC_DURATION};
+
+    fn calls(method: &str, outcome: &str) -> u64 {
+        RPC_CALLS.with_label_values(&[method, outcome]).get()
+    }
+
+    #[test]
+    fn counts_each_request_by_outcome() {
+        let mock = MockProvider::new();
+        let metered = Metered::new(mock.clone());
+        // the label is unique, so counters are not shared with other tests
+        let method = "test_countsEachRequest";
+
+        mock.push::<u64, _>(1).unwrap();
+        let res: Result<u64, MockError> = block_on(metered.request(method, ()));
+        assert_eq!(res.unwrap(), 1);
+        assert_eq!(calls(method, "ok"), 1);
+        assert_eq!(calls(method, "error"), 0);
+
+        // nothing is pushed, so the mock fails
+        let res: Result<u64, MockError> = block_on(metered.request(method, ()));
+        assert!(res.is_err());
+        assert_eq!(calls(method, "ok"), 1);
+        assert_eq!(calls(method, "error"), 1);
+        assert_eq!(
+            RPC_DURATION.with_label_values(&[method]).get_sample_count(),
+            2
+        );
+    }
+}
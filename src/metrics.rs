@@ -0,0 +1,132 @@
+//! Prometheus metrics of RPC usage, caches and scans. They are registered
+//! in the default registry, so metrics of the embedding service are
+//! exported along with them by `gather_metrics`. Without the `metrics`
+//! feature nothing is recorded.
+
+use std::time::Duration;
+#[cfg(feature = "metrics")]
+use {
+    prometheus::{
+        register_histogram_vec, register_int_counter, register_int_counter_vec, Encoder,
+        HistogramVec, IntCounter, IntCounterVec, TextEncoder,
+    },
+    std::sync::LazyLock,
+};
+
+/// Content type of [`gather_metrics`] output
+#[cfg(feature = "metrics")]
+pub const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Attempts of JSON-RPC requests sent through [`Metered`](crate::Metered),
+/// by method and outcome: `ok` or `error`
+#[cfg(feature = "metrics")]
+pub(crate) static RPC_CALLS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "my_approvals_rpc_calls_total",
+        "JSON-RPC requests by method and outcome",
+        &["method", "outcome"]
+    )
+    .unwrap()
+});
+
+#[cfg(feature = "metrics")]
+pub(crate) static RPC_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "my_approvals_rpc_duration_seconds",
+        "Latency of JSON-RPC requests by method",
+        &["method"],
+        vec![0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0]
+    )
+    .unwrap()
+});
+
+#[cfg(feature = "metrics")]
+static LOG_RANGE_SPLITS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "my_approvals_log_range_splits_total",
+        "eth_getLogs queries split into chunks by max log range of the chain"
+    )
+    .unwrap()
+});
+
+#[cfg(feature = "metrics")]
+static CACHE_LOOKUPS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "my_approvals_cache_lookups_total",
+        "Cache lookups by cache and result: hit or miss",
+        &["cache", "result"]
+    )
+    .unwrap()
+});
+
+#[cfg(feature = "metrics")]
+static SCAN_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "my_approvals_scan_duration_seconds",
+        "Duration of successful scans by kind",
+        &["scan"],
+        vec![0.1, 0.5, 1.0, 5.0, 15.0, 60.0, 300.0, 900.0]
+    )
+    .unwrap()
+});
+
+#[cfg(feature = "metrics")]
+static APPROVALS_FOUND: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "my_approvals_approvals_found_total",
+        "Approvals returned by scans by kind",
+        &["scan"]
+    )
+    .unwrap()
+});
+
+/// Records an attempt of a request sent to the node
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn observe_rpc(method: &str, outcome: &str, latency: Duration) {
+    #[cfg(feature = "metrics")]
+    {
+        RPC_CALLS.with_label_values(&[method, outcome]).inc();
+        RPC_DURATION
+            .with_label_values(&[method])
+            .observe(latency.as_secs_f64());
+    }
+}
+
+/// Records a lookup of the cache, whether it was a hit
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn observe_cache(cache: &str, hit: bool) {
+    #[cfg(feature = "metrics")]
+    CACHE_LOOKUPS
+        .with_label_values(&[cache, if hit { "hit" } else { "miss" }])
+        .inc();
+}
+
+/// Records a log query split into chunks
+pub(crate) fn observe_range_split() {
+    #[cfg(feature = "metrics")]
+    LOG_RANGE_SPLITS.inc();
+}
+
+/// Records a successful scan of the kind
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn observe_scan(scan: &str, duration: Duration, approvals: usize) {
+    #[cfg(feature = "metrics")]
+    {
+        SCAN_DURATION
+            .with_label_values(&[scan])
+            .observe(duration.as_secs_f64());
+        APPROVALS_FOUND
+            .with_label_values(&[scan])
+            .inc_by(approvals as u64);
+    }
+}
+
+/// All metrics of the default registry in the Prometheus text format
+#[cfg(feature = "metrics")]
+pub fn gather_metrics() -> String {
+    let mut text = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut text)
+        .expect("metrics are encodable");
+    String::from_utf8(text).expect("metrics are UTF-8")
+}
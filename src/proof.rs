@@ -0,0 +1,196 @@
+//! Current allowances proven by `eth_getProof` against the state root,
+//! instead of trusting the result of `allowance()` call.
+
+use std::{fmt::Display, sync::Arc};
+
+use ethers::{
+    providers::Middleware,
+    types::{Address, BigEndianHash, H256, U256, U64},
+    utils::{keccak256, rlp},
+};
+use futures::{
+    future::try_join,
+    stream::{self, StreamExt, TryStreamExt},
+};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{abi::ierc20::IERC20, cached::CachedMap, error::Error, trie::verify_proof};
+
+/// Base slots of the allowances mapping probed when discovering
+/// the storage layout of a token
+const PROBED_SLOTS: u64 = 16;
+
+/// Result of proving the current allowance
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+pub enum ProvenAllowance {
+    /// Proof was not requested
+    #[default]
+    Skipped,
+    /// Value read from the token storage and proven against
+    /// the state root of the block
+    Proven { value: U256, block: U64 },
+    /// Storage layout of the token is unknown or the proof is invalid
+    Unverified(Arc<str>),
+}
+
+impl Display for ProvenAllowance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Skipped => write!(f, "not proven"),
+            Self::Proven { value, block } => write!(f, "proven {value} at block {block}"),
+            Self::Unverified(reason) => write!(f, "unverified: {reason}"),
+        }
+    }
+}
+
+/// Where `allowance[owner][spender]` is stored
+#[derive(Debug, Clone, Copy)]
+enum Layout {
+    /// `keccak(spender . keccak(owner . slot))`
+    Solidity(U256),
+    /// `keccak(keccak(slot . owner) . spender)`
+    Vyper(U256),
+}
+
+impl Layout {
+    fn candidates() -> impl Iterator<Item = Self> {
+        (0..PROBED_SLOTS).flat_map(|slot| [Self::Solidity(slot.into()), Self::Vyper(slot.into())])
+    }
+
+    fn slot(self, owner: Address, spender: Address) -> H256 {
+        let word = |address: Address| H256::from(address).0;
+        match self {
+            Self::Solidity(slot) => {
+                let inner = keccak256([word(owner), slot.into()].concat());
+                keccak256([word(spender), inner].concat())
+            }
+            Self::Vyper(slot) => {
+                let inner = keccak256([slot.into(), word(owner)].concat());
+                keccak256([inner, word(spender)].concat())
+            }
+        }
+        .into()
+    }
+}
+
+pub(crate) struct Prover<M: Middleware> {
+    client: Arc<M>,
+    layouts: CachedMap<Address, Layout>,
+    state_roots: CachedMap<U64, H256>,
+}
+
+impl<M: Middleware + 'static> Prover<M> {
+    pub fn new(client: impl Into<Arc<M>>) -> Self {
+        Self {
+            client: client.into(),
+            layouts: CachedMap::new("storage_layouts"),
+            state_roots: CachedMap::new("state_roots"),
+        }
+    }
+
+    /// Proves the allowance of the spender over owner tokens at the block
+    pub async fn try_prove(
+        &self,
+        token: Address,
+        owner: Address,
+        spender: Address,
+        block: U64,
+    ) -> Result<ProvenAllowance, Error> {
+        let layout = match self
+            .layouts
+            .get_or_try_insert_with(token, || self.discover(token, owner, spender, block))
+            .await
+        {
+            Ok(layout) => layout,
+            Err(None) => return Ok(ProvenAllowance::Unverified("unknown storage layout".into())),
+            Err(Some(err)) => return Err(err),
+        };
+        let slot = layout.slot(owner, spender);
+
+        let (state_root, proof) = try_join(self.try_get_state_root(block), async {
+            self.client
+                .get_proof(token, vec![slot], Some(block.into()))
+                .await
+                .map_err(Error::rpc)
+        })
+        .await?;
+
+        let unverified = |reason: &str| Ok(ProvenAllowance::Unverified(reason.into()));
+        let account = match verify_proof(state_root, token.as_bytes(), &proof.account_proof) {
+            Ok(Some(account)) => account,
+            Ok(None) => return unverified("token account does not exist"),
+            Err(reason) => return unverified(reason),
+        };
+        // account is [nonce, balance, storage root, code hash]
+        let Ok(storage_root) = rlp::Rlp::new(&account).val_at::<H256>(2) else {
+            return unverified("invalid account");
+        };
+        let Some(storage_proof) = proof.storage_proof.first() else {
+            return unverified("storage proof is missing");
+        };
+        let value = match verify_proof(storage_root, slot.as_bytes(), &storage_proof.proof) {
+            Ok(Some(value)) => match rlp::decode::<U256>(&value) {
+                Ok(value) => value,
+                Err(_) => return unverified("invalid storage value"),
+            },
+            Ok(None) => U256::zero(),
+            Err(reason) => return unverified(reason),
+        };
+        Ok(ProvenAllowance::Proven { value, block })
+    }
+
+    /// Probes common layouts of the allowances mapping for the one holding
+    /// the value returned by `allowance()`. Errors with None if none matched,
+    /// so the token is probed again with other owner and spender, since
+    /// zero allowance can not tell layouts apart.
+    async fn discover(
+        &self,
+        token: Address,
+        owner: Address,
+        spender: Address,
+        block: U64,
+    ) -> Result<Layout, Option<Error>> {
+        let allowance = IERC20::new(token, self.client.clone())
+            .allowance(owner, spender)
+            .block(block)
+            .call()
+            .await
+            .map_err(|err| Error::token_call(token, err))?;
+        if allowance.is_zero() {
+            return Err(None);
+        }
+        let expected = H256::from_uint(&allowance);
+        stream::iter(Layout::candidates())
+            .map(|layout| async move {
+                let value = self
+                    .client
+                    .get_storage_at(token, layout.slot(owner, spender), Some(block.into()))
+                    .await
+                    .map_err(Error::rpc)?;
+                Ok::<_, Error>((value == expected).then_some(layout))
+            })
+            .buffered(PROBED_SLOTS as usize)
+            .try_collect::<Vec<_>>()
+            .await?
+            .into_iter()
+            .flatten()
+            .next()
+            .ok_or(None)
+    }
+
+    async fn try_get_state_root(&self, block: U64) -> Result<H256, Error> {
+        self.state_roots
+            .get_or_try_insert_with(block, || async move {
+                self.client
+                    .get_block(block)
+                    .await
+                    .map_err(Error::rpc)?
+                    .map(|block| block.state_root)
+                    .ok_or_else(|| Error::rpc(format!("block {block} not found")))
+            })
+            .await
+    }
+}
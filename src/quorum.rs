@@ -0,0 +1,237 @@
+use std::{collections::HashMap, fmt::Display, sync::Arc};
+
+use ethers::{
+    contract::LogMeta,
+    providers::Middleware,
+    types::{H256, U256},
+};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::erc20::TokenApproval;
+
+/// Independent providers which should agree on the logs
+pub(crate) struct Quorum<M: Middleware> {
+    providers: Vec<Arc<M>>,
+    threshold: usize,
+}
+
+impl<M: Middleware + 'static> Quorum<M> {
+    pub fn new(providers: Vec<Arc<M>>, threshold: usize) -> Self {
+        Self {
+            providers,
+            threshold,
+        }
+    }
+
+    pub fn providers(&self) -> &[Arc<M>] {
+        &self.providers
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Splits logs reported by each provider into the ones reported
+    /// identically by at least threshold providers and discrepancies.
+    /// Logs are matched by transaction hash and log index, a tie between
+    /// the most voted variants is a discrepancy whatever the votes.
+    pub fn agree<D: PartialEq>(
+        &self,
+        reported: Vec<Vec<(D, LogMeta)>>,
+    ) -> (Vec<(D, LogMeta)>, Vec<Discrepancy>) {
+        // different variants of each log along with their votes
+        let mut variants = HashMap::<_, Vec<_>>::new();
+        for log in reported.into_iter().flatten() {
+            let variants = variants
+                .entry((log.1.transaction_hash, log.1.log_index))
+                .or_default();
+            match variants.iter_mut().find(|(variant, _)| *variant == log) {
+                Some((_, votes)) => *votes += 1,
+                None => variants.push((log, 1)),
+            }
+        }
+
+        let mut agreed = Vec::new();
+        let mut discrepancies = Vec::new();
+        for ((transaction_hash, log_index), variants) in variants {
+            let n = variants.len();
+            let votes = variants
+                .iter()
+                .map(|(_, votes)| *votes)
+                .max()
+                .expect("at least one variant");
+            let mut leaders = variants.into_iter().filter(|(_, v)| *v == votes);
+            let (log, _) = leaders.next().expect("at least one variant");
+            if votes >= self.threshold && leaders.next().is_none() {
+                agreed.push(log);
+            } else {
+                discrepancies.push(Discrepancy {
+                    transaction_hash,
+                    log_index,
+                    votes,
+                    variants: n,
+                    providers: self.providers.len(),
+                    threshold: self.threshold,
+                });
+            }
+        }
+        (agreed, discrepancies)
+    }
+}
+
+/// Approvals reported by the quorum of providers
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+pub struct CheckedApprovals {
+    pub approvals: Vec<TokenApproval>,
+    /// Logs left out of `approvals`, since providers did not agree on them
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+/// Approvals with nothing to check them against, like ones resumed from a checkpoint
+impl From<Vec<TokenApproval>> for CheckedApprovals {
+    fn from(approvals: Vec<TokenApproval>) -> Self {
+        Self {
+            approvals,
+            discrepancies: Vec::new(),
+        }
+    }
+}
+
+/// Log which did not reach the quorum
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+pub struct Discrepancy {
+    pub transaction_hash: H256,
+    pub log_index: U256,
+    /// Number of providers which reported the most common variant of the log
+    pub votes: usize,
+    /// Number of different variants of the log reported
+    pub variants: usize,
+    pub providers: usize,
+    pub threshold: usize,
+}
+
+impl Display for Discrepancy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "tx {} log #{}: reported by {} of {} providers, quorum is {}",
+            self.transaction_hash, self.log_index, self.votes, self.providers, self.threshold,
+        )?;
+        if self.variants > 1 {
+            write!(f, " ({} different variants)", self.variants)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        providers::{MockProvider, Provider},
+        types::Address,
+    };
+
+    use super::*;
+
+    fn quorum(providers: usize, threshold: usize) -> Quorum<Provider<MockProvider>> {
+        let providers = (0..providers)
+            .map(|_| Arc::new(Provider::new(MockProvider::new())))
+            .collect();
+        Quorum::new(providers, threshold)
+    }
+
+    /// Log with data of the variant reported by a provider
+    fn log(tx: u64, variant: u8) -> (u8, LogMeta) {
+        let meta = LogMeta {
+            address: Address::zero(),
+            block_number: tx.into(),
+            block_hash: H256::from_low_u64_be(tx),
+            transaction_hash: H256::from_low_u64_be(tx),
+            transaction_index: 0.into(),
+            log_index: 1.into(),
+        };
+        (variant, meta)
+    }
+
+    fn agreed(agreed: &[(u8, LogMeta)]) -> Vec<(u64, u8)> {
+        let mut agreed: Vec<_> = agreed
+            .iter()
+            .map(|(variant, meta)| (meta.block_number.as_u64(), *variant))
+            .collect();
+        agreed.sort();
+        agreed
+    }
+
+    #[test]
+    fn agrees_on_logs_reported_by_threshold() {
+        let reported = vec![
+            vec![log(1, 0), log(2, 0), log(3, 0)],
+            vec![log(1, 0), log(2, 0)],
+            vec![log(1, 0), log(3, 1)],
+        ];
+        let (logs, discrepancies) = quorum(3, 2).agree(reported);
+        assert_eq!(agreed(&logs), [(1, 0), (2, 0)]);
+
+        // log 3 got one vote for each of the two variants
+        let [discrepancy] = &discrepancies[..] else {
+            panic!("expected one discrepancy: {discrepancies:?}");
+        };
+        assert_eq!(discrepancy.transaction_hash, H256::from_low_u64_be(3));
+        assert_eq!(discrepancy.log_index, 1.into());
+        assert_eq!(
+            (discrepancy.votes, discrepancy.variants),
+            (1, 2),
+            "{discrepancy}"
+        );
+        assert_eq!((discrepancy.providers, discrepancy.threshold), (3, 2));
+    }
+
+    #[test]
+    fn outvotes_variants_below_threshold() {
+        let reported = vec![vec![log(1, 0)], vec![log(1, 1)], vec![log(1, 1)]];
+        let (logs, discrepancies) = quorum(3, 2).agree(reported);
+        assert_eq!(agreed(&logs), [(1, 1)]);
+        assert!(discrepancies.is_empty());
+    }
+
+    #[test]
+    fn tie_is_a_discrepancy() {
+        // both variants reach the threshold of 1
+        let reported = vec![vec![log(1, 0)], vec![log(1, 1)]];
+        let (logs, discrepancies) = quorum(2, 1).agree(reported);
+        assert!(logs.is_empty());
+        assert_eq!(discrepancies.len(), 1);
+        assert_eq!((discrepancies[0].votes, discrepancies[0].variants), (1, 2));
+
+        // two of four providers on each side
+        let reported = vec![
+            vec![log(1, 0)],
+            vec![log(1, 0)],
+            vec![log(1, 1)],
+            vec![log(1, 1)],
+        ];
+        let (logs, discrepancies) = quorum(4, 2).agree(reported);
+        assert!(logs.is_empty());
+        assert_eq!((discrepancies[0].votes, discrepancies[0].variants), (2, 2));
+    }
+
+    #[test]
+    fn logs_missing_from_providers_do_not_reach_quorum() {
+        let reported = vec![vec![log(1, 0)], vec![], vec![]];
+        let (logs, discrepancies) = quorum(3, 2).agree(reported);
+        assert!(logs.is_empty());
+        assert_eq!((discrepancies[0].votes, discrepancies[0].variants), (1, 1));
+        assert_eq!(
+            discrepancies[0].to_string(),
+            format!(
+                "tx {} log #1: reported by 1 of 3 providers, quorum is 2",
+                H256::from_low_u64_be(1)
+            )
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use std::{fmt::Display, sync::Arc};
+
+use ethers::{
+    providers::Middleware,
+    types::{Address, NameOrAddress},
+};
+use futures::future::try_join_all;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    blocks::BlockRef,
+    chain::ChainProfile,
+    erc20::{live_approvals, TokenApproval},
+    error::Error,
+    risk::{RiskFactor, RiskLevel},
+    spender::Fingerprints,
+    App,
+};
+
+/// Scans the same owner on several chains with a separate [`App`] per chain
+pub struct CrossChain<M: Middleware> {
+    apps: Vec<(u64, App<M>)>,
+}
+
+impl<M: Middleware + 'static> CrossChain<M> {
+    /// Apps are given as (expected chain id, app) pairs
+    pub fn new(apps: impl IntoIterator<Item = (u64, App<M>)>) -> Self {
+        Self {
+            apps: apps.into_iter().collect(),
+        }
+    }
+
+    /// Matches spenders on all chains against the fingerprints,
+    /// see [`App::with_fingerprints`]
+    pub fn with_fingerprints(self, fingerprints: Fingerprints) -> Self {
+        let fingerprints = Arc::new(fingerprints);
+        Self {
+            apps: self
+                .apps
+                .into_iter()
+                .map(|(chain_id, app)| (chain_id, app.with_fingerprints(fingerprints.clone())))
+                .collect(),
+        }
+    }
+
+    /// Resolves ENS name on the mainnet node, if given among the endpoints
+    pub async fn resolve_name(&self, name: &NameOrAddress) -> Result<Address, Error> {
+        match (name, self.apps.iter().find(|(chain_id, _)| *chain_id == 1)) {
+            (NameOrAddress::Address(address), _) => Ok(*address),
+            (name, Some((_, mainnet))) => mainnet.resolve_name(name).await,
+            (NameOrAddress::Name(name), None) => Err(Error::InvalidInput(format!(
+                "can not resolve {name} without mainnet node"
+            ))),
+        }
+    }
+
+    /// Live approvals on all chains. Range is resolved on each chain
+    /// separately, so it is better given by time rather than block numbers.
+    pub async fn get_report(
+        &self,
+        owner: Address,
+        from: Option<BlockRef>,
+        to: Option<BlockRef>,
+    ) -> Result<CrossChainReport, Error> {
+        let chains = try_join_all(self.apps.iter().map(|(chain_id, app)| async move {
+            let chain = app.chain().await?;
+            if chain.chain_id != *chain_id {
+                return Err(Error::InvalidInput(format!(
+                    "node for chain {chain_id} is connected to chain {}",
+                    chain.chain_id
+                )));
+            }
+            let block_filter = app.resolve_range(from, to).await?;
+            let approvals = live_approvals(app.get_token_approvals(owner, block_filter).await?);
+            Ok((chain, approvals))
+        }))
+        .await?;
+
+        let mut report = CrossChainReport::default();
+        for (chain, approvals) in chains {
+            let totals = Exposure::of(&approvals);
+            report.summary.add(&totals);
+            report.chains.push(ChainTotals {
+                chain: chain.clone(),
+                totals,
+            });
+            report
+                .approvals
+                .extend(approvals.into_iter().map(|approval| ChainApproval {
+                    chain: chain.clone(),
+                    approval,
+                }));
+        }
+        Ok(report)
+    }
+}
+
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+pub struct CrossChainReport {
+    pub approvals: Vec<ChainApproval>,
+    pub chains: Vec<ChainTotals>,
+    /// Exposure combined over all chains
+    pub summary: Exposure,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+pub struct ChainApproval {
+    pub chain: Arc<ChainProfile>,
+    pub approval: TokenApproval,
+}
+
+impl Display for ChainApproval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {}", self.chain.name, self.approval)
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+pub struct ChainTotals {
+    pub chain: Arc<ChainProfile>,
+    pub totals: Exposure,
+}
+
+impl Display for ChainTotals {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (chain id {}): {}",
+            self.chain.name, self.chain.chain_id, self.totals
+        )
+    }
+}
+
+/// Counts of live approvals by the kind of risk they pose
+#[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+pub struct Exposure {
+    pub approvals: usize,
+    pub unlimited: usize,
+    /// with high or critical risk level
+    pub risky: usize,
+    pub known_drainers: usize,
+}
+
+impl Exposure {
+    pub fn of<'a>(approvals: impl IntoIterator<Item = &'a TokenApproval>) -> Self {
+        let mut exposure = Self::default();
+        for a in approvals {
+            let factors = a.risk.factors();
+            exposure.approvals += 1;
+            exposure.unlimited += factors.contains(&RiskFactor::Unlimited) as usize;
+            exposure.risky += (a.risk.level() >= RiskLevel::High) as usize;
+            exposure.known_drainers += factors.contains(&RiskFactor::KnownDrainer) as usize;
+        }
+        exposure
+    }
+
+    pub fn add(&mut self, other: &Self) {
+        self.approvals += other.approvals;
+        self.unlimited += other.unlimited;
+        self.risky += other.risky;
+        self.known_drainers += other.known_drainers;
+    }
+}
+
+impl Display for Exposure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} live approvals, {} unlimited, {} with high risk, {} to known drainers",
+            self.approvals, self.unlimited, self.risky, self.known_drainers,
+        )
+    }
+}
@@ -0,0 +1,364 @@
+//! Risk scoring of approvals.
+//!
+//! Score is a sum of weights of the factors present, capped at 100:
+//!
+//! | factor                                      | weight |
+//! |---------------------------------------------|-------:|
+//! | spender bytecode matches a known drainer    |     60 |
+//! | amount is unlimited (`>= 2^96 - 1`)         |     25 |
+//! | spender is an EOA                           |     20 |
+//! | spender contract deployed within 30 days    |     15 |
+//! | owner balance is exposed to the spender     |     15 |
+//! | approval is older than a year               |     10 |
+//!
+//! Only the live allowance counts: approvals superseded by a later one,
+//! revoked or spent always score 0, since there is nothing at risk.
+//! Ages are measured by block timestamps, so they hold on chains
+//! with any block time.
+use std::{fmt::Display, str::FromStr, time::Duration};
+
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use ethers::types::U256;
+use itertools::Itertools;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::spender::{Spender, SpenderKind};
+
+/// Amounts starting from `2^96 - 1` (max of uint96, used by some tokens
+/// instead of uint256) are considered unlimited
+const UNLIMITED_THRESHOLD: U256 = U256([u64::MAX, (1 << 32) - 1, 0, 0]);
+
+/// Spender contracts deployed within this time are considered recent
+pub(crate) const RECENT_DEPLOYMENT: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Approvals older than this are considered stale
+const STALE_APPROVAL: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+pub enum RiskFactor {
+    KnownDrainer,
+    Unlimited,
+    EoaSpender,
+    RecentDeployment,
+    ExposedBalance,
+    Stale,
+}
+
+impl RiskFactor {
+    pub fn weight(&self) -> u8 {
+        match self {
+            Self::KnownDrainer => 60,
+            Self::Unlimited => 25,
+            Self::EoaSpender => 20,
+            Self::RecentDeployment => 15,
+            Self::ExposedBalance => 15,
+            Self::Stale => 10,
+        }
+    }
+}
+
+impl Display for RiskFactor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::KnownDrainer => "known drainer",
+            Self::Unlimited => "unlimited",
+            Self::EoaSpender => "EOA spender",
+            Self::RecentDeployment => "recently deployed spender",
+            Self::ExposedBalance => "balance exposed",
+            Self::Stale => "stale",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum RiskLevel {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl RiskLevel {
+    /// Lowest score of the level
+    pub fn min_score(&self) -> RiskScore {
+        RiskScore(match self {
+            Self::Low => 0,
+            Self::Medium => 25,
+            Self::High => 50,
+            Self::Critical => 75,
+        })
+    }
+}
+
+impl Display for RiskLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+            Self::Critical => "critical",
+        })
+    }
+}
+
+/// Score in range `0..=100`, the higher the riskier
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RiskScore(u8);
+
+impl RiskScore {
+    pub fn value(&self) -> u8 {
+        self.0
+    }
+
+    pub fn level(&self) -> RiskLevel {
+        [RiskLevel::Critical, RiskLevel::High, RiskLevel::Medium]
+            .into_iter()
+            .find(|level| *self >= level.min_score())
+            .unwrap_or(RiskLevel::Low)
+    }
+}
+
+impl Display for RiskScore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.0, self.level())
+    }
+}
+
+/// Parses either a score in range `0..=100` or a level name,
+/// which resolves to the lowest score of the level
+impl FromStr for RiskScore {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(score) = s.parse::<u8>() {
+            return (score <= 100)
+                .then_some(Self(score))
+                .ok_or_else(|| anyhow!("score should be in range 0..=100"));
+        }
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "low" => RiskLevel::Low,
+            "medium" => RiskLevel::Medium,
+            "high" => RiskLevel::High,
+            "critical" => RiskLevel::Critical,
+            _ => {
+                return Err(anyhow!(
+                    "expected score or one of: low, medium, high, critical"
+                ))
+            }
+        }
+        .min_score())
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Risk {
+    score: RiskScore,
+    level: RiskLevel,
+    factors: Vec<RiskFactor>,
+    /// Allowance left to the spender at the head block,
+    /// zero for approvals superseded by a later one
+    allowance: U256,
+    /// Amount of owner tokens the spender can move right now
+    exposed: U256,
+}
+
+impl Risk {
+    /// Scores the live `allowance` of an approval made at `approved_at`,
+    /// `deployed_at` is the timestamp of the spender deployment block
+    /// and `now` is the timestamp of the head block
+    pub fn assess(
+        allowance: U256,
+        spender: &Spender,
+        deployed_at: Option<DateTime<Utc>>,
+        balance: U256,
+        approved_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let age = |since: DateTime<Utc>| (now - since).to_std().unwrap_or_default();
+        let exposed = allowance.min(balance);
+        let factors: Vec<_> = if allowance.is_zero() {
+            Vec::new()
+        } else {
+            [
+                (spender.drainer().is_some(), RiskFactor::KnownDrainer),
+                (allowance >= UNLIMITED_THRESHOLD, RiskFactor::Unlimited),
+                (spender.kind() == SpenderKind::Eoa, RiskFactor::EoaSpender),
+                (
+                    deployed_at.is_some_and(|deployed_at| age(deployed_at) < RECENT_DEPLOYMENT),
+                    RiskFactor::RecentDeployment,
+                ),
+                (!exposed.is_zero(), RiskFactor::ExposedBalance),
+                (age(approved_at) > STALE_APPROVAL, RiskFactor::Stale),
+            ]
+            .into_iter()
+            .filter_map(|(present, factor)| present.then_some(factor))
+            .collect()
+        };
+
+        let score = RiskScore(
+            factors
+                .iter()
+                .map(|factor| factor.weight() as u32)
+                .sum::<u32>()
+                .min(100) as u8,
+        );
+        Self {
+            score,
+            level: score.level(),
+            factors,
+            allowance,
+            exposed,
+        }
+    }
+
+    pub fn score(&self) -> RiskScore {
+        self.score
+    }
+
+    pub fn level(&self) -> RiskLevel {
+        self.level
+    }
+
+    pub fn factors(&self) -> &[RiskFactor] {
+        &self.factors
+    }
+
+    pub fn allowance(&self) -> U256 {
+        self.allowance
+    }
+
+    pub fn exposed(&self) -> U256 {
+        self.exposed
+    }
+}
+
+impl Display for Risk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "risk {}", self.score)?;
+        if !self.factors.is_empty() {
+            write!(f, " ({})", self.factors.iter().format(", "))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn days_ago(days: i64) -> DateTime<Utc> {
+        now() - chrono::Duration::days(days)
+    }
+
+    fn now() -> DateTime<Utc> {
+        Utc.timestamp_opt(1_700_000_000, 0).unwrap()
+    }
+
+    fn assess(
+        allowance: U256,
+        spender: &Spender,
+        deployed_days_ago: Option<i64>,
+        balance: U256,
+        approved_days_ago: i64,
+    ) -> Risk {
+        Risk::assess(
+            allowance,
+            spender,
+            deployed_days_ago.map(days_ago),
+            balance,
+            days_ago(approved_days_ago),
+            now(),
+        )
+    }
+
+    #[test]
+    fn scores_nothing_without_allowance() {
+        let drainer = Spender::for_test(SpenderKind::Eoa, Some("Inferno"));
+        let risk = assess(U256::zero(), &drainer, None, U256::MAX, 1000);
+        assert_eq!(risk.score(), RiskScore(0));
+        assert_eq!(risk.level(), RiskLevel::Low);
+        assert!(risk.factors().is_empty());
+        assert!(risk.exposed().is_zero());
+    }
+
+    #[test]
+    fn sums_factor_weights() {
+        let contract = Spender::for_test(SpenderKind::Contract, None);
+        let risk = assess(UNLIMITED_THRESHOLD, &contract, Some(10), 100.into(), 1);
+        assert_eq!(
+            risk.factors(),
+            [
+                RiskFactor::Unlimited,
+                RiskFactor::RecentDeployment,
+                RiskFactor::ExposedBalance
+            ]
+        );
+        assert_eq!(risk.score(), RiskScore(55));
+        assert_eq!(risk.level(), RiskLevel::High);
+        assert_eq!(risk.allowance(), UNLIMITED_THRESHOLD);
+        assert_eq!(risk.exposed(), 100.into());
+        assert_eq!(
+            risk.to_string(),
+            "risk 55 high (unlimited, recently deployed spender, balance exposed)"
+        );
+    }
+
+    #[test]
+    fn measures_ages_by_timestamps() {
+        let contract = Spender::for_test(SpenderKind::Contract, None);
+        let risk = assess(1.into(), &contract, Some(31), U256::zero(), 365);
+        assert!(risk.factors().is_empty());
+
+        let risk = assess(1.into(), &contract, Some(29), U256::zero(), 366);
+        assert_eq!(
+            risk.factors(),
+            [RiskFactor::RecentDeployment, RiskFactor::Stale]
+        );
+        assert_eq!(risk.score(), RiskScore(25));
+    }
+
+    #[test]
+    fn caps_score() {
+        let drainer = Spender::for_test(SpenderKind::Eoa, Some("Inferno"));
+        let risk = assess(U256::MAX, &drainer, None, U256::MAX, 400);
+        assert_eq!(risk.score(), RiskScore(100));
+        assert_eq!(risk.level(), RiskLevel::Critical);
+        assert_eq!(risk.exposed(), U256::MAX);
+    }
+
+    #[test]
+    fn levels_scores() {
+        for (score, level) in [
+            (0, RiskLevel::Low),
+            (24, RiskLevel::Low),
+            (25, RiskLevel::Medium),
+            (50, RiskLevel::High),
+            (74, RiskLevel::High),
+            (75, RiskLevel::Critical),
+            (100, RiskLevel::Critical),
+        ] {
+            assert_eq!(RiskScore(score).level(), level, "{score}");
+        }
+    }
+
+    #[test]
+    fn parses_scores_and_levels() {
+        assert_eq!("0".parse::<RiskScore>().unwrap(), RiskScore(0));
+        assert_eq!("100".parse::<RiskScore>().unwrap(), RiskScore(100));
+        assert_eq!("high".parse::<RiskScore>().unwrap(), RiskScore(50));
+        assert_eq!("Critical".parse::<RiskScore>().unwrap(), RiskScore(75));
+        assert_eq!("low".parse::<RiskScore>().unwrap(), RiskScore(0));
+        assert!("101".parse::<RiskScore>().is_err());
+        assert!("-1".parse::<RiskScore>().is_err());
+        assert!("severe".parse::<RiskScore>().is_err());
+    }
+}
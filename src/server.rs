@@ -0,0 +1,331 @@
+//! REST API over [`App`], answering queries with JSON.
+//!
+//! - `GET /approvals/{owner}?from=&to=&since=&until=&min_risk=`
+//! - `GET /allowances/{owner}?from=&since=&stale_after=&min_risk=`
+//! - `GET /spenders/{spender}/owners`, requires an index
+//! - `GET /metrics` in the Prometheus text format
+//!
+//! Parameters take the same values as the CLI options, owners may be
+//! given by ENS names. Range starts either `from` a block or `since` a time,
+//! and ends either `to` a block or `until` a time, giving both is an error.
+//! Caches of the app are shared across requests.
+
+use std::{cmp::Reverse, convert::Infallible, net::SocketAddr, sync::Arc, time::Duration};
+
+use ethers::{
+    providers::Middleware,
+    types::{Address, FilterBlockOption},
+};
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde::Serialize;
+use url::form_urlencoded;
+
+use crate::{
+    gather_metrics, parse_duration, parse_name_or_address, App, BlockRef, Error, RiskScore,
+    METRICS_CONTENT_TYPE,
+};
+
+/// Serves the API until the server fails
+pub async fn serve<M: Middleware + 'static>(
+    app: App<M>,
+    addr: SocketAddr,
+) -> Result<(), hyper::Error> {
+    let app = Arc::new(app);
+    let make_service = make_service_fn(move |_| {
+        let app = app.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let app = app.clone();
+                async move { Ok::<_, Infallible>(handle(&app, req).await) }
+            }))
+        }
+    });
+    Server::bind(&addr).serve(make_service).await
+}
+
+/// Serves only `GET /metrics`, for daemons without the API
+pub async fn serve_metrics(addr: SocketAddr) -> Result<(), hyper::Error> {
+    let make_service = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+            Ok::<_, Infallible>(match (req.method(), req.uri().path()) {
+                (&Method::GET, "/metrics") => metrics(),
+                _ => ApiError::NotFound(format!("no such endpoint: {}", req.uri().path()))
+                    .into_response(),
+            })
+        }))
+    });
+    Server::bind(&addr).serve(make_service).await
+}
+
+fn metrics() -> Response<Body> {
+    Response::builder()
+        .header(CONTENT_TYPE, METRICS_CONTENT_TYPE)
+        .body(gather_metrics().into())
+        .expect("response is valid")
+}
+
+enum ApiError {
+    BadRequest(String),
+    NotFound(String),
+    /// Node failed to answer
+    BadGateway(String),
+    /// Index failed to answer
+    Internal(String),
+}
+
+impl ApiError {
+    fn into_response(self) -> Response<Body> {
+        let (status, error) = match self {
+            Self::BadRequest(error) => (StatusCode::BAD_REQUEST, error),
+            Self::NotFound(error) => (StatusCode::NOT_FOUND, error),
+            Self::BadGateway(error) => (StatusCode::BAD_GATEWAY, error),
+            Self::Internal(error) => (StatusCode::INTERNAL_SERVER_ERROR, error),
+        };
+        json(status, &serde_json::json!({ "error": error }))
+    }
+}
+
+fn bad_request(err: impl ToString) -> ApiError {
+    ApiError::BadRequest(err.to_string())
+}
+
+impl From<Error> for ApiError {
+    /// Invalid input is the client's fault, other errors are of the node
+    /// or the index
+    fn from(err: Error) -> Self {
+        match err {
+            Error::InvalidInput(_) => Self::BadRequest(err.to_string()),
+            Error::Storage(_) => Self::Internal(err.to_string()),
+            err => Self::BadGateway(err.to_string()),
+        }
+    }
+}
+
+fn json(status: StatusCode, value: &impl Serialize) -> Response<Body> {
+    let body = serde_json::to_vec(value).expect("responses are serializable");
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body.into())
+        .expect("response is valid")
+}
+
+async fn handle<M: Middleware + 'static>(app: &App<M>, req: Request<Body>) -> Response<Body> {
+    if req.method() != Method::GET {
+        return json(
+            StatusCode::METHOD_NOT_ALLOWED,
+            &serde_json::json!({ "error": "only GET is supported" }),
+        );
+    }
+    let res = match Query::parse(req.uri().query().unwrap_or_default()) {
+        Ok(query) => {
+            let path: Vec<_> = req.uri().path().trim_matches('/').split('/').collect();
+            match path.as_slice() {
+                ["approvals", owner] => approvals(app, owner, query).await,
+                ["allowances", owner] => allowances(app, owner, query).await,
+                ["spenders", spender, "owners"] => owners(app, spender).await,
+                ["metrics"] => Ok(metrics()),
+                _ => Err(ApiError::NotFound(format!(
+                    "no such endpoint: {}",
+                    req.uri().path()
+                ))),
+            }
+        }
+        Err(err) => Err(err),
+    };
+    res.unwrap_or_else(ApiError::into_response)
+}
+
+/// Filters given by query parameters
+#[derive(Debug, Default)]
+struct Query {
+    from: Option<BlockRef>,
+    to: Option<BlockRef>,
+    min_risk: Option<RiskScore>,
+    stale_after: Option<Duration>,
+}
+
+impl Query {
+    fn parse(query: &str) -> Result<Self, ApiError> {
+        let mut parsed = Self::default();
+        for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+            let invalid = |err: anyhow::Error| bad_request(format!("invalid {key}: {err}"));
+            match &*key {
+                "from" | "since" if parsed.from.is_some() => {
+                    return Err(bad_request("only one of from and since can be given"))
+                }
+                "to" | "until" if parsed.to.is_some() => {
+                    return Err(bad_request("only one of to and until can be given"))
+                }
+                "from" => parsed.from = Some(BlockRef::parse_block(&value).map_err(invalid)?),
+                "to" => parsed.to = Some(BlockRef::parse_block(&value).map_err(invalid)?),
+                "since" => parsed.from = Some(BlockRef::parse_time(&value).map_err(invalid)?),
+                "until" => parsed.to = Some(BlockRef::parse_time(&value).map_err(invalid)?),
+                "min_risk" => parsed.min_risk = Some(value.parse().map_err(invalid)?),
+                "stale_after" => {
+                    parsed.stale_after = Some(parse_duration(&value).map_err(invalid)?)
+                }
+                _ => return Err(bad_request(format!("unknown parameter: {key}"))),
+            }
+        }
+        Ok(parsed)
+    }
+
+    async fn resolve_range<M: Middleware + 'static>(
+        &self,
+        app: &App<M>,
+    ) -> Result<FilterBlockOption, ApiError> {
+        app.resolve_range(self.from, self.to)
+            .await
+            .map_err(ApiError::from)
+    }
+
+    /// Keeps items with at least min risk, riskiest first
+    fn report<T>(&self, mut items: Vec<T>, risk: impl Fn(&T) -> RiskScore) -> Vec<T> {
+        if let Some(min_risk) = self.min_risk {
+            items.retain(|item| risk(item) >= min_risk);
+        }
+        items.sort_by_key(|item| Reverse(risk(item)));
+        items
+    }
+}
+
+async fn resolve_name<M: Middleware + 'static>(
+    app: &App<M>,
+    name: &str,
+) -> Result<Address, ApiError> {
+    app.resolve_name(&parse_name_or_address(name))
+        .await
+        .map_err(ApiError::from)
+}
+
+async fn approvals<M: Middleware + 'static>(
+    app: &App<M>,
+    owner: &str,
+    query: Query,
+) -> Result<Response<Body>, ApiError> {
+    if query.stale_after.is_some() {
+        return Err(bad_request("stale_after is only supported for allowances"));
+    }
+    let (owner, block_filter) = (
+        resolve_name(app, owner).await?,
+        query.resolve_range(app).await?,
+    );
+    let approvals = app.get_token_approvals(owner, block_filter).await?;
+    Ok(json(
+        StatusCode::OK,
+        &query.report(approvals, |a| a.risk.score()),
+    ))
+}
+
+async fn allowances<M: Middleware + 'static>(
+    app: &App<M>,
+    owner: &str,
+    query: Query,
+) -> Result<Response<Body>, ApiError> {
+    let (owner, block_filter) = (
+        resolve_name(app, owner).await?,
+        query.resolve_range(app).await?,
+    );
+    let mut allowances = app.get_allowances(owner, block_filter).await?;
+    if let Some(stale_after) = query.stale_after {
+        allowances.retain(|a| a.usage.is_stale(stale_after));
+    }
+    Ok(json(
+        StatusCode::OK,
+        &query.report(allowances, |a| a.approval.risk.score()),
+    ))
+}
+
+async fn owners<M: Middleware + 'static>(
+    app: &App<M>,
+    spender: &str,
+) -> Result<Response<Body>, ApiError> {
+    let Some(index) = &app.index else {
+        return Err(ApiError::NotFound(
+            "spender queries require an index".to_string(),
+        ));
+    };
+    let spender = resolve_name(app, spender).await?;
+    let mut approvals = index.live_by_spender(spender).map_err(Error::Storage)?;
+    approvals
+        .sort_by_key(|approval| Reverse((approval.meta.block_number, approval.meta.log_index)));
+    Ok(json(StatusCode::OK, &approvals))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use ethers::types::BlockNumber;
+
+    use super::*;
+
+    fn rejected(query: &str) -> String {
+        match Query::parse(query) {
+            Err(ApiError::BadRequest(error)) => error,
+            Err(_) => panic!("{query} should be a bad request"),
+            Ok(parsed) => panic!("{query} should be rejected, parsed {parsed:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_query() {
+        let Ok(query) = Query::parse("from=0x10&until=2023-01-01&min_risk=high&stale_after=30d")
+        else {
+            panic!("query should parse");
+        };
+        assert_eq!(
+            query.from,
+            Some(BlockRef::Block(BlockNumber::Number(16.into())))
+        );
+        assert_eq!(
+            query.to,
+            Some(BlockRef::Time(
+                Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap()
+            ))
+        );
+        assert_eq!(query.min_risk, Some("high".parse().unwrap()));
+        assert_eq!(query.stale_after, Some(Duration::from_secs(30 * 86_400)));
+
+        let Ok(query) = Query::parse("since=7d&to=latest") else {
+            panic!("query should parse");
+        };
+        assert_eq!(
+            query.from,
+            Some(BlockRef::Ago(Duration::from_secs(7 * 86_400)))
+        );
+        assert_eq!(query.to, Some(BlockRef::Block(BlockNumber::Latest)));
+    }
+
+    #[test]
+    fn rejects_both_bounds_of_range_end() {
+        assert_eq!(
+            rejected("from=1&since=30d"),
+            "only one of from and since can be given"
+        );
+        assert_eq!(
+            rejected("since=30d&from=1"),
+            "only one of from and since can be given"
+        );
+        assert_eq!(
+            rejected("to=latest&until=2023-01-01"),
+            "only one of to and until can be given"
+        );
+        assert_eq!(
+            rejected("to=1&to=2"),
+            "only one of to and until can be given"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_parameters() {
+        assert!(rejected("from=yesterday").starts_with("invalid from: "));
+        assert!(rejected("since=1").starts_with("invalid since: "));
+        assert!(rejected("min_risk=severe").starts_with("invalid min_risk: "));
+        assert_eq!(rejected("owner=vitalik.eth"), "unknown parameter: owner");
+    }
+}
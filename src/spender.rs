@@ -0,0 +1,371 @@
+use std::{collections::HashMap, fmt::Display, str::FromStr, sync::Arc};
+
+use anyhow::{anyhow, Context};
+use ethers::{
+    providers::Middleware,
+    types::{Address, BlockNumber, H256, U64},
+    utils::keccak256,
+};
+use instant::Instant;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use tracing::{debug, debug_span, Instrument};
+
+use crate::{cached::CachedMap, error::Error};
+
+/// Known drainer families indexed by runtime bytecode fingerprint.
+///
+/// Text format: one `<fingerprint> <family name>` pair per line,
+/// empty lines and lines starting with `#` are ignored.
+#[derive(Debug, Default, Clone)]
+pub struct Fingerprints(HashMap<H256, Arc<str>>);
+
+impl Fingerprints {
+    /// keccak256 of runtime bytecode with metadata trailer stripped,
+    /// so that redeployments of the same source share the fingerprint
+    pub fn fingerprint(code: &[u8]) -> H256 {
+        keccak256(strip_metadata(code)).into()
+    }
+
+    pub fn insert(&mut self, fingerprint: H256, family: impl Into<Arc<str>>) {
+        self.0.insert(fingerprint, family.into());
+    }
+
+    pub fn family(&self, fingerprint: &H256) -> Option<&Arc<str>> {
+        self.0.get(fingerprint)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl FromStr for Fingerprints {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut db = Self::default();
+        for (n, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (fingerprint, family) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| anyhow!("line {}: expected `<fingerprint> <family>`", n + 1))?;
+            db.insert(
+                fingerprint
+                    .parse()
+                    .with_context(|| format!("line {}: invalid fingerprint", n + 1))?,
+                family.trim(),
+            );
+        }
+        Ok(db)
+    }
+}
+
+/// Solidity and Vyper append CBOR-encoded metadata to the runtime code,
+/// followed by its length as big-endian u16
+fn strip_metadata(code: &[u8]) -> &[u8] {
+    let Some((rest, len)) = code.len().checked_sub(2).map(|n| code.split_at(n)) else {
+        return code;
+    };
+    let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+    match rest.len().checked_sub(len) {
+        // CBOR map major type
+        Some(start) if len > 0 && (0xa0..=0xbf).contains(&rest[start]) => &rest[..start],
+        _ => code,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+pub enum SpenderKind {
+    /// Externally owned account, i.e. no code deployed
+    Eoa,
+    Contract,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Spender {
+    address: Address,
+    kind: SpenderKind,
+    fingerprint: Option<H256>,
+    drainer: Option<Arc<str>>,
+    deployed_at: Option<U64>,
+}
+
+impl Spender {
+    /// Looks up the spender code, deployments before the `since`
+    /// block are not searched for
+    pub async fn new<M: Middleware + 'static>(
+        address: impl Into<Address>,
+        client: Arc<M>,
+        fingerprints: &Fingerprints,
+        since: U64,
+    ) -> Result<Self, Error> {
+        let address = address.into();
+        let head = client.get_block_number().await.map_err(Error::rpc)?;
+        let code = client
+            .get_code(address, Some(head.into()))
+            .await
+            .map_err(Error::rpc)?;
+
+        if code.is_empty() {
+            return Ok(Self {
+                address,
+                kind: SpenderKind::Eoa,
+                fingerprint: None,
+                drainer: None,
+                deployed_at: None,
+            });
+        }
+
+        let fingerprint = Fingerprints::fingerprint(&code);
+        Ok(Self {
+            address,
+            kind: SpenderKind::Contract,
+            fingerprint: Some(fingerprint),
+            drainer: fingerprints.family(&fingerprint).cloned(),
+            // non-archive nodes can not serve historical code,
+            // so the deployment block is left unknown
+            deployed_at: Self::find_deployment(address, since.min(head), head, &*client)
+                .await
+                .ok()
+                .flatten(),
+        })
+    }
+
+    /// Binary search for the first block after `since` where code at address
+    /// is not empty, `None` if the code is already there at `since`.
+    /// Takes a single `eth_getCode` for contracts older than that.
+    /// Assumes that contract was not self-destructed and redeployed since then.
+    async fn find_deployment<M: Middleware + 'static>(
+        address: Address,
+        since: U64,
+        head: U64,
+        client: &M,
+    ) -> Result<Option<U64>, M::Error> {
+        let has_code = |block| async move {
+            client
+                .get_code(address, Some(BlockNumber::Number(block).into()))
+                .await
+                .map(|code| !code.is_empty())
+        };
+        if has_code(since).await? {
+            return Ok(None);
+        }
+        let (mut lo, mut hi) = (since + 1, head);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if has_code(mid).await? {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+        Ok(Some(hi))
+    }
+
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    pub fn kind(&self) -> SpenderKind {
+        self.kind
+    }
+
+    pub fn fingerprint(&self) -> Option<H256> {
+        self.fingerprint
+    }
+
+    /// Drainer family the spender bytecode matched, if any
+    pub fn drainer(&self) -> Option<&str> {
+        self.drainer.as_deref()
+    }
+
+    /// Block the contract was deployed at, if it was deployed after
+    /// the block the spender was looked up since
+    pub fn deployed_at(&self) -> Option<U64> {
+        self.deployed_at
+    }
+}
+
+impl Display for Spender {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.address())?;
+        if self.kind() == SpenderKind::Eoa {
+            write!(f, " (EOA)")?;
+        }
+        if let Some(family) = self.drainer() {
+            write!(f, " [known drainer: {family}]")?;
+        }
+        Ok(())
+    }
+}
+
+pub struct CachedSpenders<M: Middleware> {
+    client: Arc<M>,
+    fingerprints: Arc<Fingerprints>,
+    cached: CachedMap<Address, Arc<Spender>>,
+}
+
+impl<M: Middleware + 'static> CachedSpenders<M> {
+    pub fn new(client: impl Into<Arc<M>>, fingerprints: Arc<Fingerprints>) -> Self {
+        Self {
+            client: client.into(),
+            fingerprints,
+            cached: CachedMap::new("spenders"),
+        }
+    }
+
+    /// Spender looked up once, along with its deployment block if it
+    /// was deployed after the `since` block of the first lookup
+    pub async fn try_get_spender(
+        &self,
+        address: Address,
+        since: U64,
+    ) -> Result<Arc<Spender>, Error> {
+        self.cached
+            .get_or_try_insert_with(address, || {
+                async move {
+                    let started = Instant::now();
+                    let spender =
+                        Spender::new(address, self.client.clone(), &self.fingerprints, since)
+                            .await?;
+                    debug!(kind = ?spender.kind(), latency = ?started.elapsed(), "got spender");
+                    Ok(spender)
+                }
+                .instrument(debug_span!("spender_metadata", spender = ?address))
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+impl Spender {
+    pub(crate) fn for_test(kind: SpenderKind, drainer: Option<&str>) -> Self {
+        Self {
+            address: Address::zero(),
+            kind,
+            fingerprint: None,
+            drainer: drainer.map(Into::into),
+            deployed_at: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::{Http, Provider};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::testing::{http_node, Node, Reply};
+
+    /// Runtime code followed by Solidity metadata: CBOR map
+    /// `{"ipfs": <34 bytes>, "solc": <3 bytes>}` and its length
+    fn with_metadata(code: &[u8], hash: u8) -> Vec<u8> {
+        let mut cbor = vec![0xa2, 0x64];
+        cbor.extend(b"ipfs");
+        cbor.extend([0x58, 0x22]);
+        cbor.extend([hash; 34]);
+        cbor.push(0x64);
+        cbor.extend(b"solc");
+        cbor.extend([0x43, 0, 8, 19]);
+        [code, &cbor, &(cbor.len() as u16).to_be_bytes()].concat()
+    }
+
+    #[test]
+    fn strips_metadata() {
+        let code = [0x60, 0x80, 0x60, 0x40, 0x52, 0xfe];
+        assert_eq!(strip_metadata(&with_metadata(&code, 1)), code);
+        assert_eq!(
+            Fingerprints::fingerprint(&with_metadata(&code, 1)),
+            Fingerprints::fingerprint(&with_metadata(&code, 2)),
+        );
+        assert_eq!(Fingerprints::fingerprint(&code), keccak256(code).into());
+    }
+
+    #[test]
+    fn keeps_code_without_metadata() {
+        // trailing length is not preceded by a CBOR map
+        let code = [0x60, 0x80, 0x60, 0x40, 0x00, 0x03];
+        assert_eq!(strip_metadata(&code), code);
+        // length exceeds the code
+        assert_eq!(strip_metadata(&[0xa1, 0x00, 0x10]), [0xa1, 0x00, 0x10]);
+        // zero length
+        assert_eq!(strip_metadata(&[0xa1, 0x00, 0x00]), [0xa1, 0x00, 0x00]);
+        assert_eq!(strip_metadata(&[0x00]), [0x00]);
+        assert_eq!(strip_metadata(&[]), [] as [u8; 0]);
+    }
+
+    #[test]
+    fn parses_fingerprints() {
+        let fingerprint = H256::repeat_byte(0xab);
+        let db: Fingerprints = format!("# drainers\n\n{fingerprint:#x}  Inferno Drainer \n")
+            .parse()
+            .unwrap();
+        assert_eq!(db.len(), 1);
+        assert_eq!(db.family(&fingerprint).unwrap().as_ref(), "Inferno Drainer");
+        assert!("0xab".parse::<Fingerprints>().is_err());
+        assert!("nothex family".parse::<Fingerprints>().is_err());
+    }
+
+    /// Node at block 1_000_000 with the contract code deployed at the block
+    async fn node(deployed_at: u64) -> Node {
+        http_node(move |method, params: &Value| match method {
+            "eth_blockNumber" => Reply::ok(U64::from(1_000_000)),
+            "eth_getCode" => {
+                let block: U64 = serde_json::from_value(params[1].clone()).unwrap();
+                Reply::ok(if block.as_u64() >= deployed_at {
+                    "0x6080"
+                } else {
+                    "0x"
+                })
+            }
+            _ => Reply::Error(-32601, "method not found"),
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn finds_deployment_since_the_block() {
+        let node = node(999_000).await;
+        let client = Arc::new(Provider::new(Http::new(node.url.clone())));
+        let spender = Spender::new(
+            Address::zero(),
+            client,
+            &Fingerprints::default(),
+            990_000.into(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(spender.kind(), SpenderKind::Contract);
+        assert_eq!(spender.deployed_at(), Some(999_000.into()));
+        // code at the head, at `since` and the search over 10_000 blocks
+        assert!(node.count("eth_getCode") <= 2 + 14);
+    }
+
+    #[tokio::test]
+    async fn does_not_search_deployments_before_the_block() {
+        let node = node(1_000).await;
+        let client = Arc::new(Provider::new(Http::new(node.url.clone())));
+        let spender = Spender::new(
+            Address::zero(),
+            client,
+            &Fingerprints::default(),
+            990_000.into(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(spender.deployed_at(), None);
+        assert_eq!(node.count("eth_getCode"), 2);
+    }
+}
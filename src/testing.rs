@@ -0,0 +1,485 @@
+//! Local stand-ins for nodes, answering JSON-RPC requests by a handler
+
+// not every combination of features uses all of the helpers
+#![allow(dead_code)]
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use ethers::{
+    abi::{encode, Token},
+    contract::EthEvent,
+    types::{Address, Bytes, H256, U256, U64},
+    utils::keccak256,
+};
+use hyper::{
+    body::to_bytes,
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use serde_json::{json, Value};
+use url::Url;
+
+use crate::abi::{ierc20::ApprovalFilter, ierc721::ApprovalForAllFilter};
+
+/// How the node answers a request
+#[derive(Debug, Clone)]
+pub enum Reply {
+    Result(Value),
+    Error(i64, &'static str),
+    /// HTTP status without JSON-RPC body, WebSocket nodes close the connection
+    Status(u16),
+    /// Replies after the delay, e.g. to time the request out
+    Delayed(Duration, Box<Reply>),
+}
+
+impl Reply {
+    pub fn ok(value: impl serde::Serialize) -> Self {
+        Self::Result(serde_json::to_value(value).unwrap())
+    }
+}
+
+type Handler = dyn Fn(&str, &Value) -> Reply + Send + Sync;
+
+/// Node listening on a local port until the test ends
+pub struct Node {
+    pub url: Url,
+    calls: Arc<Mutex<Vec<String>>>,
+}
+
+impl Node {
+    /// Methods of all requests received so far
+    pub fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    pub fn count(&self, method: &str) -> usize {
+        self.calls().iter().filter(|call| *call == method).count()
+    }
+}
+
+/// Answers a single request or a batch, or fails with HTTP status
+async fn answer(handler: &Handler, calls: &Mutex<Vec<String>>, body: &[u8]) -> Result<Value, u16> {
+    let request: Value = serde_json::from_slice(body).map_err(|_| 400u16)?;
+    let one = |request: &Value| {
+        let method = request["method"].as_str().unwrap_or_default();
+        calls.lock().unwrap().push(method.to_string());
+        (request["id"].clone(), handler(method, &request["params"]))
+    };
+    let mut replies = match &request {
+        Value::Array(batch) => batch.iter().map(one).collect(),
+        request => vec![one(request)],
+    };
+    let mut responses = Vec::new();
+    for (id, mut reply) in replies.drain(..) {
+        while let Reply::Delayed(delay, inner) = reply {
+            tokio::time::sleep(delay).await;
+            reply = *inner;
+        }
+        responses.push(match reply {
+            Reply::Result(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Reply::Error(code, message) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": code, "message": message },
+            }),
+            Reply::Status(status) => return Err(status),
+            Reply::Delayed(..) => unreachable!(),
+        });
+    }
+    Ok(match request {
+        Value::Array(_) => Value::Array(responses),
+        _ => responses.pop().unwrap(),
+    })
+}
+
+/// Starts an HTTP node on a free port
+pub async fn http_node(handler: impl Fn(&str, &Value) -> Reply + Send + Sync + 'static) -> Node {
+    let handler: Arc<Handler> = Arc::new(handler);
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let make_service = {
+        let calls = calls.clone();
+        make_service_fn(move |_| {
+            let (handler, calls) = (handler.clone(), calls.clone());
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let (handler, calls) = (handler.clone(), calls.clone());
+                    async move {
+                        let body = to_bytes(req.into_body()).await.unwrap();
+                        Ok::<_, Infallible>(match answer(&*handler, &calls, &body).await {
+                            Ok(value) => Response::new(Body::from(value.to_string())),
+                            Err(status) => {
+                                let status = StatusCode::from_u16(status).unwrap();
+                                // gateways reply with a plain text reason
+                                let reason = status.canonical_reason().unwrap_or_default();
+                                Response::builder()
+                                    .status(status)
+                                    .body(Body::from(reason))
+                                    .unwrap()
+                            }
+                        })
+                    }
+                }))
+            }
+        })
+    };
+    let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+    let url = format!("http://{}", server.local_addr()).parse().unwrap();
+    tokio::spawn(server);
+    Node { url, calls }
+}
+
+/// Starts a WebSocket node on a free port
+#[cfg(feature = "ws")]
+pub async fn ws_node(handler: impl Fn(&str, &Value) -> Reply + Send + Sync + 'static) -> Node {
+    use futures::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message;
+
+    let handler: Arc<Handler> = Arc::new(handler);
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap())
+        .parse()
+        .unwrap();
+    let node = Node {
+        url,
+        calls: calls.clone(),
+    };
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let (handler, calls) = (handler.clone(), calls.clone());
+            tokio::spawn(async move {
+                let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+                while let Some(Ok(Message::Text(text))) = ws.next().await {
+                    match answer(&*handler, &calls, text.as_bytes()).await {
+                        Ok(value) => ws.send(Message::Text(value.to_string())).await.unwrap(),
+                        Err(_) => return,
+                    }
+                }
+            });
+        }
+    });
+    node
+}
+
+/// Local chain with blocks mined every 12 seconds and approval logs,
+/// answering the requests of scans, syncs and token metadata.
+/// Other requests are passed to the fallback handler.
+#[derive(Clone)]
+pub struct DevChain {
+    state: Arc<Mutex<ChainState>>,
+}
+
+#[derive(Default)]
+struct ChainState {
+    chain_id: u64,
+    head: u64,
+    /// blocks from this one on have hashes of another fork
+    fork: Option<(u64, u64)>,
+    logs: Vec<TestLog>,
+    /// (token, owner, spender) to allowances set in blocks, in order of approvals
+    allowances: HashMap<(Address, Address, Address), Vec<(u64, U256)>>,
+}
+
+struct TestLog {
+    block: u64,
+    address: Address,
+    topics: Vec<H256>,
+    data: Vec<u8>,
+}
+
+pub const GENESIS_TIMESTAMP: u64 = 1_600_000_000;
+
+impl DevChain {
+    pub fn new(chain_id: u64, head: u64) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(ChainState {
+                chain_id,
+                head,
+                ..Default::default()
+            })),
+        }
+    }
+
+    /// Starts the node, other contracts have no code
+    pub async fn start(&self) -> Node {
+        self.start_with(|method, _| match method {
+            "eth_call" => Reply::ok("0x"),
+            _ => Reply::Error(-32601, "method not found"),
+        })
+        .await
+    }
+
+    pub async fn start_with(
+        &self,
+        fallback: impl Fn(&str, &Value) -> Reply + Send + Sync + 'static,
+    ) -> Node {
+        let chain = self.clone();
+        http_node(move |method, params| {
+            chain
+                .answer(method, params)
+                .unwrap_or_else(|| fallback(method, params))
+        })
+        .await
+    }
+
+    pub fn head(&self) -> u64 {
+        self.state.lock().unwrap().head
+    }
+
+    pub fn mine(&self, blocks: u64) {
+        self.state.lock().unwrap().head += blocks;
+    }
+
+    pub fn timestamp(block: u64) -> u64 {
+        GENESIS_TIMESTAMP + 12 * block
+    }
+
+    pub fn block_hash(&self, block: u64) -> H256 {
+        self.state.lock().unwrap().block_hash(block)
+    }
+
+    /// Replaces blocks from the given one with another fork,
+    /// dropping their logs
+    pub fn reorg(&self, from: u64) {
+        let mut state = self.state.lock().unwrap();
+        let salt = state.fork.map_or(1, |(_, salt)| salt + 1);
+        state.fork = Some((from, salt));
+        state.logs.retain(|log| log.block < from);
+        for history in state.allowances.values_mut() {
+            history.retain(|(block, _)| *block < from);
+        }
+    }
+
+    /// Emits an ERC20 `Approval` log in the block and sets the allowance
+    pub fn approve(
+        &self,
+        block: u64,
+        token: Address,
+        owner: Address,
+        spender: Address,
+        value: U256,
+    ) {
+        let mut data = [0; 32];
+        value.to_big_endian(&mut data);
+        self.log(
+            block,
+            token,
+            vec![
+                ApprovalFilter::signature(),
+                H256::from(owner),
+                H256::from(spender),
+            ],
+            data.to_vec(),
+        );
+        self.set_allowance(block, token, owner, spender, value);
+    }
+
+    /// Sets the allowance without a log, as `transferFrom` spending it does
+    pub fn set_allowance(
+        &self,
+        block: u64,
+        token: Address,
+        owner: Address,
+        spender: Address,
+        value: U256,
+    ) {
+        self.state
+            .lock()
+            .unwrap()
+            .allowances
+            .entry((token, owner, spender))
+            .or_default()
+            .push((block, value));
+    }
+
+    /// Emits an `ApprovalForAll` log in the block
+    pub fn approve_for_all(
+        &self,
+        block: u64,
+        token: Address,
+        owner: Address,
+        operator: Address,
+        approved: bool,
+    ) {
+        let mut data = [0; 32];
+        data[31] = approved as u8;
+        self.log(
+            block,
+            token,
+            vec![
+                ApprovalForAllFilter::signature(),
+                H256::from(owner),
+                H256::from(operator),
+            ],
+            data.to_vec(),
+        );
+    }
+
+    pub fn log(&self, block: u64, address: Address, topics: Vec<H256>, data: Vec<u8>) {
+        self.state.lock().unwrap().logs.push(TestLog {
+            block,
+            address,
+            topics,
+            data,
+        });
+    }
+
+    /// Reply of the chain to the request, if the method is supported
+    pub fn answer(&self, method: &str, params: &Value) -> Option<Reply> {
+        let state = self.state.lock().unwrap();
+        let block = |tag: &Value| state.block_number(tag.as_str().unwrap_or("latest"));
+        Some(match method {
+            "eth_chainId" => Reply::ok(U64::from(state.chain_id)),
+            "eth_blockNumber" => Reply::ok(U64::from(state.head)),
+            "eth_getBlockByNumber" => {
+                let number = block(&params[0]);
+                Reply::Result(state.block(number).unwrap_or(Value::Null))
+            }
+            "eth_getBlockByHash" => {
+                let hash: H256 = serde_json::from_value(params[0].clone()).unwrap();
+                let number = (0..=state.head).find(|&n| state.block_hash(n) == hash);
+                Reply::Result(number.and_then(|n| state.block(n)).unwrap_or(Value::Null))
+            }
+            "eth_getLogs" => Reply::Result(Value::Array(state.logs(&params[0]))),
+            "eth_getCode" => Reply::ok("0x"),
+            "eth_call" => return state.call(&params[0], block(&params[1])),
+            _ => return None,
+        })
+    }
+}
+
+impl ChainState {
+    fn block_number(&self, tag: &str) -> u64 {
+        match tag {
+            "earliest" => 0,
+            "latest" | "pending" | "safe" | "finalized" => self.head,
+            number => u64::from_str_radix(number.trim_start_matches("0x"), 16).unwrap(),
+        }
+    }
+
+    fn block_hash(&self, block: u64) -> H256 {
+        let salt = match self.fork {
+            Some((from, salt)) if block >= from => salt,
+            _ => 0,
+        };
+        H256(keccak256(format!("{}:{block}:{salt}", self.chain_id)))
+    }
+
+    fn block(&self, number: u64) -> Option<Value> {
+        if number > self.head {
+            return None;
+        }
+        let hash = |n: u64| format!("{:#x}", self.block_hash(n));
+        let zero = format!("{:#x}", H256::zero());
+        Some(json!({
+            "number": format!("{number:#x}"),
+            "hash": hash(number),
+            "parentHash": if number == 0 { zero.clone() } else { hash(number - 1) },
+            "sha3Uncles": zero,
+            "miner": format!("{:#x}", Address::zero()),
+            "stateRoot": zero,
+            "transactionsRoot": zero,
+            "receiptsRoot": zero,
+            "logsBloom": format!("0x{}", "00".repeat(256)),
+            "difficulty": "0x0",
+            "totalDifficulty": "0x0",
+            "gasLimit": "0x1c9c380",
+            "gasUsed": "0x0",
+            "timestamp": format!("{:#x}", DevChain::timestamp(number)),
+            "extraData": "0x",
+            "mixHash": zero,
+            "nonce": "0x0000000000000000",
+            "baseFeePerGas": "0x7",
+            "size": "0x100",
+            "uncles": [],
+            "transactions": [],
+        }))
+    }
+
+    fn logs(&self, filter: &Value) -> Vec<Value> {
+        let bound =
+            |key: &str, default: &str| self.block_number(filter[key].as_str().unwrap_or(default));
+        let (from, to) = (bound("fromBlock", "latest"), bound("toBlock", "latest"));
+        // a value or a list of alternatives, null matches anything
+        let matches = |filter: &Value, value: String| match filter {
+            Value::Null => true,
+            Value::Array(values) => values.iter().any(|v| v.as_str() == Some(&value)),
+            v => v.as_str() == Some(&value),
+        };
+        let empty = Vec::new();
+        let topics = filter["topics"].as_array().unwrap_or(&empty);
+        let mut log_index = HashMap::<u64, u64>::new();
+        let mut logs = Vec::new();
+        for (i, log) in self.logs.iter().enumerate() {
+            let index = log_index.entry(log.block).or_default();
+            *index += 1;
+            if !(from..=to.min(self.head)).contains(&log.block)
+                || !matches(&filter["address"], format!("{:#x}", log.address))
+                || topics
+                    .iter()
+                    .enumerate()
+                    .any(|(t, topic)| match log.topics.get(t) {
+                        Some(value) => !matches(topic, format!("{value:#x}")),
+                        None => !topic.is_null(),
+                    })
+            {
+                continue;
+            }
+            logs.push(json!({
+                "address": format!("{:#x}", log.address),
+                "topics": log.topics,
+                "data": Bytes::from(log.data.clone()),
+                "blockNumber": format!("{:#x}", log.block),
+                "blockHash": format!("{:#x}", self.block_hash(log.block)),
+                "transactionHash": format!("{:#x}", H256(keccak256(i.to_be_bytes()))),
+                "transactionIndex": "0x0",
+                "logIndex": format!("{:#x}", *index - 1),
+                "removed": false,
+            }));
+        }
+        logs
+    }
+
+    /// Tokens with symbol TKN, 18 decimals, zero balances and allowances
+    /// of approvals at the block. Calls to other contracts are left
+    /// to the fallback.
+    fn call(&self, call: &Value, block: u64) -> Option<Reply> {
+        let to: Address = serde_json::from_value(call["to"].clone()).ok()?;
+        let data: Bytes = serde_json::from_value(call["data"].clone()).ok()?;
+        let is_token = self.logs.iter().any(|log| log.address == to);
+        if !is_token || data.len() < 4 {
+            return None;
+        }
+        let output = match data[..4] {
+            // symbol()
+            [0x95, 0xd8, 0x9b, 0x41] => Token::String("TKN".into()),
+            // decimals()
+            [0x31, 0x3c, 0xe5, 0x67] => Token::Uint(18.into()),
+            // balanceOf(owner)
+            [0x70, 0xa0, 0x82, 0x31] => Token::Uint(U256::zero()),
+            // allowance(owner, spender)
+            [0xdd, 0x62, 0xed, 0x3e] => {
+                let owner = Address::from_slice(&data[16..36]);
+                let spender = Address::from_slice(&data[48..68]);
+                let allowance = self
+                    .allowances
+                    .get(&(to, owner, spender))
+                    .and_then(|history| {
+                        history
+                            .iter()
+                            .filter(|(set_at, _)| *set_at <= block)
+                            .max_by_key(|(set_at, _)| *set_at)
+                    });
+                Token::Uint(allowance.map(|(_, value)| *value).unwrap_or_default())
+            }
+            _ => return None,
+        };
+        Some(Reply::ok(Bytes::from(encode(&[output]))))
+    }
+}
@@ -0,0 +1,384 @@
+use std::{fmt::Debug, sync::Mutex, time::Duration};
+
+use async_trait::async_trait;
+use ethers::providers::{HttpClientError, JsonRpcClient};
+use instant::Instant;
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::debug;
+
+/// Errors which may go away when the request is repeated
+pub trait RetryableError {
+    fn is_retryable(&self) -> bool;
+}
+
+impl RetryableError for HttpClientError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::ReqwestError(err) => {
+                err.is_timeout()
+                    || err.is_request()
+                    || err
+                        .status()
+                        .is_some_and(|status| status.as_u16() == 429 || status.is_server_error())
+            }
+            // providers report exceeded limits as JSON-RPC errors
+            Self::JsonRpcError(err) => {
+                matches!(err.code, 429 | -32005) || is_rate_limit_message(&err.message)
+            }
+            // gateways reply with plain text or HTML pages when overloaded
+            Self::SerdeJson { text, .. } => text.contains("429") || is_rate_limit_message(text),
+        }
+    }
+}
+
+/// Local nodes do not limit requests, and a broken socket is not reconnected
+#[cfg(not(target_arch = "wasm32"))]
+impl RetryableError for ethers::providers::IpcError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::JsonRpcError(err) => {
+                matches!(err.code, 429 | -32005) || is_rate_limit_message(&err.message)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Same as IPC, closed connections are not reopened
+#[cfg(feature = "ws")]
+impl RetryableError for ethers::providers::WsClientError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::JsonRpcError(err) => {
+                matches!(err.code, 429 | -32005) || is_rate_limit_message(&err.message)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn is_rate_limit_message(message: &str) -> bool {
+    let message = message.to_lowercase();
+    ["rate limit", "too many requests"]
+        .iter()
+        .any(|pattern| message.contains(pattern))
+}
+
+/// How to retry failed requests
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt, zero disables retries
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled with each next one
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with random jitter in `[delay / 2, delay]`,
+    /// so concurrent requests do not retry all at once
+    pub(crate) fn backoff(&self, retry: u32) -> Duration {
+        let delay = self
+            .initial_backoff
+            .saturating_mul(1 << retry.min(16))
+            .min(self.max_backoff);
+        delay.mul_f64(0.5 + fastrand::f64() / 2.0)
+    }
+}
+
+/// Max sustained request rate. Bursts up to one second worth
+/// of requests are allowed.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RateLimit {
+    pub requests_per_second: Option<f64>,
+    /// Compute units as billed by providers, see [`compute_units`]
+    pub compute_units_per_second: Option<f64>,
+}
+
+/// Approximate cost of the method in provider compute units
+pub fn compute_units(method: &str) -> f64 {
+    match method {
+        "eth_chainId" | "net_version" => 0.0,
+        "eth_blockNumber" => 10.0,
+        "eth_getBlockByNumber" | "eth_getBlockByHash" => 16.0,
+        "eth_getTransactionByHash" | "eth_getStorageAt" => 17.0,
+        "eth_getProof" => 21.0,
+        "eth_call" | "eth_getCode" | "eth_getBalance" => 26.0,
+        "eth_getLogs" => 75.0,
+        "eth_getBlockReceipts" => 500.0,
+        _ => 20.0,
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: f64) -> Self {
+        // rates below one request per second still need to fit one
+        let capacity = rate.max(1.0);
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Takes tokens if there are enough of them, otherwise returns
+    /// how long to wait until there are
+    fn take(&mut self, cost: f64) -> Result<(), Duration> {
+        let now = Instant::now();
+        self.tokens =
+            (self.tokens + (now - self.updated).as_secs_f64() * self.rate).min(self.capacity);
+        self.updated = now;
+        // costs over the capacity would never fit
+        let cost = cost.min(self.capacity);
+        if self.tokens >= cost {
+            self.tokens -= cost;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((cost - self.tokens) / self.rate))
+        }
+    }
+}
+
+/// Transport wrapper retrying transient failures with exponential
+/// backoff and keeping requests under the rate limit
+#[derive(Debug)]
+pub struct Throttle<C> {
+    inner: C,
+    policy: RetryPolicy,
+    requests: Option<Mutex<Bucket>>,
+    compute_units: Option<Mutex<Bucket>>,
+}
+
+impl<C> Throttle<C> {
+    pub fn new(inner: C, policy: RetryPolicy, limit: RateLimit) -> Self {
+        let bucket = |rate: Option<f64>| {
+            rate.filter(|rate| *rate > 0.0)
+                .map(|rate| Mutex::new(Bucket::new(rate)))
+        };
+        Self {
+            inner,
+            policy,
+            requests: bucket(limit.requests_per_second),
+            compute_units: bucket(limit.compute_units_per_second),
+        }
+    }
+
+    async fn acquire(&self, method: &str) {
+        for (bucket, cost) in [
+            (&self.requests, 1.0),
+            (&self.compute_units, compute_units(method)),
+        ] {
+            let Some(bucket) = bucket else {
+                continue;
+            };
+            loop {
+                let wait = bucket.lock().unwrap().take(cost);
+                match wait {
+                    Ok(()) => break,
+                    Err(wait) => sleep(wait).await,
+                }
+            }
+        }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<C> JsonRpcClient for Throttle<C>
+where
+    C: JsonRpcClient,
+    C::Error: RetryableError,
+{
+    type Error = C::Error;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned,
+    {
+        let mut retry = 0;
+        loop {
+            self.acquire(method).await;
+            // the response may be not `Send`, so it should be gone before the next await
+            match self.inner.request(method, &params).await {
+                Err(err) if err.is_retryable() && retry < self.policy.max_retries => {
+                    debug!(method, retry, error = %err, "retrying request");
+                }
+                res => return res,
+            };
+            sleep(self.policy.backoff(retry)).await;
+            retry += 1;
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn sleep(duration: Duration) {
+    futures_timer::Delay::new(duration).await
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn sleep(duration: Duration) {
+    // the timer can only fail if the browser is gone
+    let _ = wasm_timer::Delay::new(duration).await;
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use ethers::{providers::Http, types::U64};
+
+    use super::*;
+    use crate::testing::{http_node, Node, Reply};
+
+    const POLICY: RetryPolicy = RetryPolicy {
+        max_retries: 3,
+        initial_backoff: Duration::from_millis(20),
+        max_backoff: Duration::from_millis(40),
+    };
+
+    fn throttle(node: &Node, policy: RetryPolicy, limit: RateLimit) -> Throttle<Http> {
+        Throttle::new(Http::new(node.url.clone()), policy, limit)
+    }
+
+    async fn block_number(throttle: &Throttle<Http>) -> Result<U64, HttpClientError> {
+        throttle.request("eth_blockNumber", ()).await
+    }
+
+    /// Node failing the first `failures` requests with the reply
+    async fn flaky_node(failures: usize, reply: Reply) -> Node {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        http_node(move |_, _| {
+            if attempts.fetch_add(1, Ordering::Relaxed) < failures {
+                reply.clone()
+            } else {
+                Reply::ok("0x1")
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn retries_rate_limited_requests() {
+        for reply in [
+            Reply::Status(429),
+            Reply::Error(-32005, "limit exceeded"),
+            Reply::Error(-32000, "Too Many Requests, slow down"),
+        ] {
+            let node = flaky_node(2, reply).await;
+            let throttle = throttle(&node, POLICY, RateLimit::default());
+            assert_eq!(block_number(&throttle).await.unwrap(), 1.into());
+            assert_eq!(node.count("eth_blockNumber"), 3);
+        }
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries_with_backoff() {
+        let node = http_node(|_, _| Reply::Error(429, "rate limited")).await;
+        let throttle = throttle(&node, POLICY, RateLimit::default());
+
+        let started = Instant::now();
+        assert!(block_number(&throttle).await.is_err());
+        assert_eq!(node.count("eth_blockNumber"), 4);
+        // backoffs of 20, 40 and 40 ms, each at least halved by jitter
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_other_errors() {
+        for reply in [Reply::Error(3, "execution reverted"), Reply::Status(404)] {
+            let node = flaky_node(1, reply).await;
+            let throttle = throttle(&node, POLICY, RateLimit::default());
+            assert!(block_number(&throttle).await.is_err());
+            assert_eq!(node.count("eth_blockNumber"), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_requests_under_compute_units_rate() {
+        let node = http_node(|_, _| Reply::ok(Vec::<()>::new())).await;
+        let limit = RateLimit {
+            requests_per_second: None,
+            compute_units_per_second: Some(100.0),
+        };
+        let throttle = throttle(&node, RetryPolicy::default(), limit);
+
+        let started = Instant::now();
+        for _ in 0..2 {
+            let _: Vec<()> = throttle.request("eth_getLogs", ()).await.unwrap();
+        }
+        // the burst fits one request of 75 units, the second one
+        // waits for 50 more units to be refilled
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(450), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(1500), "{elapsed:?}");
+    }
+
+    #[test]
+    fn bucket_refills_at_rate_up_to_capacity() {
+        let mut bucket = Bucket::new(2.0);
+        assert_eq!(bucket.take(1.0), Ok(()));
+        assert_eq!(bucket.take(1.0), Ok(()));
+        let wait = bucket.take(1.0).unwrap_err();
+        assert!(wait <= Duration::from_millis(500) && wait > Duration::from_millis(400));
+
+        // idle time does not add tokens over the capacity
+        bucket.tokens = 0.0;
+        bucket.updated -= Duration::from_secs(10);
+        assert_eq!(bucket.take(2.0), Ok(()));
+        assert!(bucket.take(1.0).is_err());
+    }
+
+    #[test]
+    fn bucket_fits_costs_over_capacity() {
+        // a single request always fits, even below one per second
+        let mut bucket = Bucket::new(0.5);
+        assert_eq!(bucket.capacity, 1.0);
+        assert_eq!(bucket.take(1.0), Ok(()));
+        let wait = bucket.take(1.0).unwrap_err();
+        assert!(wait <= Duration::from_secs(2) && wait > Duration::from_millis(1900));
+
+        let mut bucket = Bucket::new(100.0);
+        assert_eq!(bucket.take(compute_units("eth_getBlockReceipts")), Ok(()));
+        assert!(bucket.take(1.0).is_err());
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max_with_jitter() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+        };
+        for (retry, delay) in [(0, 100), (1, 200), (3, 800), (4, 1000), (40, 1000)] {
+            let delay = Duration::from_millis(delay);
+            for _ in 0..20 {
+                let backoff = policy.backoff(retry);
+                assert!(backoff >= delay / 2 && backoff <= delay, "{backoff:?}");
+            }
+        }
+    }
+}
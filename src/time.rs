@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use itertools::Itertools;
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+const YEAR: u64 = 365 * DAY;
+
+const UNITS: [(&str, u64); 6] = [
+    ("y", YEAR),
+    ("w", WEEK),
+    ("d", DAY),
+    ("h", HOUR),
+    ("m", MINUTE),
+    ("s", 1),
+];
+
+/// Parses durations like `90s`, `12h`, `30d` or `1y6w`
+pub fn parse_duration(s: &str) -> anyhow::Result<Duration> {
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return Err(anyhow!("empty duration"));
+    }
+    let mut secs = 0u64;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let n: u64 = rest[..digits]
+            .parse()
+            .with_context(|| format!("invalid duration: {s}"))?;
+        rest = &rest[digits..];
+        let unit = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (_, mul) = UNITS
+            .iter()
+            .find(|(name, _)| *name == &rest[..unit])
+            .ok_or_else(|| {
+                anyhow!("invalid duration unit in {s}, expected one of: y, w, d, h, m, s")
+            })?;
+        secs = n
+            .checked_mul(*mul)
+            .and_then(|n| secs.checked_add(n))
+            .ok_or_else(|| anyhow!("duration is too long: {s}"))?;
+        rest = &rest[unit..];
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// Human readable rounded duration, i.e. `1y 1w`
+pub fn format_duration(d: Duration) -> String {
+    let mut secs = d.as_secs();
+    let parts: Vec<_> = UNITS
+        .iter()
+        .filter_map(|(name, mul)| {
+            let n = secs / mul;
+            secs %= mul;
+            (n > 0).then(|| format!("{n}{name}"))
+        })
+        .take(2)
+        .collect();
+    if parts.is_empty() {
+        return "0s".to_string();
+    }
+    parts.into_iter().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(
+            parse_duration(" 12h ").unwrap(),
+            Duration::from_secs(12 * HOUR)
+        );
+        assert_eq!(
+            parse_duration("1y6w").unwrap(),
+            Duration::from_secs(YEAR + 6 * WEEK)
+        );
+        assert_eq!(parse_duration("0m").unwrap(), Duration::ZERO);
+        for invalid in ["", "30", "d", "30x", "1d2", "-1d", "99999999999999y"] {
+            assert!(parse_duration(invalid).is_err(), "{invalid:?} should fail");
+        }
+    }
+
+    #[test]
+    fn formats_two_largest_units() {
+        assert_eq!(format_duration(Duration::ZERO), "0s");
+        assert_eq!(format_duration(Duration::from_secs(90)), "1m 30s");
+        assert_eq!(format_duration(Duration::from_secs(30 * DAY)), "4w 2d");
+        assert_eq!(
+            format_duration(Duration::from_secs(YEAR + 12 * DAY + 5 * HOUR)),
+            "1y 1w"
+        );
+        assert_eq!(format_duration(parse_duration("2d3h").unwrap()), "2d 3h");
+    }
+}
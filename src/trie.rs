@@ -0,0 +1,397 @@
+//! Minimal Merkle Patricia trie, enough to rebuild roots of
+//! per-block tries from their contents and to check state proofs.
+
+use ethers::{
+    types::H256,
+    utils::{
+        keccak256,
+        rlp::{self, Rlp, RlpStream},
+    },
+};
+
+/// Root of the trie keyed by RLP-encoded indices of values,
+/// as transactions and receipts tries are
+pub(crate) fn ordered_root(values: impl IntoIterator<Item = Vec<u8>>) -> H256 {
+    root(
+        values
+            .into_iter()
+            .enumerate()
+            .map(|(i, value)| (rlp::encode(&i).to_vec(), value)),
+    )
+}
+
+fn root(items: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>) -> H256 {
+    let mut items: Vec<_> = items
+        .into_iter()
+        .map(|(key, value)| (nibbles(&key), value))
+        .collect();
+    items.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    keccak256(encode_node(&items, 0)).into()
+}
+
+/// Follows the path of `keccak(key)` from the root through proof nodes,
+/// as returned by `eth_getProof`. Returns the value at the key, or None
+/// if the proof shows there is no such key.
+pub(crate) fn verify_proof(
+    root: H256,
+    key: &[u8],
+    proof: &[impl AsRef<[u8]>],
+) -> Result<Option<Vec<u8>>, &'static str> {
+    if root == keccak256(rlp::NULL_RLP).into() {
+        return Ok(None);
+    }
+    let key = nibbles(&keccak256(key));
+    let mut path = &key[..];
+    let mut proof = proof.iter();
+    let mut next = NodeRef::Hash(root);
+    loop {
+        let node = match next {
+            NodeRef::Hash(hash) => {
+                let node = proof.next().ok_or("proof is too short")?.as_ref();
+                if keccak256(node) != hash.0 {
+                    return Err("proof node does not match its hash");
+                }
+                node.to_vec()
+            }
+            NodeRef::Inline(node) => node,
+        };
+        let node = Rlp::new(&node);
+        let child = match node.item_count().map_err(|_| "invalid proof node")? {
+            17 => {
+                let Some((&nibble, rest)) = path.split_first() else {
+                    return Ok(data(&node.at(16).map_err(|_| "invalid branch node")?));
+                };
+                path = rest;
+                node.at(nibble as usize)
+                    .map_err(|_| "invalid branch node")?
+            }
+            2 => {
+                let encoded = node
+                    .at(0)
+                    .and_then(|p| p.data().map(<[u8]>::to_vec))
+                    .map_err(|_| "invalid proof node")?;
+                let (node_path, leaf) = decode_hex_prefix(&encoded).ok_or("invalid node path")?;
+                let Some(rest) = path.strip_prefix(&node_path[..]) else {
+                    return Ok(None);
+                };
+                path = rest;
+                let child = node.at(1).map_err(|_| "invalid proof node")?;
+                if leaf {
+                    return Ok(if path.is_empty() { data(&child) } else { None });
+                }
+                child
+            }
+            _ => return Err("invalid proof node"),
+        };
+        next = match (child.is_list(), child.data()) {
+            (true, _) => NodeRef::Inline(child.as_raw().to_vec()),
+            (false, Ok([])) => return Ok(None),
+            (false, Ok(hash)) if hash.len() == 32 => NodeRef::Hash(H256::from_slice(hash)),
+            _ => return Err("invalid child reference"),
+        };
+    }
+}
+
+enum NodeRef {
+    Hash(H256),
+    /// nodes shorter than a hash are embedded into their parent
+    Inline(Vec<u8>),
+}
+
+/// Non-empty string item
+fn data(item: &Rlp) -> Option<Vec<u8>> {
+    item.data()
+        .ok()
+        .filter(|data| !data.is_empty())
+        .map(<[u8]>::to_vec)
+}
+
+fn decode_hex_prefix(encoded: &[u8]) -> Option<(Vec<u8>, bool)> {
+    let (&first, rest) = encoded.split_first()?;
+    let flag = first >> 4;
+    let mut path = Vec::with_capacity(rest.len() * 2 + 1);
+    if flag & 1 == 1 {
+        path.push(first & 0x0f);
+    }
+    path.extend(nibbles(rest));
+    (flag < 4).then_some((path, flag & 2 == 2))
+}
+
+fn nibbles(key: &[u8]) -> Vec<u8> {
+    key.iter().flat_map(|b| [b >> 4, b & 0x0f]).collect()
+}
+
+/// Compact encoding of the path with leaf flag
+fn hex_prefix(path: &[u8], leaf: bool) -> Vec<u8> {
+    let flag = if leaf { 2 } else { 0 } + (path.len() % 2) as u8;
+    let (first, rest) = match path.len() % 2 {
+        1 => (flag << 4 | path[0], &path[1..]),
+        _ => (flag << 4, path),
+    };
+    [first]
+        .into_iter()
+        .chain(rest.chunks(2).map(|pair| pair[0] << 4 | pair[1]))
+        .collect()
+}
+
+/// RLP of the node holding given sorted items, whose keys
+/// are equal up to `depth` nibbles
+fn encode_node(items: &[(Vec<u8>, Vec<u8>)], depth: usize) -> Vec<u8> {
+    let mut s = RlpStream::new();
+    match items {
+        [] => {
+            s.append_empty_data();
+        }
+        [(key, value)] => {
+            s.begin_list(2);
+            s.append(&hex_prefix(&key[depth..], true));
+            s.append(value);
+        }
+        [(first, _), .., (last, _)] => {
+            // items are sorted, so the first and the last ones
+            // share the shortest common prefix
+            let shared = first[depth..]
+                .iter()
+                .zip(&last[depth..])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if shared > 0 {
+                s.begin_list(2);
+                s.append(&hex_prefix(&first[depth..depth + shared], false));
+                append_child(&mut s, encode_node(items, depth + shared));
+            } else {
+                s.begin_list(17);
+                let (value, mut rest) = match items.split_first() {
+                    Some(((key, value), rest)) if key.len() == depth => (Some(value), rest),
+                    _ => (None, items),
+                };
+                for nibble in 0..16 {
+                    let n = rest
+                        .iter()
+                        .take_while(|(key, _)| key[depth] == nibble)
+                        .count();
+                    let (children, tail) = rest.split_at(n);
+                    rest = tail;
+                    match children {
+                        [] => {
+                            s.append_empty_data();
+                        }
+                        children => append_child(&mut s, encode_node(children, depth + 1)),
+                    }
+                }
+                match value {
+                    Some(value) => s.append(value),
+                    None => s.append_empty_data(),
+                };
+            }
+        }
+    }
+    s.out().to_vec()
+}
+
+/// Nodes shorter than a hash are embedded into their parent
+fn append_child(s: &mut RlpStream, node: Vec<u8>) {
+    if node.len() < 32 {
+        s.append_raw(&node, 1);
+    } else {
+        s.append(&keccak256(&node).as_slice());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::Bytes;
+
+    use super::*;
+
+    const EMPTY_ROOT: &str = "0x56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421";
+
+    fn hash(hex: &str) -> H256 {
+        hex.parse().unwrap()
+    }
+
+    fn root_of(items: &[(&str, &str)]) -> H256 {
+        root(
+            items
+                .iter()
+                .map(|(key, value)| (key.as_bytes().to_vec(), value.as_bytes().to_vec())),
+        )
+    }
+
+    /// Proof of `key` in the trie of `items`, which are sorted and keyed
+    /// by nibbles, as `eth_getProof` would return it. Every node is
+    /// expected to be referenced by hash.
+    fn proof(items: &[(Vec<u8>, Vec<u8>)], key: &[u8], depth: usize) -> Vec<Vec<u8>> {
+        let mut nodes = vec![encode_node(items, depth)];
+        if let [(first, _), .., (last, _)] = items {
+            let shared = first[depth..]
+                .iter()
+                .zip(&last[depth..])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if shared > 0 {
+                nodes.extend(proof(items, key, depth + shared));
+            } else {
+                let children: Vec<_> = items
+                    .iter()
+                    .filter(|(k, _)| k[depth] == key[depth])
+                    .cloned()
+                    .collect();
+                if !children.is_empty() {
+                    nodes.extend(proof(&children, key, depth + 1));
+                }
+            }
+        }
+        nodes
+    }
+
+    // vectors of trieanyorder.json in ethereum/tests
+    #[test]
+    fn computes_roots_of_test_vectors() {
+        assert_eq!(root(None), hash(EMPTY_ROOT));
+        assert_eq!(
+            root_of(&[("A", &"a".repeat(50))]),
+            hash("0xd23786fb4a010da3ce639d66d5e904a11dbc02746d1ce25029e53290cabf28ab")
+        );
+        assert_eq!(
+            root_of(&[
+                ("doe", "reindeer"),
+                ("dog", "puppy"),
+                ("dogglesworth", "cat")
+            ]),
+            hash("0x8aad789dff2f538bca5d8ea56e8abe10f4c7ba3a5dea95fea4cd6e7c3a1168d3")
+        );
+        assert_eq!(
+            root_of(&[
+                ("do", "verb"),
+                ("horse", "stallion"),
+                ("doge", "coin"),
+                ("dog", "puppy")
+            ]),
+            hash("0x5991bb8c6514148a29db676a14ac506cd2cd5775ace63c30a4fe457715e9ac84")
+        );
+        assert_eq!(
+            root_of(&[("be", "e"), ("dog", "puppy"), ("bed", "d")]),
+            hash("0x3f67c7a47520f79faa29255d2d3c084a7a6df0453116ed7232ff10277a8be68b")
+        );
+        assert_eq!(
+            root_of(&[("test", "test"), ("te", "testy")]),
+            hash("0x8452568af70d8d140f58d941338542f645fcca50094b20f3c3d8c3df49337928")
+        );
+        assert_eq!(
+            root([
+                (vec![0x00, 0x45], vec![0x01, 0x23, 0x45, 0x67, 0x89]),
+                (vec![0x45, 0x00], vec![0x98, 0x76, 0x54, 0x32, 0x10]),
+            ]),
+            hash("0x285505fcabe84badc8aa310e2aae17eddc7d120aabec8a476902c8184b3a3503")
+        );
+    }
+
+    #[test]
+    fn ordered_root_keys_values_by_index() {
+        assert_eq!(ordered_root(None), hash(EMPTY_ROOT));
+        let values = || ["zero", "one", "two"].map(|v| v.as_bytes().to_vec());
+        assert_eq!(
+            ordered_root(values()),
+            root(
+                values()
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, v)| (rlp::encode(&i).to_vec(), v))
+            )
+        );
+        // index 0 is encoded as the empty string, not as a zero byte
+        assert_eq!(
+            ordered_root(values().into_iter().take(1)),
+            root([(vec![0x80], values()[0].clone())])
+        );
+    }
+
+    // examples of the Ethereum yellow paper, appendix C
+    #[test]
+    fn hex_prefix_round_trips() {
+        for (path, leaf, encoded) in [
+            (&[1, 2, 3, 4, 5][..], false, &[0x11, 0x23, 0x45][..]),
+            (&[0, 1, 2, 3, 4, 5], false, &[0x00, 0x01, 0x23, 0x45]),
+            (&[0, 0xf, 1, 0xc, 0xb, 8], true, &[0x20, 0x0f, 0x1c, 0xb8]),
+            (&[0xf, 1, 0xc, 0xb, 8], true, &[0x3f, 0x1c, 0xb8]),
+            (&[], true, &[0x20]),
+        ] {
+            assert_eq!(hex_prefix(path, leaf), encoded);
+            assert_eq!(decode_hex_prefix(encoded), Some((path.to_vec(), leaf)));
+        }
+        assert_eq!(decode_hex_prefix(&[]), None);
+        assert_eq!(decode_hex_prefix(&[0x40]), None);
+    }
+
+    #[test]
+    fn encodes_nodes() {
+        assert_eq!(encode_node(&[], 0), rlp::NULL_RLP);
+        // leaf of "do" => "verb"
+        let leaf = encode_node(&[(nibbles(b"do"), b"verb".to_vec())], 0);
+        assert_eq!(leaf, b"\xc9\x83\x20do\x84verb");
+        // below the shared prefix, the path is relative to the depth
+        let leaf = encode_node(&[(nibbles(b"do"), b"verb".to_vec())], 2);
+        assert_eq!(leaf, b"\xc8\x82\x20o\x84verb");
+
+        // "do" and "dog" share the whole path of "do", so the extension
+        // leads to a branch holding "verb" as its value and "dog" inlined
+        let items = [
+            (nibbles(b"do"), b"verb".to_vec()),
+            (nibbles(b"dog"), b"puppy".to_vec()),
+        ];
+        let branch = encode_node(&items, 4);
+        let rlp = Rlp::new(&branch);
+        assert_eq!(rlp.item_count().unwrap(), 17);
+        assert_eq!(rlp.val_at::<Vec<u8>>(16).unwrap(), b"verb");
+        assert!(rlp.at(6).unwrap().is_list());
+        let extension = encode_node(&items, 0);
+        let rlp = Rlp::new(&extension);
+        assert_eq!(
+            rlp.val_at::<Vec<u8>>(0).unwrap(),
+            hex_prefix(&nibbles(b"do"), false)
+        );
+        assert_eq!(rlp.at(1).unwrap().as_raw(), branch);
+    }
+
+    #[test]
+    fn verifies_proofs() {
+        let value = |i: u8| rlp::encode(&H256::repeat_byte(i).as_bytes()).to_vec();
+        let mut items: Vec<_> = (1..=20u8)
+            .map(|i| (nibbles(&keccak256([i])), value(i)))
+            .collect();
+        items.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        let root = H256::from(keccak256(encode_node(&items, 0)));
+
+        for i in 1..=20u8 {
+            let key = nibbles(&keccak256([i]));
+            let proof = proof(&items, &key, 0);
+            assert_eq!(verify_proof(root, &[i], &proof), Ok(Some(value(i))));
+            assert_eq!(
+                verify_proof(root, &[i], &proof[..proof.len() - 1]),
+                Err("proof is too short")
+            );
+        }
+
+        // absence is proven by the path to where the key would be
+        let missing = nibbles(&keccak256([0xff]));
+        assert_eq!(
+            verify_proof(root, &[0xff], &proof(&items, &missing, 0)),
+            Ok(None)
+        );
+
+        let mut tampered = proof(&items, &items[0].0, 0);
+        let last = tampered.last_mut().unwrap();
+        *last.last_mut().unwrap() ^= 1;
+        let key = (1..=20u8)
+            .find(|i| nibbles(&keccak256([*i])) == items[0].0)
+            .unwrap();
+        assert_eq!(
+            verify_proof(root, &[key], &tampered),
+            Err("proof node does not match its hash")
+        );
+
+        let empty: [Bytes; 0] = [];
+        assert_eq!(verify_proof(hash(EMPTY_ROOT), b"any", &empty), Ok(None));
+    }
+}
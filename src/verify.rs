@@ -0,0 +1,558 @@
+//! Verification of logs reported by the node against block headers,
+//! so that the node does not have to be trusted.
+//!
+//! A log is verified when the header of its block hashes to the reported
+//! block hash, receipts of the block rebuild the header's `receiptsRoot`,
+//! and the log is found at the reported position in those receipts.
+//! Optionally, the block is checked to be an ancestor of a trusted
+//! checkpoint block by following parent hashes down from the checkpoint.
+
+use std::{collections::HashMap, fmt::Display, sync::Arc};
+
+use ethers::{
+    abi::RawLog,
+    contract::{EthLogDecode, LogMeta},
+    providers::Middleware,
+    types::{
+        Address, Block, BlockId, BlockNumber, Bloom, Bytes, Transaction, TransactionReceipt, H256,
+        H64, U256, U64,
+    },
+    utils::{keccak256, rlp::RlpStream},
+};
+use futures::{
+    lock::Mutex,
+    stream::{self, StreamExt, TryStreamExt},
+};
+use serde::de::DeserializeOwned;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{blocks::MAX_CONCURRENT_HEADERS, cached::CachedMap, error::Error, trie::ordered_root};
+
+/// How much of the reported log was verified
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+pub enum Verification {
+    /// Verification was not requested
+    #[default]
+    Skipped,
+    /// Log is included in the receipts of the block with the reported hash
+    Receipts,
+    /// In addition, the block is an ancestor of the trusted checkpoint
+    Checkpoint,
+    /// Node reported data which does not add up
+    Failed(Arc<str>),
+}
+
+impl Verification {
+    pub fn is_verified(&self) -> bool {
+        matches!(self, Self::Receipts | Self::Checkpoint)
+    }
+}
+
+impl Display for Verification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Skipped => write!(f, "not verified"),
+            Self::Receipts => write!(f, "verified by receipts root"),
+            Self::Checkpoint => write!(f, "verified up to checkpoint"),
+            Self::Failed(reason) => write!(f, "VERIFICATION FAILED: {reason}"),
+        }
+    }
+}
+
+/// Header fields in the order they are hashed. Fields introduced
+/// by forks are only hashed if present.
+#[derive(Debug)]
+pub(crate) struct Header {
+    pub parent_hash: H256,
+    sha3_uncles: H256,
+    miner: Address,
+    pub state_root: H256,
+    transactions_root: H256,
+    pub receipts_root: H256,
+    logs_bloom: Bloom,
+    difficulty: U256,
+    pub number: U64,
+    gas_limit: U256,
+    gas_used: U256,
+    timestamp: U256,
+    extra_data: Bytes,
+    mix_hash: Option<H256>,
+    nonce: Option<H64>,
+    base_fee_per_gas: Option<U256>,
+    withdrawals_root: Option<H256>,
+    blob_gas_used: Option<U64>,
+    excess_blob_gas: Option<U64>,
+    parent_beacon_block_root: Option<H256>,
+    requests_hash: Option<H256>,
+}
+
+impl Header {
+    pub fn hash(&self) -> H256 {
+        let mut s = RlpStream::new();
+        s.begin_unbounded_list();
+        s.append(&self.parent_hash)
+            .append(&self.sha3_uncles)
+            .append(&self.miner)
+            .append(&self.state_root)
+            .append(&self.transactions_root)
+            .append(&self.receipts_root)
+            .append(&self.logs_bloom)
+            .append(&self.difficulty)
+            .append(&self.number)
+            .append(&self.gas_limit)
+            .append(&self.gas_used)
+            .append(&self.timestamp)
+            .append(&self.extra_data.as_ref());
+        if let Some(mix_hash) = &self.mix_hash {
+            s.append(mix_hash);
+        }
+        if let Some(nonce) = &self.nonce {
+            s.append(nonce);
+        }
+        if let Some(base_fee) = &self.base_fee_per_gas {
+            s.append(base_fee);
+        }
+        if let Some(withdrawals_root) = &self.withdrawals_root {
+            s.append(withdrawals_root);
+        }
+        if let Some(blob_gas_used) = &self.blob_gas_used {
+            s.append(blob_gas_used);
+        }
+        if let Some(excess_blob_gas) = &self.excess_blob_gas {
+            s.append(excess_blob_gas);
+        }
+        if let Some(root) = &self.parent_beacon_block_root {
+            s.append(root);
+        }
+        if let Some(requests_hash) = &self.requests_hash {
+            s.append(requests_hash);
+        }
+        s.finalize_unbounded_list();
+        keccak256(s.out()).into()
+    }
+}
+
+/// Fields of forks after London are not known to ethers,
+/// so they are taken from the other fields of the block
+impl TryFrom<Block<H256>> for Header {
+    type Error = Error;
+
+    fn try_from(block: Block<H256>) -> Result<Self, Self::Error> {
+        let missing = |field: &str| Error::rpc(format!("block has no {field}"));
+        fn other<T: DeserializeOwned>(
+            block: &Block<H256>,
+            field: &str,
+        ) -> Result<Option<T>, Error> {
+            block
+                .other
+                .get_deserialized(field)
+                .transpose()
+                .map_err(|err| Error::rpc(format!("invalid {field} of block: {err}")))
+        }
+        Ok(Self {
+            parent_hash: block.parent_hash,
+            sha3_uncles: block.uncles_hash,
+            miner: block.author.ok_or_else(|| missing("miner"))?,
+            state_root: block.state_root,
+            transactions_root: block.transactions_root,
+            receipts_root: block.receipts_root,
+            logs_bloom: block.logs_bloom.ok_or_else(|| missing("logs bloom"))?,
+            difficulty: block.difficulty,
+            number: block.number.ok_or_else(|| missing("number"))?,
+            gas_limit: block.gas_limit,
+            gas_used: block.gas_used,
+            timestamp: block.timestamp,
+            extra_data: block.extra_data.clone(),
+            mix_hash: block.mix_hash,
+            nonce: block.nonce,
+            base_fee_per_gas: block.base_fee_per_gas,
+            withdrawals_root: other(&block, "withdrawalsRoot")?,
+            blob_gas_used: other(&block, "blobGasUsed")?,
+            excess_blob_gas: other(&block, "excessBlobGas")?,
+            parent_beacon_block_root: other(&block, "parentBeaconBlockRoot")?,
+            requests_hash: other(&block, "requestsHash")?,
+        })
+    }
+}
+
+/// Type of OP Stack deposit transactions
+const DEPOSIT_TX_TYPE: u64 = 0x7e;
+
+fn is_deposit(receipt: &TransactionReceipt) -> bool {
+    receipt.transaction_type == Some(DEPOSIT_TX_TYPE.into())
+}
+
+/// Fields which the receipt of an OP Stack deposit transaction commits to:
+/// the nonce since Regolith and the receipt version since Canyon
+fn deposit_fields(tx: &Transaction) -> Result<Vec<U64>, Error> {
+    let version: Option<U64> = tx
+        .other
+        .get_deserialized("depositReceiptVersion")
+        .transpose()
+        .map_err(|err| Error::rpc(format!("invalid depositReceiptVersion of deposit: {err}")))?;
+    Ok([tx.nonce.low_u64().into()]
+        .into_iter()
+        .chain(version)
+        .collect())
+}
+
+/// Consensus encoding of the receipt, as it is stored in the receipts trie.
+/// Deposit receipts of OP Stack chains also commit to the deposit fields.
+fn encode_receipt(receipt: &TransactionReceipt, deposit: &[U64]) -> Vec<u8> {
+    let deposit = if is_deposit(receipt) { deposit } else { &[] };
+    let mut s = RlpStream::new_list(4 + deposit.len());
+    match (receipt.status, receipt.root) {
+        (Some(status), _) => s.append(&status),
+        // before Byzantium receipts held post-transaction state root
+        (None, root) => s.append(&root.unwrap_or_default()),
+    };
+    s.append(&receipt.cumulative_gas_used)
+        .append(&receipt.logs_bloom)
+        .append_list(&receipt.logs);
+    for value in deposit {
+        s.append(value);
+    }
+    match receipt.transaction_type.map(|t| t.as_u64()) {
+        None | Some(0) => s.out().to_vec(),
+        Some(t) => [t as u8].into_iter().chain(s.out()).collect(),
+    }
+}
+
+/// Receipts of the block whose header was verified
+struct VerifiedBlock {
+    number: U64,
+    receipts: Vec<TransactionReceipt>,
+}
+
+/// Verified chain of block hashes down from the checkpoint
+struct Ancestors {
+    /// number of the checkpoint block
+    number: U64,
+    /// hash of the block `number - i` at index `i`
+    hashes: Vec<H256>,
+    /// parent hash of the last block in `hashes`
+    parent: H256,
+}
+
+pub(crate) struct Verifier<M: Middleware> {
+    client: Arc<M>,
+    checkpoint: Option<H256>,
+    /// verified blocks, or the reason verification failed
+    blocks: CachedMap<H256, Result<Arc<VerifiedBlock>, Arc<str>>>,
+    /// loaded on first use
+    ancestors: Mutex<Option<Ancestors>>,
+}
+
+impl<M: Middleware + 'static> Verifier<M> {
+    pub fn new(client: impl Into<Arc<M>>, checkpoint: Option<H256>) -> Self {
+        Self {
+            client: client.into(),
+            checkpoint,
+            blocks: CachedMap::new("verified_blocks"),
+            ancestors: Mutex::new(None),
+        }
+    }
+
+    /// Checks that the log was emitted in the block it was reported in
+    pub async fn try_verify<D: EthLogDecode + PartialEq>(
+        &self,
+        log: &D,
+        meta: &LogMeta,
+    ) -> Result<Verification, Error> {
+        let block = match self.try_get_block(meta.block_hash).await? {
+            Ok(block) => block,
+            Err(reason) => return Ok(Verification::Failed(reason)),
+        };
+        if block.number != meta.block_number {
+            return Ok(Verification::Failed("block number does not match".into()));
+        }
+
+        // log index is counted over the whole block
+        let tx = meta.transaction_index.as_usize();
+        let found = block.receipts.get(tx).and_then(|receipt| {
+            let before: usize = block.receipts[..tx].iter().map(|r| r.logs.len()).sum();
+            let i = meta.log_index.checked_sub(before.into())?;
+            receipt.logs.get(usize::try_from(i).ok()?)
+        });
+        let matches = found.is_some_and(|found| {
+            found.address == meta.address
+                && D::decode_log(&RawLog {
+                    topics: found.topics.clone(),
+                    data: found.data.to_vec(),
+                })
+                .is_ok_and(|decoded| decoded == *log)
+        });
+        if !matches {
+            return Ok(Verification::Failed(
+                "log is not found in block receipts".into(),
+            ));
+        }
+
+        if self.checkpoint.is_none() {
+            return Ok(Verification::Receipts);
+        }
+        Ok(match self.try_get_ancestor(block.number).await? {
+            Some(hash) if hash == meta.block_hash => Verification::Checkpoint,
+            Some(_) => Verification::Failed("block is not an ancestor of the checkpoint".into()),
+            // block is newer than the checkpoint
+            None => Verification::Receipts,
+        })
+    }
+
+    async fn try_get_block(
+        &self,
+        hash: H256,
+    ) -> Result<Result<Arc<VerifiedBlock>, Arc<str>>, Error> {
+        self.blocks
+            .get_or_try_insert_with(hash, || async move {
+                let Some(header) = self.try_get_header(hash).await? else {
+                    return Ok(Err("block is not found".into()));
+                };
+                if header.hash() != hash {
+                    return Ok(Err("header does not match block hash".into()));
+                }
+                let receipts = self
+                    .client
+                    .get_block_receipts(header.number)
+                    .await
+                    .map_err(Error::rpc)?;
+                // the block could be reorged out since the header was fetched
+                if receipts
+                    .iter()
+                    .any(|receipt| receipt.block_hash != Some(hash))
+                {
+                    return Ok(Err("receipts are of another block".into()));
+                }
+                let deposits = self.try_get_deposits(hash, &receipts).await?;
+                let root = |deposits: &HashMap<H256, Vec<U64>>| {
+                    ordered_root(receipts.iter().map(|receipt| {
+                        let fields = deposits.get(&receipt.transaction_hash);
+                        encode_receipt(receipt, fields.map_or(&[], Vec::as_slice))
+                    }))
+                };
+                // deposit receipts before Regolith commit to no other fields,
+                // while their transactions report the nonce as 0 anyway
+                if root(&deposits) != header.receipts_root
+                    && (deposits.is_empty() || root(&HashMap::new()) != header.receipts_root)
+                {
+                    return Ok(Err("receipts do not match receipts root".into()));
+                }
+                Ok(Ok(Arc::new(VerifiedBlock {
+                    number: header.number,
+                    receipts,
+                })))
+            })
+            .await
+    }
+
+    /// Fields which deposit receipts of the block commit to, by transaction hash.
+    /// Typed receipts of ethers drop them, but deposit transactions report
+    /// the nonce and the receipt version of their receipts too.
+    async fn try_get_deposits(
+        &self,
+        hash: H256,
+        receipts: &[TransactionReceipt],
+    ) -> Result<HashMap<H256, Vec<U64>>, Error> {
+        if !receipts.iter().any(is_deposit) {
+            return Ok(HashMap::new());
+        }
+        let block = self
+            .client
+            .get_block_with_txs(hash)
+            .await
+            .map_err(Error::rpc)?
+            .ok_or_else(|| Error::rpc("block of deposits is not found"))?;
+        block
+            .transactions
+            .iter()
+            .filter(|tx| tx.transaction_type == Some(DEPOSIT_TX_TYPE.into()))
+            .map(|tx| Ok((tx.hash, deposit_fields(tx)?)))
+            .collect()
+    }
+
+    async fn try_get_header(&self, block: impl Into<BlockId>) -> Result<Option<Header>, Error> {
+        self.client
+            .get_block(block.into())
+            .await
+            .map_err(Error::rpc)?
+            .map(Header::try_from)
+            .transpose()
+    }
+
+    /// Hash of the checkpoint ancestor with given number, if the
+    /// checkpoint is not older. All headers between are fetched
+    /// and chained by parent hashes.
+    async fn try_get_ancestor(&self, number: U64) -> Result<Option<H256>, Error> {
+        let mut ancestors = self.ancestors.lock().await;
+        let ancestors = match &mut *ancestors {
+            Some(ancestors) => ancestors,
+            None => {
+                let checkpoint = self.checkpoint.expect("checkpoint is set");
+                let header = self
+                    .try_get_header(checkpoint)
+                    .await?
+                    .filter(|header| header.hash() == checkpoint)
+                    .ok_or_else(|| Error::rpc(format!("checkpoint {checkpoint:#x} not found")))?;
+                ancestors.insert(Ancestors {
+                    number: header.number,
+                    hashes: vec![checkpoint],
+                    parent: header.parent_hash,
+                })
+            }
+        };
+        if number > ancestors.number {
+            return Ok(None);
+        }
+
+        let depth = (ancestors.number - number).as_usize();
+        while ancestors.hashes.len() <= depth {
+            let next = ancestors.number.as_u64() - ancestors.hashes.len() as u64;
+            let n = (depth + 1 - ancestors.hashes.len()).min(MAX_CONCURRENT_HEADERS) as u64;
+            let headers: Vec<Option<Header>> = stream::iter(0..n)
+                .map(|i| self.try_get_header(BlockNumber::Number((next - i).into())))
+                .buffered(MAX_CONCURRENT_HEADERS)
+                .try_collect()
+                .await?;
+            for (i, header) in headers.into_iter().enumerate() {
+                match header {
+                    Some(header) if header.hash() == ancestors.parent => {
+                        ancestors.hashes.push(ancestors.parent);
+                        ancestors.parent = header.parent_hash;
+                    }
+                    _ => {
+                        return Err(Error::rpc(format!(
+                            "block {} does not chain to the checkpoint",
+                            next - i as u64
+                        )))
+                    }
+                }
+            }
+        }
+        Ok(Some(ancestors.hashes[depth]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        types::{Bytes, Log},
+        utils::rlp::Rlp,
+    };
+    use serde_json::json;
+
+    use super::*;
+
+    /// Decodes the consensus encoding back into the fields it commits to
+    fn decode_receipt(encoded: &[u8]) -> TransactionReceipt {
+        let (tx_type, payload) = match encoded[0] {
+            t if t < 0x7f => (Some(U64::from(t)), &encoded[1..]),
+            _ => (None, encoded),
+        };
+        let rlp = Rlp::new(payload);
+        let logs = rlp
+            .at(3)
+            .unwrap()
+            .iter()
+            .map(|log| Log {
+                address: log.val_at(0).unwrap(),
+                topics: log.list_at(1).unwrap(),
+                data: log.val_at::<Vec<u8>>(2).unwrap().into(),
+                ..Default::default()
+            })
+            .collect();
+        TransactionReceipt {
+            status: Some(rlp.val_at(0).unwrap()),
+            cumulative_gas_used: rlp.val_at(1).unwrap(),
+            logs_bloom: rlp.val_at(2).unwrap(),
+            logs,
+            transaction_type: tx_type,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rebuilds_mainnet_receipts_root() {
+        // receipts of mainnet block 14764013, which has legacy
+        // and EIP-1559 transactions
+        let encoded: Vec<Bytes> = include_str!("testdata/receipts_14764013.txt")
+            .lines()
+            .map(|line| line.parse().unwrap())
+            .collect();
+        assert_eq!(encoded.len(), 19);
+        let reencoded: Vec<_> = encoded
+            .iter()
+            .map(|encoded| encode_receipt(&decode_receipt(encoded), &[]))
+            .collect();
+        for (encoded, reencoded) in encoded.iter().zip(&reencoded) {
+            assert_eq!(encoded.as_ref(), reencoded);
+        }
+        assert_eq!(
+            ordered_root(reencoded),
+            "0x168a3827607627e781941dc777737fc4b6beb69a8b139240b881992b35b854ea"
+                .parse()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn encodes_pre_byzantium_receipt_with_state_root() {
+        let receipt = TransactionReceipt {
+            root: Some(H256::repeat_byte(0xaa)),
+            cumulative_gas_used: 21000.into(),
+            ..Default::default()
+        };
+        let encoded = encode_receipt(&receipt, &[]);
+        let rlp = Rlp::new(&encoded);
+        assert_eq!(rlp.item_count().unwrap(), 4);
+        assert_eq!(rlp.val_at::<H256>(0).unwrap(), H256::repeat_byte(0xaa));
+    }
+
+    #[test]
+    fn encodes_deposit_receipts() {
+        // OP Stack deposit receipts after Regolith and after Canyon
+        let regolith: Bytes = "0xf9010c0182b741b9010000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000c0833d3bbf".parse().unwrap();
+        let canyon: Bytes = "0xf9010d0182b741b9010000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000c0833d3bbf01".parse().unwrap();
+        let receipt = TransactionReceipt {
+            status: Some(1.into()),
+            cumulative_gas_used: 46913.into(),
+            transaction_type: Some(DEPOSIT_TX_TYPE.into()),
+            ..Default::default()
+        };
+        let nonce = U64::from(0x3d3bbf);
+
+        let expected: Vec<u8> = [0x7e].into_iter().chain(regolith.to_vec()).collect();
+        assert_eq!(encode_receipt(&receipt, &[nonce]), expected);
+        let expected: Vec<u8> = [0x7e].into_iter().chain(canyon.to_vec()).collect();
+        assert_eq!(encode_receipt(&receipt, &[nonce, 1.into()]), expected);
+
+        // fields of other receipts are never encoded
+        let legacy = TransactionReceipt {
+            transaction_type: None,
+            ..receipt
+        };
+        assert_eq!(
+            encode_receipt(&legacy, &[nonce]),
+            encode_receipt(&legacy, &[])
+        );
+    }
+
+    #[test]
+    fn takes_deposit_fields_from_transactions() {
+        let deposit = |other| Transaction {
+            nonce: 0x3d3bbf.into(),
+            transaction_type: Some(DEPOSIT_TX_TYPE.into()),
+            other: serde_json::from_value(other).unwrap(),
+            ..Default::default()
+        };
+        let nonce = U64::from(0x3d3bbf);
+        assert_eq!(deposit_fields(&deposit(json!({}))).unwrap(), [nonce]);
+        assert_eq!(
+            deposit_fields(&deposit(json!({ "depositReceiptVersion": "0x1" }))).unwrap(),
+            [nonce, 1.into()]
+        );
+        assert!(deposit_fields(&deposit(json!({ "depositReceiptVersion": "v1" }))).is_err());
+    }
+}
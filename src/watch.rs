@@ -0,0 +1,282 @@
+//! Approvals of watched owners worth notifying about: new ones,
+//! increases of allowances and risky ones.
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use ethers::{
+    providers::Middleware,
+    types::{Address, H256, U256, U64},
+};
+use itertools::Itertools;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{erc20::TokenApproval, risk::RiskScore, App, Error, REORG_DEPTH};
+
+/// Why an approval is notified about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+#[non_exhaustive]
+pub enum Trigger {
+    /// Spender had no allowance before
+    New,
+    /// Allowance was raised
+    Increase,
+    /// Risk score is at least the watched one
+    Risky,
+}
+
+impl Trigger {
+    /// Triggers of changing the allowance from `previous` by the approval
+    pub(crate) fn of(previous: U256, approval: &TokenApproval, min_risk: RiskScore) -> Vec<Self> {
+        let value = approval.approval.value;
+        let mut triggers = Vec::new();
+        if previous.is_zero() && !value.is_zero() {
+            triggers.push(Self::New);
+        } else if value > previous {
+            triggers.push(Self::Increase);
+        }
+        if !value.is_zero() && approval.risk.score() >= min_risk {
+            triggers.push(Self::Risky);
+        }
+        triggers
+    }
+}
+
+impl Display for Trigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::New => "new",
+            Self::Increase => "increase",
+            Self::Risky => "risky",
+        })
+    }
+}
+
+/// Approval of a watched owner, as sent to webhooks
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+pub struct ApprovalEvent {
+    pub chain_id: u64,
+    pub triggers: Vec<Trigger>,
+    /// Allowance right before the approval
+    pub previous: U256,
+    pub approval: TokenApproval,
+    /// Block of the approval was reorged out after the event was sent.
+    /// If the approval is included again, it is sent as a new event.
+    pub removed: bool,
+}
+
+impl Display for ApprovalEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.removed {
+            write!(f, "REORGED OUT ")?;
+        }
+        write!(
+            f,
+            "{} approval on chain {}: {}",
+            self.triggers.iter().join(", "),
+            self.chain_id,
+            self.approval
+        )
+    }
+}
+
+/// Follows the chain for approval events of the owners. Hashes of scanned
+/// heads and of blocks with events are kept for 64 blocks, so
+/// when they are reorged out, the events are sent again as removed and
+/// blocks since the fork are scanned again.
+#[derive(Debug)]
+pub struct Watcher {
+    owners: Vec<Address>,
+    min_risk: RiskScore,
+    start: U64,
+    next: U64,
+    hashes: BTreeMap<U64, H256>,
+    sent: Vec<ApprovalEvent>,
+}
+
+impl Watcher {
+    /// Starts at the block, see [`App::get_approval_events`]
+    pub fn new(owners: Vec<Address>, from: U64, min_risk: RiskScore) -> Self {
+        Self {
+            owners,
+            min_risk,
+            start: from,
+            next: from,
+            hashes: BTreeMap::new(),
+            sent: Vec::new(),
+        }
+    }
+
+    /// Next block to be scanned
+    pub fn next_block(&self) -> U64 {
+        self.next
+    }
+
+    /// Events since the last poll, in order of logs, preceded by
+    /// the removed ones if the chain was reorged. The watcher is left
+    /// as it was if polling fails, so it can be polled again.
+    pub async fn poll<M: Middleware + 'static>(
+        &mut self,
+        app: &App<M>,
+    ) -> Result<Vec<ApprovalEvent>, Error> {
+        let fork = self.find_fork(app).await?;
+        let next = fork.unwrap_or(self.next);
+        let (new, head) = app
+            .get_approval_events(&self.owners, next, self.min_risk)
+            .await?;
+        let head_hash = if head >= next {
+            app.try_get_block_hash(head).await?
+        } else {
+            None
+        };
+
+        let mut events = Vec::new();
+        if let Some(fork) = fork {
+            warn!(%fork, next = %self.next, "scanned blocks were reorged out");
+            self.next = fork;
+            self.hashes.split_off(&fork);
+            let (removed, kept) = self
+                .sent
+                .drain(..)
+                .partition(|event| event.approval.meta.block_number >= fork);
+            self.sent = kept;
+            events.extend(removed.into_iter().map(|mut event: ApprovalEvent| {
+                event.removed = true;
+                event
+            }));
+        }
+        if let Some(hash) = head_hash {
+            self.hashes.insert(head, hash);
+        }
+        for event in &new {
+            let meta = &event.approval.meta;
+            self.hashes.insert(meta.block_number, meta.block_hash);
+        }
+        self.sent.extend(new.iter().cloned());
+        self.next = self.next.max(head + 1);
+
+        let oldest = head.saturating_sub(REORG_DEPTH.into());
+        self.hashes = self.hashes.split_off(&oldest);
+        self.sent
+            .retain(|event| event.approval.meta.block_number >= oldest);
+        events.extend(new);
+        Ok(events)
+    }
+
+    /// First block to scan again, if any of kept blocks were reorged out.
+    /// Blocks are checked from the newest one down to the first one which
+    /// is still in the chain, so only the last head is requested usually.
+    /// If none of them is, the fork is assumed to be within 64 blocks.
+    async fn find_fork<M: Middleware + 'static>(&self, app: &App<M>) -> Result<Option<U64>, Error> {
+        let mut fork = None;
+        for (&block, &hash) in self.hashes.iter().rev() {
+            if app.try_get_block_hash(block).await? == Some(hash) {
+                return Ok(fork.map(|_| block + 1));
+            }
+            fork = Some(block);
+        }
+        Ok(fork.map(|block| block.saturating_sub(REORG_DEPTH.into()).max(self.start)))
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use crate::testing::{http_node, DevChain, Reply};
+
+    use super::*;
+
+    fn address(n: u64) -> Address {
+        Address::from_low_u64_be(n)
+    }
+
+    fn blocks(events: &[ApprovalEvent]) -> Vec<(u64, bool)> {
+        events
+            .iter()
+            .map(|event| (event.approval.meta.block_number.as_u64(), event.removed))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn removes_events_of_reorged_blocks() {
+        let chain = DevChain::new(1337, 10);
+        let (token, owner) = (address(0xc1), address(1));
+        chain.approve(8, token, owner, address(2), 5.into());
+        chain.approve(9, token, owner, address(3), 5.into());
+        let node = chain.start().await;
+        let app = App::new(node.url.clone());
+        let mut watcher = Watcher::new(vec![owner], 1.into(), "100".parse().unwrap());
+
+        let events = watcher.poll(&app).await.unwrap();
+        assert_eq!(blocks(&events), [(8, false), (9, false)]);
+        assert_eq!(watcher.next_block(), 11.into());
+
+        // nothing changed, only the last head is checked
+        chain.mine(1);
+        let requested = node.count("eth_getBlockByNumber");
+        assert!(watcher.poll(&app).await.unwrap().is_empty());
+        assert_eq!(node.count("eth_getBlockByNumber"), requested + 2);
+
+        // the approval of block 9 is included again in block 10
+        chain.reorg(9);
+        chain.approve(10, token, owner, address(3), 5.into());
+        chain.mine(1);
+        let events = watcher.poll(&app).await.unwrap();
+        assert_eq!(blocks(&events), [(9, true), (10, false)]);
+        assert_eq!(events[1].triggers, [Trigger::New]);
+        assert_eq!(watcher.next_block(), 13.into());
+    }
+
+    #[tokio::test]
+    async fn reports_reorged_events_after_failed_poll() {
+        let chain = DevChain::new(1337, 10);
+        let (token, owner) = (address(0xc1), address(1));
+        chain.approve(9, token, owner, address(2), 5.into());
+        let failing = Arc::new(AtomicBool::new(false));
+        let node = http_node({
+            let (chain, failing) = (chain.clone(), failing.clone());
+            move |method, params| match method {
+                "eth_getLogs" if failing.load(Ordering::Relaxed) => {
+                    Reply::Error(-32000, "internal error")
+                }
+                _ => chain.answer(method, params).unwrap_or(Reply::ok("0x")),
+            }
+        })
+        .await;
+        let app = App::new(node.url.clone());
+        let mut watcher = Watcher::new(vec![owner], 1.into(), "100".parse().unwrap());
+        assert_eq!(blocks(&watcher.poll(&app).await.unwrap()), [(9, false)]);
+
+        chain.reorg(9);
+        failing.store(true, Ordering::Relaxed);
+        assert!(watcher.poll(&app).await.is_err());
+        failing.store(false, Ordering::Relaxed);
+        let events = watcher.poll(&app).await.unwrap();
+        assert_eq!(blocks(&events), [(9, true)]);
+    }
+
+    #[tokio::test]
+    async fn rescans_reorged_blocks_without_events() {
+        let chain = DevChain::new(1337, 10);
+        let (token, owner) = (address(0xc1), address(1));
+        let node = chain.start().await;
+        let app = App::new(node.url.clone());
+        let mut watcher = Watcher::new(vec![owner], 5.into(), "100".parse().unwrap());
+        assert!(watcher.poll(&app).await.unwrap().is_empty());
+
+        // approval in a block which was scanned on another fork
+        chain.reorg(8);
+        chain.approve(9, token, owner, address(2), 5.into());
+        let events = watcher.poll(&app).await.unwrap();
+        assert_eq!(blocks(&events), [(9, false)]);
+    }
+}
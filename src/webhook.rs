@@ -0,0 +1,299 @@
+//! Delivery of events to webhooks as JSON payloads, signed with a secret
+//! shared with the receiver. Deliveries which still fail after retries
+//! are appended to a dead-letter file, so they can be inspected and
+//! replayed later.
+
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Utc};
+use ethers::utils::hex;
+use futures::future::join_all;
+use hmac::{Hmac, Mac};
+use reqwest::{header::CONTENT_TYPE, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use url::Url;
+
+use crate::throttle::RetryPolicy;
+
+/// Header with `sha256=<hex>` HMAC of the body, if a secret is set
+pub const SIGNATURE_HEADER: &str = "X-Signature-256";
+
+/// Max time to wait for a webhook to respond
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Signature of the payload as sent in [`SIGNATURE_HEADER`],
+/// for receivers to check the payload with
+pub fn sign_payload(secret: &[u8], payload: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(payload);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Delivery failed after all retries
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub url: Url,
+    pub payload: serde_json::Value,
+    pub error: String,
+    pub failed_at: DateTime<Utc>,
+}
+
+/// Webhooks every event is posted to
+#[derive(Debug)]
+pub struct Webhooks {
+    client: reqwest::Client,
+    urls: Vec<Url>,
+    secret: Option<Vec<u8>>,
+    policy: RetryPolicy,
+    /// JSON lines file of failed deliveries, if set
+    dead_letters: Option<Mutex<PathBuf>>,
+}
+
+impl Webhooks {
+    pub fn new(urls: impl IntoIterator<Item = Url>) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(DELIVERY_TIMEOUT)
+                .build()
+                .expect("failed to build HTTP client"),
+            urls: urls.into_iter().collect(),
+            secret: None,
+            policy: RetryPolicy::default(),
+            dead_letters: None,
+        }
+    }
+
+    /// Signs payloads with the secret, see [`sign_payload`]
+    pub fn secret(mut self, secret: impl Into<Vec<u8>>) -> Self {
+        self.secret = Some(secret.into());
+        self
+    }
+
+    /// How to retry failed deliveries. Timeouts, connection errors,
+    /// server errors and `429 Too Many Requests` are retried.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Appends failed deliveries to the JSON lines file
+    pub fn dead_letters(mut self, path: impl Into<PathBuf>) -> Self {
+        self.dead_letters = Some(Mutex::new(path.into()));
+        self
+    }
+
+    /// Posts the event to all webhooks at once. Returns deliveries
+    /// failed after all retries, which are also stored as dead letters.
+    /// Fails only if the dead letters can not be stored.
+    pub async fn notify(&self, event: &impl Serialize) -> anyhow::Result<Vec<DeadLetter>> {
+        let payload = serde_json::to_value(event)?;
+        let body = serde_json::to_vec(&payload)?;
+        let failed: Vec<_> = join_all(self.urls.iter().map(|url| async {
+            self.deliver(url, &body)
+                .await
+                .err()
+                .map(|error| DeadLetter {
+                    url: url.clone(),
+                    payload: payload.clone(),
+                    error,
+                    failed_at: SystemTime::now().into(),
+                })
+        }))
+        .await
+        .into_iter()
+        .flatten()
+        .collect();
+        if !failed.is_empty() {
+            self.store(&failed)?;
+        }
+        Ok(failed)
+    }
+
+    async fn deliver(&self, url: &Url, body: &[u8]) -> Result<(), String> {
+        let mut retry = 0;
+        loop {
+            let mut request = self
+                .client
+                .post(url.clone())
+                .header(CONTENT_TYPE, "application/json")
+                .body(body.to_vec());
+            if let Some(secret) = &self.secret {
+                request = request.header(SIGNATURE_HEADER, sign_payload(secret, body));
+            }
+            let (error, retryable) = match request.send().await {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) => {
+                    let status = response.status();
+                    (
+                        format!("webhook responded with {status}"),
+                        status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+                    )
+                }
+                Err(err) => (err.to_string(), err.is_timeout() || err.is_connect()),
+            };
+            if !retryable || retry >= self.policy.max_retries {
+                return Err(error);
+            }
+            futures_timer::Delay::new(self.policy.backoff(retry)).await;
+            retry += 1;
+        }
+    }
+
+    fn store(&self, letters: &[DeadLetter]) -> anyhow::Result<()> {
+        let Some(path) = &self.dead_letters else {
+            return Ok(());
+        };
+        // concurrent notifications should not interleave lines
+        let path = path.lock().unwrap();
+        let mut lines = Vec::new();
+        for letter in letters {
+            serde_json::to_writer(&mut lines, letter)?;
+            lines.push(b'\n');
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&*path)?;
+        file.write_all(&lines)?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+
+    use hyper::{
+        body::to_bytes,
+        service::{make_service_fn, service_fn},
+        Body, Request, Response, Server,
+    };
+    use serde_json::json;
+
+    use super::*;
+
+    /// Requests received by the sink: signature header and body
+    type Received = Arc<Mutex<Vec<(Option<String>, Vec<u8>)>>>;
+
+    /// Webhook receiver answering the n-th request with `status(n)`
+    async fn sink(status: impl Fn(usize) -> u16 + Send + Sync + 'static) -> (Url, Received) {
+        let status = Arc::new(status);
+        let received = Received::default();
+        let make_service = {
+            let received = received.clone();
+            make_service_fn(move |_| {
+                let (status, received) = (status.clone(), received.clone());
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                        let (status, received) = (status.clone(), received.clone());
+                        async move {
+                            let signature = req
+                                .headers()
+                                .get(SIGNATURE_HEADER)
+                                .map(|value| value.to_str().unwrap().to_string());
+                            let body = to_bytes(req.into_body()).await.unwrap().to_vec();
+                            let n = {
+                                let mut received = received.lock().unwrap();
+                                received.push((signature, body));
+                                received.len() - 1
+                            };
+                            Ok::<_, Infallible>(
+                                Response::builder()
+                                    .status(status(n))
+                                    .body(Body::empty())
+                                    .unwrap(),
+                            )
+                        }
+                    }))
+                }
+            })
+        };
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let url = format!("http://{}/hook", server.local_addr())
+            .parse()
+            .unwrap();
+        tokio::spawn(server);
+        (url, received)
+    }
+
+    fn policy(max_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(10),
+        }
+    }
+
+    // test case 2 of RFC 4231
+    #[test]
+    fn signs_with_hmac_sha256() {
+        assert_eq!(
+            sign_payload(b"Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[tokio::test]
+    async fn posts_signed_payload_retrying_server_errors() {
+        let (url, received) = sink(|n| if n < 2 { 503 } else { 204 }).await;
+        let webhooks = Webhooks::new([url])
+            .secret("secret")
+            .retry_policy(policy(3));
+
+        let event = json!({ "chain_id": 1, "triggers": ["new"] });
+        assert!(webhooks.notify(&event).await.unwrap().is_empty());
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+        for (signature, body) in received.iter() {
+            assert_eq!(
+                serde_json::from_slice::<serde_json::Value>(body).unwrap(),
+                event
+            );
+            assert_eq!(signature.as_deref(), Some(&*sign_payload(b"secret", body)));
+        }
+    }
+
+    #[tokio::test]
+    async fn stores_dead_letters_after_retries() {
+        let (failing, failed) = sink(|_| 500).await;
+        let (rejecting, rejected) = sink(|_| 400).await;
+        let (accepting, accepted) = sink(|_| 200).await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dead_letters.jsonl");
+        let webhooks = Webhooks::new([failing.clone(), rejecting.clone(), accepting])
+            .retry_policy(policy(2))
+            .dead_letters(&path);
+
+        let letters = webhooks.notify(&json!({ "n": 1 })).await.unwrap();
+        let mut urls: Vec<_> = letters.iter().map(|letter| letter.url.clone()).collect();
+        urls.sort();
+        let mut expected = vec![failing, rejecting];
+        expected.sort();
+        assert_eq!(urls, expected);
+        // client errors are not retried
+        assert_eq!(failed.lock().unwrap().len(), 3);
+        assert_eq!(rejected.lock().unwrap().len(), 1);
+        assert_eq!(accepted.lock().unwrap().len(), 1);
+        assert!(accepted.lock().unwrap()[0].0.is_none());
+
+        let stored: Vec<DeadLetter> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(stored.len(), 2);
+        assert!(stored
+            .iter()
+            .all(|letter| letter.payload == json!({ "n": 1 })));
+    }
+}
@@ -18,22 +18,42 @@ getButton.addEventListener('click', event => {
 		last_node_url = nodeURL.value;
 	}
 	try {
-		app.get_token_approvals(owner.value, fromBlock.value, toBlock.value).then((approvals) => {
+		Promise.all([
+			app.chain(),
+			app.get_token_approvals(owner.value, fromBlock.value, toBlock.value),
+		]).then(([chain, approvals]) => {
 		for (const a of approvals) {
 			const row = approvalsTable.insertRow();
 
+			row.insertCell().textContent = new Date(a.timestamp).toUTCString();
+
 			var token_node = document.createElement('a');
 			var token_text = document.createTextNode(a.token.symbol);
 			token_node.appendChild(token_text);
 			token_node.title = a.meta.address;
 			row.insertCell().appendChild(token_node);
-			row.insertCell().innerHTML = a.approval.spender;
-			row.insertCell().innerHTML = parseInt(a.approval.value, 16)/(10**a.token.decimals);
+			var spender_cell = row.insertCell();
+			// ENS names and drainer families are untrusted, so they are
+			// only ever inserted as text
+			spender_cell.textContent = a.approval.spender_name
+				? `${a.approval.spender_name} (${a.spender.address})`
+				: a.spender.address;
+			if (a.spender.drainer) {
+				spender_cell.appendChild(document.createTextNode(` (known drainer: ${a.spender.drainer})`));
+				spender_cell.style.color = "red";
+			}
+			row.insertCell().textContent = parseInt(a.approval.value, 16)/(10**a.token.decimals);
+
+			var risk_cell = row.insertCell();
+			risk_cell.textContent = `${a.risk.score} ${a.risk.level}`;
+			risk_cell.title = a.risk.factors.join(", ");
 
 			var tx_node = document.createElement('a');
 			var tx_text = document.createTextNode(a.meta.transaction_hash);
 			tx_node.appendChild(tx_text);
-			tx_node.href = `https://etherscan.io/tx/${a.meta.transaction_hash}#eventlog`;
+			if (chain.explorer_tx_url) {
+				tx_node.href = chain.explorer_tx_url.replace("{tx}", a.meta.transaction_hash) + "#eventlog";
+			}
 			row.insertCell().appendChild(tx_node);
 		}
 		getButton.value = "Refresh Approvals";